@@ -0,0 +1,80 @@
+use battllm_server::embedding::index::{brute_force, HnswIndex, HnswParams};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use ndarray::Array1;
+
+/// Same dimensionality as `text-embedding-3-small`
+const DIMENSIONS: usize = 1536;
+
+/// Deterministic pseudo-random noise in `[-1, 1)`.
+fn noise(state: &mut u64) -> f32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    (*state % 2000) as f32 / 1000.0 - 1.0
+}
+
+/// Unit vectors scattered around a few centres, standing in for real embeddings
+/// (which cluster by topic rather than being spread uniformly).
+fn clustered_vectors(count: usize, seed: u64) -> Vec<Array1<f32>> {
+    let mut state = seed.max(1);
+    let centres: Vec<Array1<f32>> = (0..32)
+        .map(|_| (0..DIMENSIONS).map(|_| noise(&mut state)).collect())
+        .collect();
+
+    (0..count)
+        .map(|i| {
+            let vector: Array1<f32> = centres[i % centres.len()]
+                .iter()
+                .map(|c| c + 0.5 * noise(&mut state))
+                .collect();
+            let norm = vector.dot(&vector).sqrt();
+            vector / norm
+        })
+        .collect()
+}
+
+/// The search as it was before the index: score everything, sort everything, truncate.
+fn full_sort(vectors: &[Array1<f32>], query: &Array1<f32>, k: usize) -> Vec<(usize, f32)> {
+    let mut similarities: Vec<(usize, f32)> = vectors.iter().map(|v| v.dot(query)).enumerate().collect();
+    similarities.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    similarities.truncate(k);
+    similarities
+}
+
+fn bench_search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("search");
+    let queries = clustered_vectors(16, 42);
+    let k = 5;
+
+    for size in [100, 1_000, 10_000] {
+        let vectors = clustered_vectors(size, size as u64);
+        let index = HnswIndex::build(&vectors, HnswParams::default());
+
+        group.bench_with_input(BenchmarkId::new("full_sort", size), &vectors, |b, vectors| {
+            b.iter(|| {
+                for query in &queries {
+                    black_box(full_sort(vectors, query, k));
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("brute_force", size), &vectors, |b, vectors| {
+            b.iter(|| {
+                for query in &queries {
+                    black_box(brute_force(vectors, query, k));
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("hnsw", size), &vectors, |b, vectors| {
+            b.iter(|| {
+                for query in &queries {
+                    black_box(index.search(vectors, query, k));
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_search);
+criterion_main!(benches);
@@ -30,4 +30,4 @@ CREATE TABLE IF NOT EXISTS Player (
     items TEXT DEFAULT "[]",
     game_id INTEGER NOT NULL,
     FOREIGN KEY (game_id) REFERENCES Game (id)
-);
\ No newline at end of file
+);
@@ -0,0 +1,27 @@
+-- Battles, rule sets and change tracking on Game
+ALTER TABLE Game ADD COLUMN seed INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE Game ADD COLUMN battle TEXT;
+ALTER TABLE Game ADD COLUMN rules TEXT NOT NULL DEFAULT '{}';
+ALTER TABLE Game ADD COLUMN catalog_version TEXT NOT NULL DEFAULT '';
+ALTER TABLE Game ADD COLUMN version INTEGER NOT NULL DEFAULT 1; -- Bumped along with the timestamp on every change
+
+-- Every game event is recorded in Ledger, the id doubles as the event sequence number
+-- Events caused by the game itself have no player, SQLite can only drop NOT NULL by rebuilding the table
+CREATE TABLE Ledger_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    game_id INTEGER NOT NULL,
+    player_id INTEGER, -- NULL for events caused by the game itself
+    timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+    command TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    FOREIGN KEY (game_id) REFERENCES Game (id),
+    FOREIGN KEY (player_id) REFERENCES Player (id)
+);
+
+INSERT INTO Ledger_new (id, game_id, player_id, timestamp, command, payload)
+    SELECT id, game_id, player_id, timestamp, command, payload FROM Ledger;
+DROP TABLE Ledger;
+ALTER TABLE Ledger_new RENAME TO Ledger;
+
+CREATE INDEX idx_ledger_game_id ON Ledger (game_id);
+CREATE INDEX idx_ledger_timestamp ON Ledger (timestamp);
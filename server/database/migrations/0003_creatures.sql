@@ -0,0 +1,38 @@
+-- Creatures and their abilities as rows. Player.creatures is deprecated, its blobs are moved here on startup
+CREATE TABLE Creature (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    game_id INTEGER NOT NULL,
+    player_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    image TEXT,
+    max_health INTEGER NOT NULL,
+    -- NULL when the creature was created without the attribute
+    strength INTEGER,
+    defense INTEGER,
+    perception INTEGER,
+    intelligence INTEGER,
+    wisdom INTEGER,
+    elements TEXT NOT NULL, -- JSON array of elements
+    FOREIGN KEY (game_id) REFERENCES Game (id),
+    FOREIGN KEY (player_id) REFERENCES Player (id)
+);
+
+CREATE INDEX idx_creature_player_id ON Creature (player_id);
+
+CREATE TABLE Ability (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    creature_id INTEGER NOT NULL,
+    position INTEGER NOT NULL, -- Index used by decisions
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    base_damage INTEGER NOT NULL,
+    available INTEGER NOT NULL,
+    max_available INTEGER NOT NULL,
+    elements TEXT NOT NULL, -- JSON array of elements
+    modifiers TEXT NOT NULL, -- JSON array of [amount, attribute]
+    category TEXT NOT NULL,
+    target TEXT NOT NULL,
+    UNIQUE (creature_id, position),
+    FOREIGN KEY (creature_id) REFERENCES Creature (id) ON DELETE CASCADE
+);
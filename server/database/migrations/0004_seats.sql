@@ -0,0 +1,7 @@
+-- Each player takes a seat, the owner 0 and the one who joined 1. At most one player per seat
+ALTER TABLE Player ADD COLUMN seat INTEGER;
+
+-- Seat existing players in the order they joined
+UPDATE Player SET seat = (SELECT COUNT(*) FROM Player AS earlier WHERE earlier.game_id = Player.game_id AND earlier.id < Player.id);
+
+CREATE UNIQUE INDEX idx_player_seat ON Player (game_id, seat);
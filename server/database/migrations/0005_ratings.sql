@@ -0,0 +1,20 @@
+-- Ratings of players, agents and creature designs. Derived from the ledger, `recompute-ratings` rebuilds them
+CREATE TABLE Rating (
+    kind TEXT NOT NULL, -- player, agent or creature
+    name TEXT NOT NULL,
+    rating REAL NOT NULL,
+    deviation REAL NOT NULL,
+    games INTEGER NOT NULL DEFAULT 0,
+    wins INTEGER NOT NULL DEFAULT 0,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (kind, name)
+);
+
+CREATE INDEX idx_rating_kind_rating ON Rating (kind, rating);
+
+-- Games counted in Rating, so none is counted twice
+CREATE TABLE RatedGame (
+    game_id INTEGER PRIMARY KEY,
+    rated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (game_id) REFERENCES Game (id)
+);
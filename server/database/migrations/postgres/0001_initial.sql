@@ -0,0 +1,31 @@
+-- PostgreSQL counterpart of ../0001_initial.sql
+-- Table to store game states
+CREATE TABLE IF NOT EXISTS Game (
+    id BIGSERIAL PRIMARY KEY,
+    phase TEXT NOT NULL,
+    timestamp TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc'),
+    initialized_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+CREATE INDEX IF NOT EXISTS idx_game_timestamp ON Game (timestamp);
+
+CREATE TABLE IF NOT EXISTS Player (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    creatures TEXT DEFAULT '[]',
+    items TEXT DEFAULT '[]',
+    game_id BIGINT NOT NULL REFERENCES Game (id)
+);
+
+-- Table to store ledger entries for GameState transactions
+CREATE TABLE IF NOT EXISTS Ledger (
+    id BIGSERIAL PRIMARY KEY,
+    game_id BIGINT NOT NULL REFERENCES Game (id),
+    player_id BIGINT NOT NULL REFERENCES Player (id),
+    timestamp TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc'),
+    command TEXT NOT NULL,
+    payload TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_ledger_game_id ON Ledger (game_id);
+CREATE INDEX IF NOT EXISTS idx_ledger_timestamp ON Ledger (timestamp);
@@ -0,0 +1,10 @@
+-- PostgreSQL counterpart of ../0002_battle.sql
+-- Battles, rule sets and change tracking on Game
+ALTER TABLE Game ADD COLUMN seed BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE Game ADD COLUMN battle TEXT;
+ALTER TABLE Game ADD COLUMN rules TEXT NOT NULL DEFAULT '{}';
+ALTER TABLE Game ADD COLUMN catalog_version TEXT NOT NULL DEFAULT '';
+ALTER TABLE Game ADD COLUMN version BIGINT NOT NULL DEFAULT 1; -- Bumped along with the timestamp on every change
+
+-- Every game event is recorded in Ledger, the id doubles as the event sequence number
+ALTER TABLE Ledger ALTER COLUMN player_id DROP NOT NULL; -- NULL for events caused by the game itself
@@ -0,0 +1,36 @@
+-- PostgreSQL counterpart of ../0003_creatures.sql
+-- Creatures and their abilities as rows. Player.creatures is deprecated
+CREATE TABLE Creature (
+    id BIGSERIAL PRIMARY KEY,
+    game_id BIGINT NOT NULL REFERENCES Game (id),
+    player_id BIGINT NOT NULL REFERENCES Player (id),
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    image TEXT,
+    max_health BIGINT NOT NULL,
+    -- NULL when the creature was created without the attribute
+    strength SMALLINT,
+    defense SMALLINT,
+    perception SMALLINT,
+    intelligence SMALLINT,
+    wisdom SMALLINT,
+    elements TEXT NOT NULL -- JSON array of elements
+);
+
+CREATE INDEX idx_creature_player_id ON Creature (player_id);
+
+CREATE TABLE Ability (
+    id BIGSERIAL PRIMARY KEY,
+    creature_id BIGINT NOT NULL REFERENCES Creature (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL, -- Index used by decisions
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    base_damage INTEGER NOT NULL,
+    available SMALLINT NOT NULL,
+    max_available SMALLINT NOT NULL,
+    elements TEXT NOT NULL, -- JSON array of elements
+    modifiers TEXT NOT NULL, -- JSON array of [amount, attribute]
+    category TEXT NOT NULL,
+    target TEXT NOT NULL,
+    UNIQUE (creature_id, position)
+);
@@ -0,0 +1,20 @@
+-- PostgreSQL counterpart of ../0005_ratings.sql
+-- Ratings of players, agents and creature designs. Derived from the ledger, `recompute-ratings` rebuilds them
+CREATE TABLE Rating (
+    kind TEXT NOT NULL, -- player, agent or creature
+    name TEXT NOT NULL,
+    rating DOUBLE PRECISION NOT NULL,
+    deviation DOUBLE PRECISION NOT NULL,
+    games BIGINT NOT NULL DEFAULT 0,
+    wins BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc'),
+    PRIMARY KEY (kind, name)
+);
+
+CREATE INDEX idx_rating_kind_rating ON Rating (kind, rating);
+
+-- Games counted in Rating, so none is counted twice
+CREATE TABLE RatedGame (
+    game_id BIGINT PRIMARY KEY REFERENCES Game (id),
+    rated_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc')
+);
@@ -0,0 +1,8 @@
+-- PostgreSQL counterpart of ../0006_tokens.sql
+-- Players authenticate with a random secret instead of their id, which states and events make public
+ALTER TABLE Player ADD COLUMN token TEXT;
+
+-- Players of existing games get a new token, the ids they used no longer authenticate
+UPDATE Player SET token = replace(gen_random_uuid()::text, '-', '');
+
+CREATE UNIQUE INDEX idx_player_token ON Player (token);
@@ -0,0 +1,35 @@
+#!/bin/sh
+# Runs the tests of the PostgreSQL backend, which `cargo test` ignores otherwise.
+# Uses the server at POSTGRES_TEST_URL when set, else a throwaway one started with the local initdb and pg_ctl.
+# Extra arguments go to `cargo test`, e.g. `scripts/test-postgres.sh repository`.
+set -e
+cd "$(dirname "$0")/.."
+
+if [ -z "$POSTGRES_TEST_URL" ]; then
+    DIR=$(mktemp -d)
+    # initdb refuses to run as root, hand the server to an unprivileged user then
+    if [ "$(id -u)" = 0 ]; then
+        OS_USER=${PGOSUSER:-postgres}
+        chown "$OS_USER" "$DIR"
+        as_server() { runuser -u "$OS_USER" -- "$@"; }
+    else
+        as_server() { "$@"; }
+    fi
+    trap 'as_server pg_ctl -D "$DIR/data" -m fast stop >/dev/null 2>&1; rm -rf "$DIR"' EXIT
+    as_server initdb -D "$DIR/data" -U postgres -A trust >/dev/null
+
+    # The first free port from PGPORT on
+    PORT=${PGPORT:-54329}
+    LAST=$((PORT + 100))
+    until as_server pg_ctl -D "$DIR/data" -o "-p $PORT -k $DIR" -l "$DIR/log" -w start >/dev/null 2>&1; do
+        PORT=$((PORT + 1))
+        if [ "$PORT" -gt "$LAST" ]; then
+            echo "No free port to start PostgreSQL on, see $DIR/log" >&2
+            cat "$DIR/log" >&2
+            exit 1
+        fi
+    done
+    export POSTGRES_TEST_URL="postgres://postgres@localhost:$PORT/postgres"
+fi
+
+cargo test --features postgres "$@" -- --ignored
@@ -0,0 +1,550 @@
+// src/agent.rs
+//! LLM opponents. An agent sees the battle as its player does, asks an OpenAI-compatible
+//! chat-completion API which move to make, and plays it through the decision endpoint.
+use reqwest::{Client, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use thiserror::Error;
+
+use crate::battle::Decision;
+use crate::config::Config;
+use crate::events::GameEvent;
+use crate::models::creature::Attribute;
+use crate::models::game::GamePhase;
+use crate::visibility::{FighterView, GameView, TeamView};
+
+/// Seconds the agent waits on each long poll of the game.
+const POLL_TIMEOUT: i64 = 30;
+/// Turns of the battle recalled in the prompt, so the model knows what the opponent did.
+const RECALLED_TURNS: usize = 3;
+/// Attributes in the order the prompt lists them.
+const ATTRIBUTES: [Attribute; 5] = [Attribute::Strength, Attribute::Defense, Attribute::Perception, Attribute::Intelligence, Attribute::Wisdom];
+
+const RULES: &str = "You play a turn-based battle of creatures against another player. \
+Each turn you make one move with the creature you have in the field. \
+Attacks deal damage, more when their element beats the target's and less the other way around: \
+water beats fire, fire beats air, air beats earth, earth beats water. \
+Every ability has limited uses. A creature with no health left faints, a team whose creatures all fainted loses. \
+Reply with a JSON object holding the number of your move, e.g. {\"move\": 1}, and nothing else.";
+
+#[derive(Error, Debug)]
+pub enum AgentError {
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("{url} answered {status}: {body}")]
+    Api { url: String, status: StatusCode, body: String },
+    #[error("The completion has no message")]
+    EmptyCompletion,
+    #[error("It is not player {0}'s turn")]
+    NotYourTurn(i64),
+}
+
+/// A message of a chat completion.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub role: String,
+    pub content: String,
+}
+
+impl Message {
+    pub fn new(role: &str, content: impl Into<String>) -> Self {
+        Message { role: role.to_string(), content: content.into() }
+    }
+}
+
+/// Fails with the body of the response when its status isn't a success.
+async fn checked(response: Response) -> Result<Response, AgentError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let url = response.url().to_string();
+    let body = response.text().await.unwrap_or_default();
+    Err(AgentError::Api { url, status, body })
+}
+
+/// Client of the `/chat/completions` endpoint of an OpenAI-compatible API.
+#[derive(Debug, Clone)]
+pub struct ChatClient {
+    http: Client,
+    base_url: String,
+    model: String,
+    /// Sent as a bearer token, local servers usually need none
+    api_key: Option<String>,
+}
+
+impl ChatClient {
+    pub fn new(base_url: &str, model: &str, api_key: Option<String>) -> Self {
+        ChatClient {
+            http: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            model: model.to_string(),
+            api_key,
+        }
+    }
+
+    /// The API and model of the config, authenticated with `OPENAI_API_KEY` when it is set.
+    pub fn from_config(config: &Config) -> Self {
+        ChatClient::new(&config.agent_base_url, &config.agent_model, env::var("OPENAI_API_KEY").ok())
+    }
+
+    /// Returns the model's reply to `messages`.
+    pub async fn complete(&self, messages: &[Message]) -> Result<String, AgentError> {
+        let mut request = self.http
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(&json!({ "model": self.model, "messages": messages }));
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let body: serde_json::Value = checked(request.send().await?).await?.json().await?;
+        body["choices"][0]["message"]["content"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or(AgentError::EmptyCompletion)
+    }
+}
+
+/// The moves offered to the model: the decisions `player_id` could make, except conceding.
+/// Empty when it isn't their turn.
+pub fn legal_decisions(view: &GameView, player_id: i64) -> Vec<Decision> {
+    let team = match view.teams.iter().find(|team| team.player_id == player_id) {
+        Some(team) if view.phase == GamePhase::Battle && view.current_turn == Some(player_id) => team,
+        _ => return Vec::new(),
+    };
+    let active = match team.active {
+        Some(active) => active,
+        None => return Vec::new(),
+    };
+
+    let mut decisions = Vec::new();
+    if let Some(Some(fighter)) = team.fighters.get(active) {
+        decisions.extend(
+            fighter.abilities
+                .iter()
+                .enumerate()
+                .filter(|(_, ability)| ability.as_ref().is_some_and(|ability| ability.available.0 > 0))
+                .map(|(ability, _)| Decision::Action { ability }),
+        );
+    }
+    decisions.extend(
+        team.fighters
+            .iter()
+            .enumerate()
+            .filter(|(i, fighter)| *i != active && fighter.as_ref().is_some_and(|fighter| fighter.health > 0))
+            .map(|(creature, _)| Decision::Swap { creature }),
+    );
+    decisions.push(Decision::Wait);
+    decisions
+}
+
+/// A move to fall back on when the model gives no usable answer: the first ability, else waiting.
+pub fn fallback(legal: &[Decision]) -> Decision {
+    legal.iter()
+        .find(|decision| matches!(decision, Decision::Action { .. }))
+        .unwrap_or(&Decision::Wait)
+        .clone()
+}
+
+fn describe_fighter(fighter: &FighterView) -> String {
+    let elements: Vec<String> = fighter.elements.iter().map(|element| format!("{:?}", element).to_lowercase()).collect();
+    let mut text = format!("{} ({}), {}/{} health", fighter.name, elements.join(", "), fighter.health, fighter.max_health);
+    if fighter.health == 0 {
+        text.push_str(", fainted");
+    }
+    match &fighter.attributes {
+        Some(attributes) => {
+            let attributes: Vec<String> = ATTRIBUTES
+                .iter()
+                .map(|attribute| format!("{} {}", format!("{:?}", attribute).to_lowercase(), attributes.get(attribute).copied().unwrap_or(0)))
+                .collect();
+            text.push_str(&format!(". {}", attributes.join(", ")));
+        }
+        None => text.push_str(". Attributes unknown"),
+    }
+    for (amount, attribute) in &fighter.modifiers {
+        text.push_str(&format!(", {:+} {}", amount, format!("{:?}", attribute).to_lowercase()));
+    }
+    text
+}
+
+fn describe_team(team: &TeamView) -> String {
+    let mut text = String::new();
+    for (i, fighter) in team.fighters.iter().enumerate() {
+        let field = if team.active == Some(i) { " [in the field]" } else { "" };
+        match fighter {
+            Some(fighter) => {
+                text.push_str(&format!("- {}{}\n", describe_fighter(fighter), field));
+                let abilities: Vec<String> = fighter.abilities
+                    .iter()
+                    .map(|ability| match ability {
+                        Some(ability) => ability.name.clone(),
+                        None => "unknown".to_string(),
+                    })
+                    .collect();
+                text.push_str(&format!("  Abilities: {}\n", abilities.join(", ")));
+            }
+            None => text.push_str(&format!("- Unknown creature{}\n", field)),
+        }
+    }
+    text
+}
+
+fn describe_decision(decision: &Decision, team: &TeamView) -> String {
+    let fighter = |i: usize| team.fighters.get(i).and_then(Option::as_ref);
+    match decision {
+        Decision::Action { ability } => {
+            match team.active.and_then(fighter).and_then(|fighter| fighter.abilities.get(*ability)?.as_ref()) {
+                Some(ability) => {
+                    let elements: Vec<String> = ability.elements.iter().map(|element| format!("{:?}", element).to_lowercase()).collect();
+                    format!(
+                        "Use {}: {:?} on {}, {}, {} base damage, {} of {} uses left. {}",
+                        ability.name,
+                        ability.category,
+                        serde_json::to_value(ability.target).ok().and_then(|target| target.as_str().map(str::to_string)).unwrap_or_default(),
+                        elements.join(" and "),
+                        ability.base_damage,
+                        ability.available.0,
+                        ability.available.1,
+                        ability.description,
+                    ).trim_end().to_string()
+                }
+                None => format!("Use ability {}", ability),
+            }
+        }
+        Decision::Swap { creature } => match fighter(*creature) {
+            Some(fighter) => format!("Swap to {}", describe_fighter(fighter)),
+            None => format!("Swap to creature {}", creature),
+        },
+        Decision::Wait => "Wait, doing nothing this turn".to_string(),
+        Decision::Concede => "Concede the game".to_string(),
+    }
+}
+
+/// Renders what `player_id` sees of the battle, and the `legal` moves numbered from 1.
+pub fn render_prompt(view: &GameView, player_id: i64, legal: &[Decision]) -> Vec<Message> {
+    let own = view.teams.iter().position(|team| team.player_id == player_id).unwrap_or(0);
+    let mut prompt = format!("Your team:\n{}", describe_team(&view.teams[own]));
+    for (i, team) in view.teams.iter().enumerate().filter(|(i, _)| *i != own) {
+        prompt.push_str(&format!("\nOpponent's team{}:\n{}", if view.teams.len() > 2 { format!(" {}", i) } else { String::new() }, describe_team(team)));
+    }
+
+    let mut turns: Vec<String> = view.events
+        .iter()
+        .rev()
+        .filter_map(|envelope| match &envelope.event {
+            GameEvent::TurnResolved { report } => Some(format!(
+                "- Turn {}, {}: {} {}",
+                report.turn + 1,
+                if report.team == own { "you" } else { "opponent" },
+                serde_json::to_string(&report.decision).unwrap_or_default(),
+                serde_json::to_string(&report.effects).unwrap_or_default(),
+            )),
+            _ => None,
+        })
+        .take(RECALLED_TURNS)
+        .collect();
+    if !turns.is_empty() {
+        turns.reverse();
+        prompt.push_str(&format!("\nLast turns:\n{}\n", turns.join("\n")));
+    }
+
+    prompt.push_str("\nYour moves:\n");
+    for (i, decision) in legal.iter().enumerate() {
+        prompt.push_str(&format!("{}. {}\n", i + 1, describe_decision(decision, &view.teams[own])));
+    }
+
+    vec![Message::new("system", RULES), Message::new("user", prompt)]
+}
+
+/// Reads the move in the model's reply: `{"move": 2}`, a bare number, or a decision as the API takes it.
+/// Fails with the reason when it isn't one of the `legal` moves.
+pub fn parse_decision(reply: &str, legal: &[Decision]) -> Result<Decision, String> {
+    let reply = reply.trim();
+    // Models like to wrap their answer in prose or code fences
+    let json = match (reply.find('{'), reply.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &reply[start..=end],
+        _ => reply,
+    };
+    let value: serde_json::Value = serde_json::from_str(json).map_err(|_| format!("`{}` is not JSON", reply))?;
+
+    let number = match &value {
+        serde_json::Value::Number(number) => Some(number.as_u64()),
+        serde_json::Value::Object(object) => object.get("move").map(|number| {
+            number.as_u64().or_else(|| number.as_str().and_then(|number| number.trim().parse().ok()))
+        }),
+        _ => None,
+    };
+    let decision = match number {
+        Some(number) => number
+            .and_then(|number| (number as usize).checked_sub(1))
+            .and_then(|i| legal.get(i))
+            .cloned()
+            .ok_or_else(|| format!("`{}` is not the number of a move", json))?,
+        None => serde_json::from_value(value).map_err(|_| format!("`{}` names no move", json))?,
+    };
+
+    if !legal.contains(&decision) {
+        return Err(format!("`{}` is not one of the moves listed", json));
+    }
+    Ok(decision)
+}
+
+/// A move of the agent and how it came to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Choice {
+    pub decision: Decision,
+    /// Completions requested
+    pub attempts: u32,
+    /// Whether the model never gave a legal move
+    pub fallback: bool,
+}
+
+/// Plays with a chat model.
+#[derive(Debug, Clone)]
+pub struct Agent {
+    client: ChatClient,
+    /// Times to ask again after a reply that isn't a legal move
+    retries: u32,
+}
+
+impl Agent {
+    pub fn new(client: ChatClient, retries: u32) -> Self {
+        Agent { client, retries }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Agent::new(ChatClient::from_config(config), config.agent_retries)
+    }
+
+    /// Picks the move of `player_id`, whose turn it must be. A reply that isn't a legal move is
+    /// sent back with the reason, up to `retries` times, before falling back to `fallback`.
+    pub async fn decide(&self, view: &GameView, player_id: i64) -> Result<Choice, AgentError> {
+        let legal = legal_decisions(view, player_id);
+        if legal.is_empty() {
+            return Err(AgentError::NotYourTurn(player_id));
+        }
+
+        let mut messages = render_prompt(view, player_id, &legal);
+        let attempts = self.retries + 1;
+        for attempt in 1..=attempts {
+            match self.client.complete(&messages).await {
+                Ok(reply) => match parse_decision(&reply, &legal) {
+                    Ok(decision) => return Ok(Choice { decision, attempts: attempt, fallback: false }),
+                    Err(reason) => {
+                        log::warn!("Agent of player {} gave no legal move: {}", player_id, reason);
+                        messages.push(Message::new("assistant", reply));
+                        messages.push(Message::new("user", format!("{}. Reply with the number of one of the moves listed, as {{\"move\": 1}}.", reason)));
+                    }
+                },
+                Err(e) => log::warn!("Agent of player {} failed to get a completion: {}", player_id, e),
+            }
+        }
+        Ok(Choice { decision: fallback(&legal), attempts, fallback: true })
+    }
+
+    /// Plays the player of `token` in the game served at `server`, e.g. `http://127.0.0.1:8080`.
+    /// Returns the state of the game once it is over.
+    pub async fn play(&self, server: &str, game_id: i64, token: &str) -> Result<GameView, AgentError> {
+        let http = Client::new();
+        let game_url = format!("{}/{}", server.trim_end_matches('/'), game_id);
+        let mut version = 0;
+
+        loop {
+            let response = http
+                .get(format!("{}/poll", game_url))
+                .query(&[("version", version), ("timeout", POLL_TIMEOUT)])
+                .header("Authorization", token)
+                .send()
+                .await?;
+            if response.status() == StatusCode::NO_CONTENT {
+                continue;
+            }
+            let view: GameView = checked(response).await?.json().await?;
+            version = view.version;
+            if view.phase == GamePhase::Finish || view.winner.is_some() {
+                return Ok(view);
+            }
+            // The view says which player the token is
+            let player_id = match view.viewer {
+                Some(player_id) if view.current_turn == Some(player_id) => player_id,
+                _ => continue,
+            };
+
+            let choice = self.decide(&view, player_id).await?;
+            log::info!("Agent of player {} plays {:?} after {} attempts", player_id, choice.decision, choice.attempts);
+            let response = http
+                .post(format!("{}/decision", game_url))
+                .header("Authorization", token)
+                .json(&choice.decision)
+                .send()
+                .await?;
+            // The turn may have moved on since the poll, the next one tells
+            if response.status() != StatusCode::CONFLICT {
+                checked(response).await?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battle::tests::battle;
+    use crate::events::EventHub;
+    use crate::models::game::{GameState, PlayerState};
+    use crate::repository::{GameRepository, MemoryRepository};
+    use crate::rules::RuleSet;
+    use crate::visibility::view;
+    use crate::handlers;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    fn battle_view(player_id: i64) -> GameView {
+        let battle = battle(4);
+        let state = GameState {
+            game_id: 1,
+            version: 1,
+            phase: GamePhase::Battle,
+            current_turn: Some(battle.teams[battle.current].player_id),
+            players: vec![
+                PlayerState { id: 1, name: "first".to_string(), ready: true },
+                PlayerState { id: 2, name: "second".to_string(), ready: true },
+            ],
+            entities: Vec::new(),
+            battle: Some(battle),
+            rules: RuleSet::default(),
+        };
+        view(&state, Some(player_id), Vec::new())
+    }
+
+    type Replies = web::Data<Mutex<VecDeque<String>>>;
+
+    async fn complete(replies: Replies) -> HttpResponse {
+        let mut replies = replies.lock().unwrap();
+        let reply = if replies.len() > 1 { replies.pop_front().unwrap() } else { replies[0].clone() };
+        HttpResponse::Ok().json(json!({ "choices": [{ "message": { "role": "assistant", "content": reply } }] }))
+    }
+
+    /// A chat-completion API replying with `replies` in turn, then always with the last one.
+    /// Returns its base URL.
+    fn mock_api(replies: &[&str]) -> String {
+        let replies: Replies = web::Data::new(Mutex::new(replies.iter().map(|reply| reply.to_string()).collect()));
+        let server = HttpServer::new(move || App::new().app_data(replies.clone()).route("/v1/chat/completions", web::post().to(complete)))
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let url = format!("http://{}/v1", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        url
+    }
+
+    #[test]
+    fn test_legal_decisions_match_the_engine() {
+        let battle = battle(4);
+        let current = battle.teams[battle.current].player_id;
+        let mut expected = battle.legal_decisions(battle.current);
+        expected.retain(|decision| *decision != Decision::Concede);
+
+        assert_eq!(legal_decisions(&battle_view(current), current), expected);
+        assert!(legal_decisions(&battle_view(3 - current), 3 - current).is_empty());
+    }
+
+    #[test]
+    fn test_prompt_lists_moves() {
+        let view = battle_view(1);
+        let legal = legal_decisions(&view, 1);
+        let messages = render_prompt(&view, 1, &legal);
+        assert_eq!(messages[0].role, "system");
+
+        let prompt = &messages[1].content;
+        assert!(prompt.contains("- Ember (fire), 100/100 health. strength 5, defense 5, perception 6"), "{}", prompt);
+        assert!(prompt.contains("1. Use Strike: Attack on enemy, physical, 10 base damage, 10 of 10 uses left"), "{}", prompt);
+        assert!(prompt.contains(&format!("{}. Wait", legal.len())), "{}", prompt);
+        // The opponent's bench and abilities stay hidden
+        assert!(prompt.contains("Attributes unknown"), "{}", prompt);
+        assert!(prompt.contains("Abilities: unknown, unknown, unknown, unknown"), "{}", prompt);
+    }
+
+    #[test]
+    fn test_parse_decision() {
+        let legal = vec![Decision::Action { ability: 0 }, Decision::Swap { creature: 1 }, Decision::Wait];
+        assert_eq!(parse_decision("{\"move\": 2}", &legal), Ok(Decision::Swap { creature: 1 }));
+        assert_eq!(parse_decision("I'll attack.\n```json\n{\"move\": \"1\"}\n```", &legal), Ok(Decision::Action { ability: 0 }));
+        assert_eq!(parse_decision(" 3 ", &legal), Ok(Decision::Wait));
+        assert_eq!(parse_decision("{\"type\": \"wait\"}", &legal), Ok(Decision::Wait));
+
+        assert!(parse_decision("{\"move\": 4}", &legal).is_err());
+        assert!(parse_decision("{\"move\": 0}", &legal).is_err());
+        assert!(parse_decision("attack!", &legal).is_err());
+        assert!(parse_decision("{\"type\": \"concede\"}", &legal).is_err());
+        assert!(parse_decision("{\"type\": \"action\", \"ability\": 3}", &legal).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_decide_retries_then_falls_back() {
+        let view = battle_view(1);
+        let current = view.current_turn.unwrap();
+        let view = battle_view(current);
+        let legal = legal_decisions(&view, current);
+
+        let agent = Agent::new(ChatClient::new(&mock_api(&["Let me think", "{\"move\": 2}"]), "mock", None), 2);
+        let choice = agent.decide(&view, current).await.unwrap();
+        assert_eq!(choice, Choice { decision: legal[1].clone(), attempts: 2, fallback: false });
+
+        let agent = Agent::new(ChatClient::new(&mock_api(&["{\"move\": 99}"]), "mock", None), 1);
+        let choice = agent.decide(&view, current).await.unwrap();
+        assert_eq!(choice, Choice { decision: Decision::Action { ability: 0 }, attempts: 2, fallback: true });
+
+        // An API that can't be reached counts as a bad reply
+        let agent = Agent::new(ChatClient::new("http://127.0.0.1:1/v1", "mock", None), 0);
+        assert!(agent.decide(&view, current).await.unwrap().fallback);
+        assert!(matches!(agent.decide(&view, 3 - current).await, Err(AgentError::NotYourTurn(_))));
+    }
+
+    #[actix_web::test]
+    async fn test_agents_play_a_game() {
+        let repo = MemoryRepository::new();
+        let (game_id, owner_seat) = repo.create_game("Owner", &RuleSet::default(), "v1").unwrap();
+        let guest_seat = repo.join_game(game_id, "Guest").unwrap();
+        let (owner, guest) = (owner_seat.id, guest_seat.id);
+        for (team, player_id) in battle(4).teams.iter().zip([owner, guest]) {
+            for fighter in &team.fighters {
+                repo.save_creature(game_id, player_id, &fighter.creature).unwrap();
+            }
+        }
+        let battle = crate::battle::BattleState::new(repo.get_seed(game_id).unwrap(), vec![
+            (owner, repo.get_creatures(game_id, owner).unwrap()),
+            (guest, repo.get_creatures(game_id, guest).unwrap()),
+        ]).unwrap();
+        repo.save_battle(game_id, "battle", &battle).unwrap();
+
+        let repo = web::Data::new(repo);
+        let hub = web::Data::new(EventHub::new());
+        let config = web::Data::new(Config::default());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(repo.clone())
+                .app_data(hub.clone())
+                .app_data(config.clone())
+                .configure(handlers::routes::<MemoryRepository>)
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let server_url = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+
+        // Both always pick the first move, an attack while they have uses left
+        let agent = Agent::new(ChatClient::new(&mock_api(&["{\"move\": 1}"]), "mock", None), 0);
+        let (first, second) = futures_util::future::join(
+            agent.play(&server_url, game_id, &owner_seat.token),
+            agent.play(&server_url, game_id, &guest_seat.token),
+        ).await;
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert!(first.winner.is_some());
+        assert_eq!(first.winner, second.winner);
+    }
+}
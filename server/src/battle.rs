@@ -0,0 +1,616 @@
+// src/battle.rs
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::models::{
+    ability::{Ability, AbilityCategory},
+    creature::{Attribute, Creature, Element, State},
+};
+
+/// How much an attack hits harder against the element it beats (and softer the other way).
+const STRONG_MULTIPLIER: f32 = 1.5;
+const WEAK_MULTIPLIER: f32 = 0.75;
+/// Damage is rolled between these fractions of its full amount.
+const ROLL_RANGE: std::ops::RangeInclusive<f32> = 0.85..=1.0;
+
+/// A decision made by the player whose turn it is. See "Battle Mechanics/Phases/Decision".
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Decision {
+    /// Use one of the active creature's abilities, by index
+    Action { ability: usize },
+    /// Do nothing
+    Wait,
+    /// Swap the active creature with another one of the team, by index
+    Swap { creature: usize },
+    /// Give up the game
+    Concede,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum BattleError {
+    #[error("It is not this player's turn")]
+    NotYourTurn,
+    #[error("The battle is already over")]
+    Finished,
+    #[error("Unknown ability {0}")]
+    UnknownAbility(usize),
+    #[error("Ability {0} has no uses left")]
+    AbilityExhausted(usize),
+    #[error("Cannot swap to creature {0}")]
+    InvalidSwap(usize),
+    #[error("Every team needs at least one creature")]
+    EmptyTeam,
+}
+
+/// A creature taking part in a battle, with its current state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fighter {
+    pub creature: Creature,
+    pub state: State,
+    /// Whether the creature has been in the field, where the opponent could see it
+    #[serde(default)]
+    pub revealed: bool,
+    /// Abilities the opponent has seen used, by index
+    #[serde(default)]
+    pub revealed_abilities: Vec<usize>,
+}
+
+impl Fighter {
+    pub fn new(creature: Creature) -> Self {
+        let state = State {
+            health: creature.max_health,
+            abilities: creature.abilities.clone(),
+            modifiers: Vec::new(),
+        };
+        Fighter { creature, state, revealed: false, revealed_abilities: Vec::new() }
+    }
+
+    pub fn is_fainted(&self) -> bool {
+        self.state.health == 0
+    }
+
+    /// The attribute including every modifier currently applied. Never below zero.
+    pub fn attribute(&self, attribute: &Attribute) -> i32 {
+        let base = self.creature.attributes.get(attribute).copied().unwrap_or(0) as i32;
+        let modifiers: i32 = self.state.modifiers
+            .iter()
+            .filter(|(_, modified)| modified == attribute)
+            .map(|(amount, _)| *amount as i32)
+            .sum();
+        (base + modifiers).max(0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Team {
+    pub player_id: i64,
+    pub fighters: Vec<Fighter>,
+    /// Index of the fighter currently in the field
+    pub active: usize,
+}
+
+impl Team {
+    pub fn active_fighter(&self) -> &Fighter {
+        &self.fighters[self.active]
+    }
+
+    pub fn is_defeated(&self) -> bool {
+        self.fighters.iter().all(Fighter::is_fainted)
+    }
+
+    /// Sends `creature` to the field.
+    fn bring_out(&mut self, creature: usize) {
+        self.active = creature;
+        self.fighters[creature].revealed = true;
+    }
+}
+
+/// Something that happened while resolving a decision.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Effect {
+    Damage { team: usize, creature: usize, amount: u32, multiplier: f32 },
+    Missed { team: usize, creature: usize },
+    Modifier { team: usize, creature: usize, attribute: Attribute, amount: i8 },
+    Swapped { team: usize, from: usize, to: usize },
+    Fainted { team: usize, creature: usize },
+    Waited { team: usize },
+    Conceded { team: usize },
+}
+
+/// The result of resolving one decision.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TurnReport {
+    pub turn: u32,
+    pub team: usize,
+    pub decision: Decision,
+    pub effects: Vec<Effect>,
+    /// Index of the winning team if the battle ended this turn
+    pub winner: Option<usize>,
+}
+
+/// The whole battle. Decisions alternate between the two teams, and every random roll
+/// comes from the seed and the turn number so the same decisions always give the same result.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BattleState {
+    pub seed: u64,
+    pub turn: u32,
+    /// Index of the team whose decision is awaited
+    pub current: usize,
+    pub teams: Vec<Team>,
+    pub winner: Option<usize>,
+}
+
+/// Whether `attacking` beats `defending`. Water > Fire > Air > Earth > Water.
+fn beats(attacking: &Element, defending: &Element) -> bool {
+    matches!(
+        (attacking, defending),
+        (Element::Water, Element::Fire)
+            | (Element::Fire, Element::Air)
+            | (Element::Air, Element::Earth)
+            | (Element::Earth, Element::Water)
+    )
+}
+
+/// Damage multiplier of an attack with `attacking` elements against a creature of `defending` elements.
+pub fn element_multiplier(attacking: &[Element], defending: &[Element]) -> f32 {
+    let mut multiplier = 1.0;
+    for attack in attacking {
+        for defense in defending {
+            if beats(attack, defense) {
+                multiplier *= STRONG_MULTIPLIER;
+            } else if beats(defense, attack) {
+                multiplier *= WEAK_MULTIPLIER;
+            }
+        }
+    }
+    multiplier
+}
+
+/// Chance of an attack by `attacker` to hit `defender`.
+fn hit_chance(attacker: &Fighter, defender: &Fighter) -> f64 {
+    (0.9 + 0.02 * (attacker.attribute(&Attribute::Perception) - defender.attribute(&Attribute::Perception)) as f64)
+        .clamp(0.5, 1.0)
+}
+
+/// Damage of an attack before the roll, and its element multiplier.
+fn attack_damage(attacker: &Fighter, defender: &Fighter, used: &Ability) -> (f32, f32) {
+    // Physical attacks use strength against defense, everything else intelligence against wisdom
+    let physical = used.elements.iter().all(|element| *element == Element::Physical);
+    let (offense, defense) = if physical {
+        (Attribute::Strength, Attribute::Defense)
+    } else {
+        (Attribute::Intelligence, Attribute::Wisdom)
+    };
+
+    let multiplier = element_multiplier(&used.elements, &defender.creature.elements);
+    let scaled = used.base_damage as f32
+        * (10 + attacker.attribute(&offense)) as f32
+        / (10 + defender.attribute(&defense)) as f32;
+    (scaled * multiplier, multiplier)
+}
+
+impl BattleState {
+    /// Starts a battle between two teams of `(player_id, creatures)`.
+    /// The team whose first creature has the higher perception goes first.
+    pub fn new(seed: u64, teams: Vec<(i64, Vec<Creature>)>) -> Result<Self, BattleError> {
+        if teams.len() != 2 || teams.iter().any(|(_, creatures)| creatures.is_empty()) {
+            return Err(BattleError::EmptyTeam);
+        }
+
+        let teams: Vec<Team> = teams
+            .into_iter()
+            .map(|(player_id, creatures)| {
+                let mut team = Team { player_id, fighters: creatures.into_iter().map(Fighter::new).collect(), active: 0 };
+                team.bring_out(0);
+                team
+            })
+            .collect();
+
+        let mut battle = BattleState { seed, turn: 0, current: 0, teams, winner: None };
+        let perception = |team: &Team| team.active_fighter().attribute(&Attribute::Perception);
+        battle.current = match perception(&battle.teams[0]).cmp(&perception(&battle.teams[1])) {
+            std::cmp::Ordering::Greater => 0,
+            std::cmp::Ordering::Less => 1,
+            std::cmp::Ordering::Equal => battle.rng().gen_range(0..2),
+        };
+        Ok(battle)
+    }
+
+    /// Random numbers for the current turn.
+    fn rng(&self) -> ChaCha8Rng {
+        ChaCha8Rng::seed_from_u64(self.seed ^ (self.turn as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.winner.is_some()
+    }
+
+    /// The battle as it was before the first decision.
+    pub fn restart(&self) -> Result<BattleState, BattleError> {
+        let teams = self.teams
+            .iter()
+            .map(|team| (team.player_id, team.fighters.iter().map(|fighter| fighter.creature.clone()).collect()))
+            .collect();
+        BattleState::new(self.seed, teams)
+    }
+
+    /// Applies the `(player_id, decision)` in order. Returns every state, starting with this one.
+    pub fn replay(&self, decisions: &[(i64, Decision)]) -> Result<Vec<BattleState>, BattleError> {
+        let mut states = vec![self.clone()];
+        let mut battle = self.clone();
+        for (player_id, decision) in decisions {
+            let team = battle.team_of(*player_id).ok_or(BattleError::NotYourTurn)?;
+            battle.apply(team, decision.clone())?;
+            states.push(battle.clone());
+        }
+        Ok(states)
+    }
+
+    /// Index of the team played by `player_id`.
+    pub fn team_of(&self, player_id: i64) -> Option<usize> {
+        self.teams.iter().position(|team| team.player_id == player_id)
+    }
+
+    /// Every decision `team` could make right now.
+    pub fn legal_decisions(&self, team: usize) -> Vec<Decision> {
+        if self.is_finished() || team != self.current {
+            return Vec::new();
+        }
+
+        let own = &self.teams[team];
+        let mut decisions: Vec<Decision> = own.active_fighter().state.abilities
+            .iter()
+            .enumerate()
+            .filter(|(_, ability)| ability.available.0 > 0)
+            .map(|(ability, _)| Decision::Action { ability })
+            .collect();
+        decisions.extend(
+            own.fighters
+                .iter()
+                .enumerate()
+                .filter(|(i, fighter)| *i != own.active && !fighter.is_fainted())
+                .map(|(creature, _)| Decision::Swap { creature }),
+        );
+        decisions.push(Decision::Wait);
+        decisions.push(Decision::Concede);
+        decisions
+    }
+
+    /// Average damage the active creature of `team` deals with `ability` right now, misses included,
+    /// up to the health the target has left. `0` for abilities that don't attack or can't be used.
+    pub fn expected_damage(&self, team: usize, ability: usize) -> f32 {
+        let attacker = self.teams[team].active_fighter();
+        let defender = self.teams[1 - team].active_fighter();
+        let used = match attacker.state.abilities.get(ability) {
+            Some(used) if used.available.0 > 0 && used.category == AbilityCategory::Attack => used,
+            _ => return 0.0,
+        };
+
+        let (scaled, _) = attack_damage(attacker, defender, used);
+        let roll = (ROLL_RANGE.start() + ROLL_RANGE.end()) / 2.0;
+        (scaled * roll).max(1.0).min(defender.state.health as f32) * hit_chance(attacker, defender) as f32
+    }
+
+    /// Resolves the decision of `team` and passes the turn to the other team.
+    pub fn apply(&mut self, team: usize, decision: Decision) -> Result<TurnReport, BattleError> {
+        if self.is_finished() {
+            return Err(BattleError::Finished);
+        }
+        if team != self.current {
+            return Err(BattleError::NotYourTurn);
+        }
+
+        let opponent = 1 - team;
+        let mut effects = Vec::new();
+        match &decision {
+            Decision::Action { ability } => self.act(team, *ability, &mut effects)?,
+            Decision::Swap { creature } => {
+                let own = &mut self.teams[team];
+                let valid = own.fighters.get(*creature).is_some_and(|fighter| !fighter.is_fainted());
+                if !valid || *creature == own.active {
+                    return Err(BattleError::InvalidSwap(*creature));
+                }
+                effects.push(Effect::Swapped { team, from: own.active, to: *creature });
+                own.bring_out(*creature);
+            }
+            Decision::Wait => effects.push(Effect::Waited { team }),
+            Decision::Concede => {
+                effects.push(Effect::Conceded { team });
+                self.winner = Some(opponent);
+            }
+        }
+
+        if self.winner.is_none() && self.teams[opponent].is_defeated() {
+            self.winner = Some(team);
+        }
+
+        let report = TurnReport {
+            turn: self.turn,
+            team,
+            decision,
+            effects,
+            winner: self.winner,
+        };
+        self.turn += 1;
+        self.current = opponent;
+        Ok(report)
+    }
+
+    /// Uses an ability of the active creature of `team`.
+    fn act(&mut self, team: usize, ability: usize, effects: &mut Vec<Effect>) -> Result<(), BattleError> {
+        let opponent = 1 - team;
+        let mut rng = self.rng();
+
+        let attacker = self.teams[team].active_fighter();
+        let used = attacker.state.abilities.get(ability).ok_or(BattleError::UnknownAbility(ability))?.clone();
+        if used.available.0 == 0 {
+            return Err(BattleError::AbilityExhausted(ability));
+        }
+
+        let attacker_index = self.teams[team].active;
+        let attacker = &mut self.teams[team].fighters[attacker_index];
+        attacker.state.abilities[ability].available.0 -= 1;
+        if !attacker.revealed_abilities.contains(&ability) {
+            attacker.revealed_abilities.push(ability);
+        }
+
+        match used.category {
+            AbilityCategory::Attack => {
+                let attacker = self.teams[team].active_fighter();
+                let defender = self.teams[opponent].active_fighter();
+                let defender_index = self.teams[opponent].active;
+
+                if !rng.gen_bool(hit_chance(attacker, defender)) {
+                    effects.push(Effect::Missed { team: opponent, creature: defender_index });
+                    return Ok(());
+                }
+
+                let (scaled, multiplier) = attack_damage(attacker, defender, &used);
+                let amount = (scaled * rng.gen_range(ROLL_RANGE)).round().max(1.0) as u32;
+
+                let defender = &mut self.teams[opponent].fighters[defender_index];
+                defender.state.health = defender.state.health.saturating_sub(amount);
+                effects.push(Effect::Damage { team: opponent, creature: defender_index, amount, multiplier });
+
+                if defender.is_fainted() {
+                    effects.push(Effect::Fainted { team: opponent, creature: defender_index });
+                    // The next creature still standing takes the field
+                    let defending = &mut self.teams[opponent];
+                    if let Some(next) = defending.fighters.iter().position(|fighter| !fighter.is_fainted()) {
+                        effects.push(Effect::Swapped { team: opponent, from: defender_index, to: next });
+                        defending.bring_out(next);
+                    }
+                }
+            }
+            category => {
+                // Defenses strengthen the caster, utilities weaken the enemy
+                let (target, sign) = match category {
+                    AbilityCategory::Defense => (team, 1),
+                    _ => (opponent, -1),
+                };
+                let modifiers = if used.modifiers.is_empty() {
+                    vec![(sign * (used.base_damage / 2).max(1) as i8, Attribute::Defense)]
+                } else {
+                    used.modifiers.clone()
+                };
+
+                let creature = self.teams[target].active;
+                for (amount, attribute) in modifiers {
+                    self.teams[target].fighters[creature].state.modifiers.push((amount, attribute.clone()));
+                    effects.push(Effect::Modifier { team: target, creature, attribute, amount });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::models::ability::Ability;
+    use std::collections::HashMap;
+
+    pub(crate) fn ability(name: &str, category: AbilityCategory, elements: Vec<Element>, base_damage: u16) -> Ability {
+        Ability {
+            name: name.to_string(),
+            description: String::new(),
+            base_damage,
+            available: (10, 10),
+            elements,
+            modifiers: vec![],
+            category,
+            target: category.target(),
+        }
+    }
+
+    pub(crate) fn creature(owner: i64, name: &str, elements: Vec<Element>, perception: u8) -> Creature {
+        Creature {
+            id: 0,
+            owner,
+            name: name.to_string(),
+            description: String::new(),
+            image: None,
+            max_health: 100,
+            attributes: HashMap::from([
+                (Attribute::Strength, 5),
+                (Attribute::Defense, 5),
+                (Attribute::Perception, perception),
+                (Attribute::Intelligence, 5),
+                (Attribute::Wisdom, 5),
+            ]),
+            abilities: vec![
+                ability("Strike", AbilityCategory::Attack, vec![Element::Physical], 10),
+                ability("Blast", AbilityCategory::Attack, elements.clone(), 8),
+                ability("Harden", AbilityCategory::Defense, vec![Element::Physical], 5),
+                ability("Weaken", AbilityCategory::Utility, vec![Element::Mental], 5),
+            ],
+            elements,
+        }
+    }
+
+    pub(crate) fn battle(seed: u64) -> BattleState {
+        BattleState::new(seed, vec![
+            (1, vec![creature(1, "Ember", vec![Element::Fire], 6), creature(1, "Pebble", vec![Element::Earth], 4)]),
+            (2, vec![creature(2, "Drop", vec![Element::Water], 4)]),
+        ]).unwrap()
+    }
+
+    #[test]
+    fn test_element_multiplier() {
+        assert_eq!(element_multiplier(&[Element::Water], &[Element::Fire]), STRONG_MULTIPLIER);
+        assert_eq!(element_multiplier(&[Element::Fire], &[Element::Water]), WEAK_MULTIPLIER);
+        assert_eq!(element_multiplier(&[Element::Physical], &[Element::Fire]), 1.0);
+        assert_eq!(element_multiplier(&[Element::Water, Element::Fire], &[Element::Fire, Element::Air]), STRONG_MULTIPLIER * STRONG_MULTIPLIER);
+    }
+
+    #[test]
+    fn test_expected_damage() {
+        let battle = battle(4);
+        // Ember (fire) against Drop (water): fire is weak there, the physical Strike isn't
+        let strike = battle.expected_damage(0, 0);
+        let blast = battle.expected_damage(0, 1);
+        assert!(strike > blast && blast > 0.0, "{} {}", strike, blast);
+        assert!(strike <= 10.0 * 0.925 + 0.01);
+        assert_eq!(battle.expected_damage(0, 2), 0.0);
+        assert_eq!(battle.expected_damage(0, 9), 0.0);
+    }
+
+    #[test]
+    fn test_turn_order_and_alternation() {
+        let mut battle = battle(1);
+        // Ember is more perceptive
+        assert_eq!(battle.current, 0);
+        assert_eq!(battle.apply(1, Decision::Wait), Err(BattleError::NotYourTurn));
+
+        battle.apply(0, Decision::Wait).unwrap();
+        assert_eq!(battle.current, 1);
+        assert_eq!(battle.turn, 1);
+        assert!(battle.legal_decisions(0).is_empty());
+        assert!(battle.legal_decisions(1).contains(&Decision::Concede));
+    }
+
+    #[test]
+    fn test_attack_uses_ability_and_deals_damage() {
+        let mut battle = battle(2);
+        let report = battle.apply(0, Decision::Action { ability: 0 }).unwrap();
+
+        assert_eq!(battle.teams[0].fighters[0].state.abilities[0].available.0, 9);
+        let defender = &battle.teams[1].fighters[0];
+        match &report.effects[0] {
+            Effect::Damage { amount, .. } => assert_eq!(defender.state.health, 100 - amount),
+            Effect::Missed { .. } => assert_eq!(defender.state.health, 100),
+            other => panic!("Unexpected effect {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let mut battle = battle(3);
+        battle.apply(0, Decision::Action { ability: 2 }).unwrap();
+        assert_eq!(battle.teams[0].fighters[0].attribute(&Attribute::Defense), 7);
+
+        battle.apply(1, Decision::Action { ability: 3 }).unwrap();
+        assert_eq!(battle.teams[0].fighters[0].attribute(&Attribute::Defense), 5);
+    }
+
+    #[test]
+    fn test_swap_and_invalid_decisions() {
+        let mut battle = battle(4);
+        assert_eq!(battle.apply(0, Decision::Swap { creature: 0 }), Err(BattleError::InvalidSwap(0)));
+        assert_eq!(battle.apply(0, Decision::Swap { creature: 5 }), Err(BattleError::InvalidSwap(5)));
+        assert_eq!(battle.apply(0, Decision::Action { ability: 9 }), Err(BattleError::UnknownAbility(9)));
+
+        battle.apply(0, Decision::Swap { creature: 1 }).unwrap();
+        assert_eq!(battle.teams[0].active, 1);
+
+        battle.teams[1].fighters[0].state.abilities[0].available.0 = 0;
+        assert_eq!(battle.apply(1, Decision::Action { ability: 0 }), Err(BattleError::AbilityExhausted(0)));
+        assert!(!battle.legal_decisions(1).contains(&Decision::Action { ability: 0 }));
+    }
+
+    #[test]
+    fn test_reveals_creatures_and_abilities() {
+        let mut battle = battle(4);
+        assert!(battle.teams[0].fighters[0].revealed);
+        assert!(!battle.teams[0].fighters[1].revealed);
+
+        battle.apply(0, Decision::Swap { creature: 1 }).unwrap();
+        assert!(battle.teams[0].fighters[1].revealed);
+
+        battle.apply(1, Decision::Action { ability: 2 }).unwrap();
+        battle.apply(0, Decision::Wait).unwrap();
+        battle.apply(1, Decision::Action { ability: 2 }).unwrap();
+        assert_eq!(battle.teams[1].fighters[0].revealed_abilities, vec![2]);
+    }
+
+    #[test]
+    fn test_concede_ends_battle() {
+        let mut battle = battle(5);
+        let report = battle.apply(0, Decision::Concede).unwrap();
+        assert_eq!(report.winner, Some(1));
+        assert_eq!(battle.apply(1, Decision::Wait), Err(BattleError::Finished));
+    }
+
+    #[test]
+    fn test_fainting_swaps_and_ends_battle() {
+        let mut battle = battle(6);
+        battle.teams[0].fighters[0].state.health = 1;
+        battle.apply(0, Decision::Wait).unwrap();
+
+        // Drop attacks until Ember faints and Pebble takes over
+        while battle.teams[0].active == 0 {
+            battle.apply(1, Decision::Action { ability: 1 }).unwrap();
+            if battle.teams[0].active == 0 {
+                battle.apply(0, Decision::Wait).unwrap();
+            }
+        }
+        assert!(battle.teams[0].fighters[0].is_fainted());
+        assert!(!battle.is_finished());
+
+        battle.teams[0].fighters[1].state.health = 1;
+        while !battle.is_finished() {
+            battle.apply(0, Decision::Wait).unwrap();
+            battle.apply(1, Decision::Action { ability: 0 }).unwrap();
+        }
+        assert!(battle.teams[0].is_defeated());
+        assert_eq!(battle.winner, Some(1));
+    }
+
+    #[test]
+    fn test_restart_and_replay() {
+        let mut battle = battle(5);
+        let decisions = vec![(1, Decision::Action { ability: 0 }), (2, Decision::Action { ability: 1 }), (1, Decision::Swap { creature: 1 })];
+        for (player_id, decision) in &decisions {
+            battle.apply(battle.team_of(*player_id).unwrap(), decision.clone()).unwrap();
+        }
+
+        let states = battle.restart().unwrap().replay(&decisions).unwrap();
+        assert_eq!(states.len(), 4);
+        assert_eq!(states[0].turn, 0);
+        assert_eq!(states[0].teams[1].fighters[0].state.health, 100);
+        assert_eq!(states[3].teams[1].fighters[0].state.health, battle.teams[1].fighters[0].state.health);
+        assert_eq!(states[3].teams[0].active, 1);
+
+        assert_eq!(battle.restart().unwrap().replay(&[(3, Decision::Wait)]).unwrap_err(), BattleError::NotYourTurn);
+    }
+
+    #[test]
+    fn test_same_seed_same_result() {
+        let play = |seed| {
+            let mut battle = battle(seed);
+            let mut reports = Vec::new();
+            while !battle.is_finished() && battle.turn < 16 {
+                let team = battle.current;
+                reports.push(battle.apply(team, Decision::Action { ability: 1 }).unwrap());
+            }
+            reports
+        };
+        assert_eq!(play(42), play(42));
+    }
+}
@@ -0,0 +1,324 @@
+// src/bot.rs
+//! Scripted opponents, for single-player games and for testing the engine.
+//! Bots see the whole battle, but not the rolls to come: they plan over reseeded copies of it.
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::battle::{BattleState, Decision};
+use crate::catalog::Catalog;
+use crate::models::ability::{Ability, AbilityCategory};
+use crate::models::creature::{Attribute, Creature, Element};
+
+/// Creatures in the team of a bot.
+pub const BOT_TEAM_SIZE: usize = 3;
+/// Attribute points of a creature, as `CreateRequest::validate` asks of players.
+const ATTRIBUTE_POINTS: u8 = 25;
+/// Score of a won battle, above any difference of health.
+const WIN: f32 = 1000.0;
+
+pub trait Bot: Send + Sync {
+    /// Picks the decision of `team`, whose turn it is. Bots never concede.
+    fn decide(&self, battle: &BattleState, team: usize, rng: &mut ChaCha8Rng) -> Decision;
+}
+
+/// The decisions a bot chooses from: every legal one but conceding.
+pub fn moves(battle: &BattleState, team: usize) -> Vec<Decision> {
+    let mut moves = battle.legal_decisions(team);
+    moves.retain(|decision| *decision != Decision::Concede);
+    moves
+}
+
+/// Random numbers of a bot's turn. They come from the battle so the game stays reproducible,
+/// but never match the battle's own rolls.
+pub fn turn_rng(battle: &BattleState) -> ChaCha8Rng {
+    ChaCha8Rng::seed_from_u64(!battle.seed ^ (battle.turn as u64).wrapping_mul(0x2545_f491_4f6c_dd1d))
+}
+
+/// Picks any legal move.
+pub struct RandomBot;
+
+impl Bot for RandomBot {
+    fn decide(&self, battle: &BattleState, team: usize, rng: &mut ChaCha8Rng) -> Decision {
+        moves(battle, team).choose(rng).cloned().unwrap_or(Decision::Wait)
+    }
+}
+
+/// Attacks with the highest expected damage. Without one, strengthens itself or weakens the enemy.
+pub struct GreedyBot;
+
+impl Bot for GreedyBot {
+    fn decide(&self, battle: &BattleState, team: usize, _rng: &mut ChaCha8Rng) -> Decision {
+        let moves = moves(battle, team);
+        let best_attack = moves
+            .iter()
+            .filter_map(|decision| match decision {
+                Decision::Action { ability } => Some((decision, battle.expected_damage(team, *ability))),
+                _ => None,
+            })
+            .filter(|(_, damage)| *damage > 0.0)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        match best_attack {
+            Some((decision, _)) => decision.clone(),
+            None => moves.into_iter().next().unwrap_or(Decision::Wait),
+        }
+    }
+}
+
+/// Looks `depth` decisions ahead, assuming the opponent answers with its best move.
+/// Attacks are chance nodes, averaged over `samples` reseeded rolls.
+pub struct MinimaxBot {
+    pub depth: u32,
+    pub samples: u32,
+}
+
+impl Default for MinimaxBot {
+    fn default() -> Self {
+        MinimaxBot { depth: 2, samples: 4 }
+    }
+}
+
+/// Share of health left in each team, from the point of view of `team`.
+fn evaluate(battle: &BattleState, team: usize) -> f32 {
+    if let Some(winner) = battle.winner {
+        return if winner == team { WIN } else { -WIN };
+    }
+    let health = |team: usize| -> f32 {
+        battle.teams[team].fighters
+            .iter()
+            .map(|fighter| fighter.state.health as f32 / fighter.creature.max_health.max(1) as f32)
+            .sum()
+    };
+    health(team) - health(1 - team)
+}
+
+impl MinimaxBot {
+    /// Average value of `decision` for `team` over reseeded rolls.
+    fn expected(&self, battle: &BattleState, decision: &Decision, team: usize, depth: u32, rng: &mut ChaCha8Rng) -> f32 {
+        let current = battle.current;
+        let random = match decision {
+            Decision::Action { ability } => battle.teams[current].active_fighter().state.abilities
+                .get(*ability)
+                .is_some_and(|ability| ability.category == AbilityCategory::Attack),
+            _ => false,
+        };
+        let samples = if random { self.samples.max(1) } else { 1 };
+
+        let mut total = 0.0;
+        for _ in 0..samples {
+            let mut sample = battle.clone();
+            sample.seed = rng.gen();
+            if sample.apply(current, decision.clone()).is_err() {
+                return f32::NEG_INFINITY;
+            }
+            total += self.value(&sample, team, depth - 1, rng);
+        }
+        total / samples as f32
+    }
+
+    fn value(&self, battle: &BattleState, team: usize, depth: u32, rng: &mut ChaCha8Rng) -> f32 {
+        if depth == 0 || battle.is_finished() {
+            return evaluate(battle, team);
+        }
+        let values = moves(battle, battle.current)
+            .iter()
+            .map(|decision| self.expected(battle, decision, team, depth, rng))
+            .collect::<Vec<_>>();
+        if battle.current == team {
+            values.into_iter().fold(f32::NEG_INFINITY, f32::max)
+        } else {
+            values.into_iter().fold(f32::INFINITY, f32::min)
+        }
+    }
+}
+
+impl Bot for MinimaxBot {
+    fn decide(&self, battle: &BattleState, team: usize, rng: &mut ChaCha8Rng) -> Decision {
+        let mut best = (Decision::Wait, f32::NEG_INFINITY);
+        for decision in moves(battle, team) {
+            let value = self.expected(battle, &decision, team, self.depth.max(1), rng);
+            if value > best.1 {
+                best = (decision, value);
+            }
+        }
+        best.0
+    }
+}
+
+/// The bots a game can be played against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BotKind {
+    Random,
+    Greedy,
+    Minimax,
+}
+
+impl BotKind {
+    pub const ALL: [BotKind; 3] = [BotKind::Random, BotKind::Greedy, BotKind::Minimax];
+
+    pub fn bot(self) -> Box<dyn Bot> {
+        match self {
+            BotKind::Random => Box::new(RandomBot),
+            BotKind::Greedy => Box::new(GreedyBot),
+            BotKind::Minimax => Box::new(MinimaxBot::default()),
+        }
+    }
+}
+
+impl fmt::Display for BotKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BotKind::Random => "random",
+            BotKind::Greedy => "greedy",
+            BotKind::Minimax => "minimax",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for BotKind {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        BotKind::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == input.to_lowercase())
+            .ok_or_else(|| format!("Unknown bot `{}`, expected random, greedy or minimax", input))
+    }
+}
+
+/// A team for a bot, built from the catalog's ability templates, the same for the same seed.
+/// Each creature has an element of the catalog and one ability per template. Elemental templates
+/// carry the creature's element, the others are physical, or mental for utilities.
+pub fn team(catalog: &Catalog, owner: i64, size: usize, seed: u64) -> Vec<Creature> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let elements: Vec<Element> = catalog.elements.iter().filter(|element| !element.is_neutral()).cloned().collect();
+    let attributes = [Attribute::Strength, Attribute::Defense, Attribute::Perception, Attribute::Intelligence, Attribute::Wisdom];
+
+    (0..size)
+        .map(|i| {
+            let element = elements.choose(&mut rng).cloned().unwrap_or(Element::Physical);
+
+            // One point each, the rest spread at random
+            let mut points: HashMap<Attribute, u8> = attributes.iter().map(|attribute| (attribute.clone(), 1)).collect();
+            for _ in attributes.len()..ATTRIBUTE_POINTS as usize {
+                *points.get_mut(attributes.choose(&mut rng).unwrap()).unwrap() += 1;
+            }
+
+            let abilities = catalog.abilities
+                .iter()
+                .map(|template| {
+                    let elements = match template.category {
+                        _ if template.name.starts_with("Elemental") => vec![element.clone()],
+                        AbilityCategory::Utility => vec![Element::Mental],
+                        _ => vec![Element::Physical],
+                    };
+                    Ability {
+                        name: template.name.clone(),
+                        description: String::new(),
+                        base_damage: template.base_value as u16,
+                        available: (10, 10),
+                        elements,
+                        modifiers: vec![],
+                        category: template.category,
+                        target: template.category.target(),
+                    }
+                })
+                .collect();
+
+            Creature {
+                id: 0,
+                owner,
+                name: format!("{:?} Bot {}", element, i + 1),
+                description: String::new(),
+                image: None,
+                max_health: 100,
+                attributes: points,
+                elements: vec![element],
+                abilities,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battle::tests::battle;
+    use crate::catalog::CATALOG_PATH;
+
+    /// Plays a battle between two bots, returns the winning team.
+    fn play(bots: [&dyn Bot; 2], seed: u64) -> Option<usize> {
+        let catalog = Catalog::load(CATALOG_PATH).unwrap();
+        let mut battle = BattleState::new(seed, vec![
+            (1, team(&catalog, 1, 2, seed)),
+            (2, team(&catalog, 2, 2, seed + 1000)),
+        ]).unwrap();
+        while !battle.is_finished() && battle.turn < 500 {
+            let team = battle.current;
+            let decision = bots[team].decide(&battle, team, &mut turn_rng(&battle));
+            battle.apply(team, decision).unwrap();
+        }
+        battle.winner
+    }
+
+    #[test]
+    fn test_bots_make_legal_moves() {
+        let battle = battle(4);
+        let team = battle.current;
+        for kind in BotKind::ALL {
+            let decision = kind.bot().decide(&battle, team, &mut turn_rng(&battle));
+            assert!(moves(&battle, team).contains(&decision), "{} played {:?}", kind, decision);
+            assert_eq!(kind.bot().decide(&battle, team, &mut turn_rng(&battle)), decision, "{} is not reproducible", kind);
+        }
+    }
+
+    #[test]
+    fn test_greedy_picks_the_strongest_attack() {
+        let mut battle = battle(4);
+        if battle.current != 0 {
+            battle.apply(battle.current, Decision::Wait).unwrap();
+        }
+        // Ember's fire Blast is weak against Drop, the physical Strike is not
+        assert_eq!(GreedyBot.decide(&battle, 0, &mut turn_rng(&battle)), Decision::Action { ability: 0 });
+
+        // Without uses left, it moves on to the next attack
+        battle.teams[0].fighters[0].state.abilities[0].available.0 = 0;
+        assert_eq!(GreedyBot.decide(&battle, 0, &mut turn_rng(&battle)), Decision::Action { ability: 1 });
+    }
+
+    #[test]
+    fn test_smarter_bots_win_more() {
+        let wins = |bots: [&dyn Bot; 2]| (0..20).filter(|seed| play(bots, *seed) == Some(0)).count();
+        assert!(wins([&GreedyBot, &RandomBot]) >= 14);
+        assert!(wins([&MinimaxBot::default(), &RandomBot]) >= 14);
+    }
+
+    #[test]
+    fn test_team() {
+        let catalog = Catalog::load(CATALOG_PATH).unwrap();
+        let creatures = team(&catalog, 7, BOT_TEAM_SIZE, 42);
+        assert_eq!(creatures.len(), BOT_TEAM_SIZE);
+        for creature in &creatures {
+            assert_eq!(creature.owner, 7);
+            assert_eq!(creature.attributes.values().sum::<u8>(), ATTRIBUTE_POINTS);
+            assert_eq!(creature.abilities.len(), catalog.abilities.len());
+            assert!(!creature.elements[0].is_neutral());
+        }
+        let names = |creatures: Vec<Creature>| creatures.into_iter().map(|creature| creature.name).collect::<Vec<_>>();
+        assert_eq!(names(team(&catalog, 7, BOT_TEAM_SIZE, 42)), names(creatures));
+    }
+
+    #[test]
+    fn test_parse_kind() {
+        assert_eq!("Minimax".parse(), Ok(BotKind::Minimax));
+        assert!("smart".parse::<BotKind>().is_err());
+        assert_eq!(serde_json::to_string(&BotKind::Greedy).unwrap(), "\"greedy\"");
+    }
+}
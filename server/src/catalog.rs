@@ -0,0 +1,313 @@
+// src/catalog.rs
+use arc_swap::ArcSwap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::embedding::{self, Category};
+use crate::models::{ability::SmolAbility, creature::Element};
+
+/// Catalog file declaring the ability templates, elements and every other category.
+pub const CATALOG_PATH: &str = "database/available.json";
+
+#[derive(Error, Debug)]
+pub enum CatalogError {
+    #[error("Failed to read catalog: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Catalog is not valid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Catalog has {} malformed entries:\n  {}", .0.len(), .0.join("\n  "))]
+    Invalid(Vec<String>),
+    #[error("Failed to load embeddings: {0}")]
+    Storage(String),
+}
+
+/// How a category is stored, as declared under `Categories` in the catalog file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CategoryDeclaration {
+    /// File name of the serialized embeddings, relative to the storage directory
+    pub storage: String,
+}
+
+/// A single entry of a category in the catalog file.
+/// Either a bare name (`"Fire"`) or an object with a name and description.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CatalogEntry {
+    Name(String),
+    Described { name: String, description: String },
+}
+
+/// A category as declared in the catalog file, along with its entries.
+#[derive(Debug, Clone)]
+pub struct DeclaredCategory {
+    pub category: Category,
+    pub declaration: CategoryDeclaration,
+    pub entries: Vec<CatalogEntry>,
+}
+
+impl CatalogEntry {
+    /// The name returned by `search` when this entry matches.
+    pub fn name(&self) -> &str {
+        match self {
+            CatalogEntry::Name(name) => name,
+            CatalogEntry::Described { name, .. } => name,
+        }
+    }
+
+    /// The text that is embedded for this entry.
+    pub fn embedding_text(&self) -> String {
+        match self {
+            CatalogEntry::Name(name) => name.clone(),
+            CatalogEntry::Described { name, description } => format!("{}: {}", name, description),
+        }
+    }
+}
+
+/// The static list of abilities, elements and other categories player text is matched against.
+/// Loaded and validated once; every entry is checked so a bad file is reported in one go.
+#[derive(Debug, Clone)]
+pub struct Catalog {
+    /// Fingerprint of the catalog file, recorded in the games played with it
+    pub version: String,
+    pub abilities: Vec<SmolAbility>,
+    pub elements: Vec<Element>,
+    pub categories: Vec<DeclaredCategory>,
+}
+
+/// Parses every entry of `values` on its own, recording a message per malformed one.
+fn parse_entries<T: DeserializeOwned>(key: &str, values: &[serde_json::Value], errors: &mut Vec<String>) -> Vec<T> {
+    values
+        .iter()
+        .enumerate()
+        .filter_map(|(i, value)| match serde_json::from_value(value.clone()) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                errors.push(format!("{}[{}]: {}", key, i, e));
+                None
+            }
+        })
+        .collect()
+}
+
+impl Catalog {
+    /// Reads and validates the catalog file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CatalogError> {
+        let catalog = fs::read_to_string(path)?;
+        Catalog::parse(&catalog)
+    }
+
+    /// Parses and validates a catalog, listing every problem found.
+    pub fn parse(catalog: &str) -> Result<Self, CatalogError> {
+        let version = crate::replay::fingerprint(catalog.as_bytes());
+        let catalog: HashMap<String, serde_json::Value> = serde_json::from_str(catalog)?;
+        let mut errors = Vec::new();
+
+        let declarations: Vec<(String, CategoryDeclaration)> = match catalog.get("Categories").and_then(|c| c.as_object()) {
+            Some(declarations) => {
+                let mut parsed = Vec::new();
+                for (name, declaration) in declarations {
+                    match serde_json::from_value::<CategoryDeclaration>(declaration.clone()) {
+                        Ok(declaration) if declaration.storage.trim().is_empty() => {
+                            errors.push(format!("Categories.{}: storage must not be empty", name));
+                        }
+                        Ok(declaration) => parsed.push((name.clone(), declaration)),
+                        Err(e) => errors.push(format!("Categories.{}: {}", name, e)),
+                    }
+                }
+                parsed
+            }
+            None => {
+                errors.push("Categories: missing or not an object".to_string());
+                Vec::new()
+            }
+        };
+
+        let mut storage_files = HashSet::new();
+        for (name, declaration) in &declarations {
+            if !storage_files.insert(declaration.storage.as_str()) {
+                errors.push(format!("Categories.{}: storage `{}` is used twice", name, declaration.storage));
+            }
+        }
+
+        for key in catalog.keys().filter(|key| *key != "Categories") {
+            if !declarations.iter().any(|(name, _)| name == key) {
+                errors.push(format!("{}: category is not declared in `Categories`", key));
+            }
+        }
+
+        let mut categories = Vec::new();
+        for (name, declaration) in declarations {
+            let values = match catalog.get(&name).and_then(|values| values.as_array()) {
+                Some(values) => values,
+                None => {
+                    errors.push(format!("{}: declared but has no list of entries", name));
+                    continue;
+                }
+            };
+
+            let entries: Vec<CatalogEntry> = parse_entries(&name, values, &mut errors);
+            let mut names = HashSet::new();
+            for entry in &entries {
+                if !names.insert(entry.name()) {
+                    errors.push(format!("{}: `{}` appears more than once", name, entry.name()));
+                }
+            }
+            categories.push(DeclaredCategory { category: Category::new(name), declaration, entries });
+        }
+
+        let mut typed = |category: &Category| -> Vec<serde_json::Value> {
+            if !categories.iter().any(|declared| declared.category == *category) {
+                errors.push(format!("{}: required category is not declared", category));
+            }
+            catalog.get(category.name()).and_then(|values| values.as_array()).cloned().unwrap_or_default()
+        };
+        let ability_values = typed(&embedding::Category::ABILITY);
+        let element_values = typed(&embedding::Category::ELEMENT);
+
+        let abilities: Vec<SmolAbility> = parse_entries("Ability", &ability_values, &mut errors);
+        let elements: Vec<Element> = parse_entries("Element", &element_values, &mut errors);
+
+        if !errors.is_empty() {
+            return Err(CatalogError::Invalid(errors));
+        }
+
+        Ok(Catalog { version, abilities, elements, categories })
+    }
+}
+
+/// The catalog shared with the handlers. Swapped atomically on reload so requests in flight
+/// keep the catalog they started with.
+pub struct SharedCatalog {
+    path: PathBuf,
+    storage_dir: PathBuf,
+    current: ArcSwap<Catalog>,
+}
+
+impl SharedCatalog {
+    /// Loads the catalog and the embeddings of every category it declares from `storage_dir`.
+    pub fn load(path: impl AsRef<Path>, storage_dir: impl AsRef<Path>) -> Result<Self, CatalogError> {
+        let catalog = Catalog::load(&path)?;
+        embedding::initialize_storage(&catalog, storage_dir.as_ref()).map_err(|e| CatalogError::Storage(e.to_string()))?;
+
+        Ok(SharedCatalog {
+            path: path.as_ref().to_path_buf(),
+            storage_dir: storage_dir.as_ref().to_path_buf(),
+            current: ArcSwap::from_pointee(catalog),
+        })
+    }
+
+    /// Shares an already loaded catalog without touching the embedding storage.
+    #[cfg(test)]
+    pub(crate) fn fixed(path: impl AsRef<Path>, storage_dir: impl AsRef<Path>, catalog: Catalog) -> Self {
+        SharedCatalog {
+            path: path.as_ref().to_path_buf(),
+            storage_dir: storage_dir.as_ref().to_path_buf(),
+            current: ArcSwap::from_pointee(catalog),
+        }
+    }
+
+    pub fn current(&self) -> Arc<Catalog> {
+        self.current.load_full()
+    }
+
+    /// Re-reads the catalog file. On failure the current catalog stays in place.
+    pub fn reload(&self) -> Result<Arc<Catalog>, CatalogError> {
+        let catalog = Catalog::load(&self.path)?;
+        embedding::initialize_storage(&catalog, &self.storage_dir).map_err(|e| CatalogError::Storage(e.to_string()))?;
+
+        let catalog = Arc::new(catalog);
+        self.current.store(Arc::clone(&catalog));
+        log::info!(
+            "Reloaded catalog: {} abilities, {} elements, {} categories",
+            catalog.abilities.len(), catalog.elements.len(), catalog.categories.len()
+        );
+        Ok(catalog)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_catalog() {
+        let catalog = Catalog::load(CATALOG_PATH).expect("Failed to load catalog");
+        let names: Vec<&str> = catalog.categories.iter().map(|declared| declared.category.name()).collect();
+        for name in ["Ability", "Element", "Status", "Item", "Archetype", "Style"] {
+            assert!(names.contains(&name), "Missing category {}", name);
+        }
+
+        assert_eq!(catalog.abilities.len(), 4);
+        assert!(catalog.elements.contains(&Element::Physical));
+
+        let abilities = catalog.categories.iter().find(|declared| declared.category == Category::ABILITY).unwrap();
+        assert_eq!(abilities.declaration.storage, "abilities.bin");
+        assert_eq!(abilities.entries[0].embedding_text(), "Basic Attack: A basic attack that does physical damage");
+
+        let elements = catalog.categories.iter().find(|declared| declared.category == Category::ELEMENT).unwrap();
+        assert_eq!(elements.entries[0], CatalogEntry::Name("Fire".to_string()));
+        assert_eq!(elements.entries[0].embedding_text(), "Fire");
+    }
+
+    #[test]
+    fn test_lists_every_malformed_entry() {
+        let catalog = r#"{
+            "Categories": {
+                "Ability": { "storage": "abilities.bin" },
+                "Element": { "storage": "elements.bin" },
+                "Status": { "storage": "elements.bin" }
+            },
+            "Ability": [
+                { "name": "Basic Attack", "description": "Hits", "base_value": 10, "modifier": 0.1, "category": "Attack" },
+                { "name": "Broken", "description": "No category", "base_value": 10, "modifier": 0.1 },
+                { "name": "Too Strong", "description": "Overflows", "base_value": 1000, "modifier": 0.1, "category": "Attack" }
+            ],
+            "Element": ["Fire", "Plasma", "Fire"],
+            "Weather": ["Rain"]
+        }"#;
+
+        let errors = match Catalog::parse(catalog) {
+            Err(CatalogError::Invalid(errors)) => errors,
+            other => panic!("Expected invalid catalog, got {:?}", other),
+        };
+
+        let expected = [
+            "storage `elements.bin` is used twice",
+            "Weather: category is not declared",
+            "Status: declared but has no list of entries",
+            "Element: `Fire` appears more than once",
+            "Ability[1]",
+            "Ability[2]",
+            "Element[1]",
+        ];
+        for expected in expected {
+            assert!(errors.iter().any(|e| e.contains(expected)), "Missing `{}` in {:?}", expected, errors);
+        }
+        assert_eq!(errors.len(), expected.len());
+    }
+
+    #[test]
+    fn test_requires_builtin_categories() {
+        let catalog = r#"{ "Categories": { "Status": { "storage": "statuses.bin" } }, "Status": ["Burned"] }"#;
+        let error = Catalog::parse(catalog).unwrap_err().to_string();
+        assert!(error.contains("Ability: required category is not declared"));
+        assert!(error.contains("Element: required category is not declared"));
+    }
+
+    #[test]
+    fn test_failed_reload_keeps_current_catalog() {
+        let path = std::env::temp_dir().join(format!("catalog_{}.json", std::process::id()));
+        fs::write(&path, r#"{ "Categories": {} }"#).unwrap();
+
+        let shared = SharedCatalog::fixed(&path, "database", Catalog::load(CATALOG_PATH).unwrap());
+        let result = shared.reload();
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(CatalogError::Invalid(_))));
+        assert_eq!(shared.current().abilities.len(), 4);
+    }
+}
@@ -0,0 +1,672 @@
+// src/cli.rs
+//! The command line of the server binary. Every command reads the same config, see README "# Command line".
+use clap::{Args, Parser, Subcommand};
+use rusqlite::Connection;
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::agent::Agent;
+use crate::bot::BotKind;
+use crate::catalog::Catalog;
+use crate::config::Config;
+use crate::db;
+use crate::embedding;
+use crate::handlers;
+use crate::migrations;
+use crate::models::creature::{CreateRequest, Creature};
+use crate::ratings;
+use crate::replay::Replay;
+use crate::simulation::{self, Setup};
+use crate::tournament::{self, Entrant, Pairing, Player, Schedule};
+#[cfg(feature = "postgres")]
+use crate::repository::PostgresRepository;
+use crate::repository::{GameRepository, SqliteRepository};
+
+#[derive(Parser, Debug)]
+#[command(name = "battllm_server", about = "Runs and maintains the Battllm server")]
+pub struct Cli {
+    /// Runs the server when omitted
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub settings: Settings,
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum Command {
+    /// Run the HTTP server
+    Serve,
+    /// Bring the database schema up to date
+    Migrate,
+    /// Embed the catalog entries the embedding storage is missing
+    SeedCatalog,
+    /// Play battles between two teams with bots, greedy ones by default, and analyse their balance
+    Simulate(SimulateArgs),
+    /// Play a tournament between teams played by bots and LLM agents
+    Tournament(TournamentArgs),
+    /// Print the state of a game as JSON
+    InspectGame {
+        game_id: i64,
+        /// Include the game's event ledger
+        #[arg(long)]
+        events: bool,
+    },
+    /// Write the replay of a finished game
+    ExportReplay {
+        game_id: i64,
+        /// File to write, standard output when omitted
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Play a replay file again and check it reproduces the recorded states
+    Replay { file: String },
+    /// Rebuild every rating from the ledgers of the finished games
+    RecomputeRatings,
+    /// Play a game with an LLM, through the API of a running server
+    Agent {
+        game_id: i64,
+        /// Token of the player the agent plays
+        #[arg(long)]
+        token: String,
+        /// URL of the server, the configured host and port when omitted
+        #[arg(long)]
+        server: Option<String>,
+    },
+}
+
+/// Flags overriding the config file and the environment. Accepted before or after the command.
+#[derive(Args, Debug, Default, Clone, PartialEq)]
+pub struct Settings {
+    /// Config file, `battllm.toml` when it exists
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Address the server listens on
+    #[arg(long, global = true)]
+    pub host: Option<String>,
+    /// Port the server listens on
+    #[arg(long, global = true)]
+    pub port: Option<String>,
+    /// Worker threads serving requests
+    #[arg(long, global = true)]
+    pub workers: Option<String>,
+    /// SQLite file, or `postgres://` URL
+    #[arg(long, global = true)]
+    pub database_url: Option<String>,
+    /// Connections kept open to the database
+    #[arg(long, global = true)]
+    pub pool_size: Option<String>,
+    /// Catalog file
+    #[arg(long, global = true)]
+    pub catalog_path: Option<String>,
+    /// Directory of the serialized embeddings
+    #[arg(long, global = true)]
+    pub storage_dir: Option<String>,
+    /// Directory of the log files
+    #[arg(long, global = true)]
+    pub log_dir: Option<String>,
+    /// `off`, `error`, `warn`, `info`, `debug` or `trace`
+    #[arg(long, global = true)]
+    pub log_level: Option<String>,
+    /// Token of the admin endpoints
+    #[arg(long, global = true)]
+    pub admin_token: Option<String>,
+    /// OpenAI-compatible API the LLM agents play with
+    #[arg(long, global = true)]
+    pub agent_base_url: Option<String>,
+    /// Model the LLM agents play with
+    #[arg(long, global = true)]
+    pub agent_model: Option<String>,
+    /// Times an agent asks again after an illegal reply
+    #[arg(long, global = true)]
+    pub agent_retries: Option<String>,
+}
+
+impl Settings {
+    /// The flags given, as `Config::load` takes them.
+    pub fn to_args(&self) -> Vec<String> {
+        let config = self.config.as_ref().map(|path| path.display().to_string());
+        let settings = [
+            ("config", &config),
+            ("host", &self.host),
+            ("port", &self.port),
+            ("workers", &self.workers),
+            ("database-url", &self.database_url),
+            ("pool-size", &self.pool_size),
+            ("catalog-path", &self.catalog_path),
+            ("storage-dir", &self.storage_dir),
+            ("log-dir", &self.log_dir),
+            ("log-level", &self.log_level),
+            ("admin-token", &self.admin_token),
+            ("agent-base-url", &self.agent_base_url),
+            ("agent-model", &self.agent_model),
+            ("agent-retries", &self.agent_retries),
+        ];
+        settings
+            .into_iter()
+            .filter_map(|(key, value)| value.as_ref().map(|value| format!("--{}={}", key, value)))
+            .collect()
+    }
+}
+
+#[derive(Args, Debug, Clone, PartialEq)]
+pub struct SimulateArgs {
+    /// JSON file with the creatures of the first team, as listed by `/{game_id}/creatures` or
+    /// as sent to create them like `examples/creature.json`
+    pub first: PathBuf,
+    /// JSON file with the creatures of the second team
+    pub second: PathBuf,
+    /// Battles to play
+    #[arg(long, default_value_t = 1000)]
+    pub games: u32,
+    /// Seed of the first battle, the next ones count up from it
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+    /// Battles still going after this many turns are a draw
+    #[arg(long, default_value_t = 500)]
+    pub max_turns: u32,
+    /// Bot playing both teams, or give it twice for one per team
+    #[arg(long = "bot", default_value = "greedy", num_args = 1..=2)]
+    pub bots: Vec<BotKind>,
+    /// Print the analysis as JSON rather than tables
+    #[arg(long)]
+    pub json: bool,
+    /// Print every turn as a line of JSON
+    #[arg(long)]
+    pub verbose: bool,
+}
+
+#[derive(Args, Debug, Clone, PartialEq)]
+pub struct TournamentArgs {
+    /// Team file, as for `simulate`. Repeat it, every team enters once per player
+    #[arg(long = "team", required = true)]
+    pub teams: Vec<PathBuf>,
+    /// `random`, `greedy`, `minimax`, `agent` or `agent:<model>`. Repeat it to enter several
+    #[arg(long = "player", default_value = "greedy")]
+    pub players: Vec<Player>,
+    #[arg(long, value_enum, default_value_t = Pairing::RoundRobin)]
+    pub pairing: Pairing,
+    /// Rounds of a Swiss tournament, enough to single out a winner when omitted
+    #[arg(long)]
+    pub rounds: Option<u32>,
+    /// Battles of each match, the entrants swap sides after each
+    #[arg(long, default_value_t = 2)]
+    pub games: u32,
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+    /// Battles still going after this many turns are a draw
+    #[arg(long, default_value_t = 500)]
+    pub max_turns: u32,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    pub format: OutputFormat,
+    /// File to write, standard output when omitted
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Json,
+    /// Standings, win matrix and matchups, a blank line apart
+    Csv,
+}
+
+/// Opens the database of the config and brings its schema up to date.
+pub fn open_repository(config: &Config) -> Result<Box<dyn GameRepository>, Box<dyn Error>> {
+    let database_url = config.database_url()?;
+    if config.is_postgres() {
+        let (repository, _) = open_postgres(config)?;
+        return Ok(Box::new(repository));
+    }
+    let pool = db::open_pool(database_url, config.pool_size)?;
+    db::initialize_database(&*pool.get()?);
+    Ok(Box::new(SqliteRepository::new(pool)))
+}
+
+/// Connects to PostgreSQL and brings its schema up to date. Returns the versions applied.
+/// The client blocks, call it off the async runtime.
+#[cfg(feature = "postgres")]
+pub fn open_postgres(config: &Config) -> Result<(PostgresRepository, Vec<u32>), Box<dyn Error>> {
+    let repository = PostgresRepository::connect(config.database_url()?, config.pool_size)?;
+    let applied = repository.migrate()?;
+    Ok((repository, applied))
+}
+
+/// Always fails, the repository type only keeps the callers the same in both builds.
+#[cfg(not(feature = "postgres"))]
+pub fn open_postgres(_config: &Config) -> Result<(SqliteRepository, Vec<u32>), Box<dyn Error>> {
+    Err("this build has no PostgreSQL support, build with `--features postgres`".into())
+}
+
+/// `migrate`: brings the database schema up to date without starting the server.
+/// Returns the exit code.
+pub fn run_migrate(config: &Config) -> i32 {
+    let database_url = match config.database_url() {
+        Ok(database_url) => database_url,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    if config.is_postgres() {
+        return match open_postgres(config) {
+            Ok((_, applied)) => {
+                println!("Applied migrations {:?}, schema is at version {}", applied, migrations::head());
+                0
+            }
+            Err(e) => {
+                eprintln!("Migration failed, the schema is left at the last applied version: {}", e);
+                1
+            }
+        };
+    }
+    let conn = match Connection::open(database_url) {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Failed to open {}: {}", database_url, e);
+            return 1;
+        }
+    };
+
+    match migrations::migrate(&conn) {
+        Ok(applied) if applied.is_empty() => println!("Schema is up to date at version {}", migrations::head()),
+        Ok(applied) => println!("Applied migrations {:?}, schema is at version {}", applied, migrations::head()),
+        Err(e) => {
+            eprintln!("Migration failed, the schema is left at the last applied version: {}", e);
+            return 1;
+        }
+    }
+    if let Err(e) = db::migrate_creature_blobs(&conn) {
+        eprintln!("Failed to move creatures out of the Player table: {}", e);
+        return 1;
+    }
+    0
+}
+
+/// `seed-catalog`: embeds whatever the catalog file has that the storage doesn't. Returns the exit code.
+pub async fn run_seed_catalog(config: &Config) -> i32 {
+    let catalog = match Catalog::load(&config.catalog_path) {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", config.catalog_path.display(), e);
+            return 1;
+        }
+    };
+    if let Err(e) = embedding::initialize_storage(&catalog, &config.storage_dir) {
+        eprintln!("Failed to load the embeddings: {}", e);
+        return 1;
+    }
+
+    for declared in &catalog.categories {
+        match embedding::seed(&declared.category, &declared.entries, &config.storage_dir).await {
+            Ok(seeded) => println!("{}: embedded {} of {} entries", declared.category, seeded, declared.entries.len()),
+            Err(e) => {
+                eprintln!("Failed to seed {}, the entries embedded so far are lost: {}", declared.category, e);
+                return 1;
+            }
+        }
+    }
+    0
+}
+
+/// Reads a team file, a list of creatures or a single one. Creatures with an `id` are read as
+/// listed by the server, the others as sent to create them and have their abilities filled from
+/// `catalog`, which needs its embeddings from `storage_dir`.
+pub async fn load_team(path: &Path, catalog: &Catalog, storage_dir: &Path) -> Result<Vec<Creature>, Box<dyn Error>> {
+    let value: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let entries = match value {
+        serde_json::Value::Array(entries) => entries,
+        entry => vec![entry],
+    };
+
+    // Every entry is checked before the embeddings are loaded, which only happens if some need filling
+    let mut parsed = Vec::new();
+    for entry in entries {
+        if entry.get("id").is_some() {
+            parsed.push(TeamEntry::Creature(serde_json::from_value(entry)?));
+        } else {
+            let request: CreateRequest = serde_json::from_value(entry)?;
+            request.validate()?;
+            parsed.push(TeamEntry::Request(request));
+        }
+    }
+    if parsed.iter().any(|entry| matches!(entry, TeamEntry::Request(_))) {
+        embedding::initialize_storage(catalog, storage_dir)?;
+    }
+
+    let mut team = Vec::new();
+    for entry in parsed {
+        team.push(match entry {
+            TeamEntry::Creature(creature) => creature,
+            TeamEntry::Request(request) => request.transform(catalog).await?,
+        });
+    }
+    Ok(team)
+}
+
+/// An entry of a team file, see `load_team`.
+enum TeamEntry {
+    Creature(Creature),
+    Request(CreateRequest),
+}
+
+/// `simulate`: plays the teams against each other and prints the balance analysis. Returns the exit code.
+pub async fn run_simulate(config: &Config, args: &SimulateArgs) -> i32 {
+    let catalog = match Catalog::load(&config.catalog_path) {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", config.catalog_path.display(), e);
+            return 1;
+        }
+    };
+    let mut teams = Vec::new();
+    for path in [&args.first, &args.second] {
+        match load_team(path, &catalog, &config.storage_dir).await {
+            Ok(team) => teams.push(team),
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                return 1;
+            }
+        }
+    }
+    let teams: [Vec<Creature>; 2] = teams.try_into().expect("two team files were read");
+
+    let setup = Setup {
+        bots: [args.bots[0], *args.bots.last().expect("clap requires a bot")],
+        games: args.games,
+        seed: args.seed,
+        max_turns: args.max_turns,
+        verbose: args.verbose,
+    };
+    let mut out = io::stdout().lock();
+    let written = simulation::simulate(teams, &setup, &catalog, &mut out).and_then(|simulation| {
+        if args.json {
+            writeln!(out, "{}", serde_json::to_string_pretty(&simulation)?)?;
+        } else {
+            simulation.write_summary(&mut out)?;
+        }
+        Ok(())
+    });
+    match written {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Simulation failed: {}", e);
+            1
+        }
+    }
+}
+
+/// The entrants of a tournament: every team with every player, named after the team file.
+pub async fn entrants(args: &TournamentArgs, catalog: &Catalog, storage_dir: &Path) -> Result<Vec<Entrant>, Box<dyn Error>> {
+    let mut entrants = Vec::new();
+    for path in &args.teams {
+        let team = load_team(path, catalog, storage_dir).await.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let stem = path.file_stem().map_or_else(|| path.display().to_string(), |stem| stem.to_string_lossy().into_owned());
+        for player in &args.players {
+            entrants.push(Entrant { name: format!("{} ({})", stem, player), team: team.clone(), player: player.clone() });
+        }
+    }
+    Ok(entrants)
+}
+
+/// `tournament`: plays it and writes the results. Returns the exit code.
+pub async fn run_tournament(config: &Config, args: &TournamentArgs) -> i32 {
+    let schedule = Schedule {
+        pairing: args.pairing,
+        rounds: args.rounds,
+        games: args.games,
+        seed: args.seed,
+        max_turns: args.max_turns,
+    };
+    let result = match Catalog::load(&config.catalog_path) {
+        Ok(catalog) => match entrants(args, &catalog, &config.storage_dir).await {
+            Ok(entrants) => tournament::run(&entrants, &schedule, config).await,
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e.into()),
+    };
+    let tournament = match result {
+        Ok(tournament) => tournament,
+        Err(e) => {
+            eprintln!("Tournament failed: {}", e);
+            return 1;
+        }
+    };
+
+    let write = |out: &mut dyn Write| -> Result<(), Box<dyn Error>> {
+        match args.format {
+            OutputFormat::Json => writeln!(out, "{}", serde_json::to_string_pretty(&tournament)?)?,
+            OutputFormat::Csv => tournament::write_csv(&tournament, out)?,
+        }
+        Ok(())
+    };
+    let written = match &args.output {
+        Some(path) => std::fs::File::create(path)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|mut file| write(&mut file)),
+        None => write(&mut io::stdout().lock()),
+    };
+    match written {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Failed to write the results: {}", e);
+            1
+        }
+    }
+}
+
+/// Writes the state of the game as JSON, with its ledger when `events`.
+pub fn inspect_game(repo: &dyn GameRepository, game_id: i64, events: bool, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let mut inspection = serde_json::json!({
+        "state": repo.load_state(game_id)?,
+        "catalog_version": repo.get_catalog_version(game_id)?,
+    });
+    if events {
+        inspection["events"] = serde_json::to_value(repo.events_since(game_id, 0)?)?;
+    }
+    writeln!(out, "{}", serde_json::to_string_pretty(&inspection)?)?;
+    Ok(())
+}
+
+/// Writes the replay of the game, as `/{game_id}/replay` serves it.
+pub fn export_replay(repo: &dyn GameRepository, game_id: i64, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let replay = handlers::export_replay(repo, game_id)?.ok_or_else(|| format!("Game {} has not finished", game_id))?;
+    writeln!(out, "{}", serde_json::to_string_pretty(&replay)?)?;
+    Ok(())
+}
+
+/// Rebuilds the ratings and reports how many games they count.
+pub fn recompute_ratings(repo: &dyn GameRepository, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let rated = ratings::recompute(repo)?;
+    writeln!(out, "Recomputed ratings from {} finished games", rated)?;
+    Ok(())
+}
+
+/// `inspect-game`, `export-replay` and `recompute-ratings`: runs `command` on the database.
+/// Returns the exit code.
+pub fn run_with_repository(
+    config: &Config,
+    command: impl FnOnce(&dyn GameRepository, &mut dyn Write) -> Result<(), Box<dyn Error>>,
+    output: Option<&Path>,
+) -> i32 {
+    let repository = match open_repository(config) {
+        Ok(repository) => repository,
+        Err(e) => {
+            eprintln!("Failed to open the database: {}", e);
+            return 1;
+        }
+    };
+    let result = match output {
+        Some(path) => std::fs::File::create(path)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|mut file| command(repository.as_ref(), &mut file)),
+        None => command(repository.as_ref(), &mut io::stdout().lock()),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// `replay <file>`: plays a replay file again and checks it reproduces the recorded states.
+/// Returns the exit code.
+pub fn run_replay(config: &Config, path: &str) -> i32 {
+    let replay = match Replay::load(path) {
+        Ok(replay) => replay,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", path, e);
+            return 1;
+        }
+    };
+
+    // Creatures are stored filled, a different catalog only matters when investigating a report
+    match Catalog::load(&config.catalog_path) {
+        Ok(catalog) if catalog.version != replay.catalog_version => {
+            eprintln!("Warning: game played with catalog {}, current catalog is {}", replay.catalog_version, catalog.version);
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("Warning: could not compare catalog versions: {}", e),
+    }
+
+    match replay.verify() {
+        Ok(battle) => {
+            let winner = battle.winner.map(|team| battle.teams[team].player_id);
+            println!("Game {} reproduced over {} decisions, winner: {:?}", replay.game_id, replay.decisions.len(), winner);
+            0
+        }
+        Err(e) => {
+            eprintln!("Game {} does not reproduce: {}", replay.game_id, e);
+            1
+        }
+    }
+}
+
+/// `agent`: plays the player of `token` until the game is over. Returns the exit code.
+pub async fn run_agent(config: &Config, game_id: i64, token: &str, server: Option<&str>) -> i32 {
+    let server = server.map_or_else(|| format!("http://{}:{}", config.host, config.port), str::to_string);
+    match Agent::from_config(config).play(&server, game_id, token).await {
+        Ok(view) => {
+            println!("Game {} is over, winner: {:?}", game_id, view.winner);
+            0
+        }
+        Err(e) => {
+            eprintln!("Agent stopped: {}", e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battle::tests::creature;
+    use crate::battle::{BattleState, Decision};
+    use crate::events::GameEvent;
+    use crate::models::creature::Element;
+    use crate::repository::{MemoryRepository, Seat};
+    use crate::rules::RuleSet;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("battllm_server").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        let cli = parse(&[]).unwrap();
+        assert_eq!(cli.command, None);
+
+        // Settings go before or after the command
+        let cli = parse(&["--port", "9000", "inspect-game", "3", "--events", "--log-level=debug"]).unwrap();
+        assert_eq!(cli.command, Some(Command::InspectGame { game_id: 3, events: true }));
+        assert_eq!(cli.settings.to_args(), vec!["--port=9000", "--log-level=debug"]);
+        let config = Config::load(&cli.settings.to_args(), Vec::new()).unwrap();
+        assert_eq!(config.port, 9000);
+
+        let cli = parse(&["export-replay", "7", "-o", "replay.json"]).unwrap();
+        assert_eq!(cli.command, Some(Command::ExportReplay { game_id: 7, output: Some(PathBuf::from("replay.json")) }));
+        let Some(Command::Simulate(args)) = parse(&["simulate", "a.json", "b.json", "--games", "20"]).unwrap().command else { panic!("not a simulation") };
+        assert_eq!((args.games, args.bots.clone(), args.json), (20, vec![crate::bot::BotKind::Greedy], false));
+        let Some(Command::Simulate(args)) = parse(&["simulate", "a.json", "b.json", "--bot", "minimax", "--bot", "random", "--json"]).unwrap().command else { panic!("not a simulation") };
+        assert_eq!((args.bots, args.json), (vec![crate::bot::BotKind::Minimax, crate::bot::BotKind::Random], true));
+        assert!(parse(&["simulate", "a.json", "b.json", "--bot", "human"]).is_err());
+
+        let cli = parse(&["tournament", "--team", "a.json", "--team", "b.json", "--player", "minimax", "--player", "agent:gpt-4o", "--pairing", "swiss"]).unwrap();
+        let Some(Command::Tournament(args)) = cli.command else { panic!("not a tournament") };
+        assert_eq!(args.teams.len(), 2);
+        assert_eq!(args.players, vec![Player::Bot(crate::bot::BotKind::Minimax), Player::Agent { model: Some("gpt-4o".to_string()) }]);
+        assert_eq!((args.pairing, args.games, args.format), (Pairing::Swiss, 2, OutputFormat::Json));
+        assert!(parse(&["tournament", "--player", "greedy"]).is_err());
+        assert!(parse(&["tournament", "--team", "a.json", "--player", "human"]).is_err());
+
+        assert_eq!(parse(&["recompute-ratings"]).unwrap().command, Some(Command::RecomputeRatings));
+        assert!(parse(&["export-replay"]).is_err());
+        assert!(parse(&["inspect-game", "three"]).is_err());
+        assert!(parse(&["--prot", "80"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_load_team() {
+        let catalog = Catalog::load(crate::catalog::CATALOG_PATH).unwrap();
+        let storage_dir = Config::default().storage_dir;
+        let dir = std::env::temp_dir().join(format!("battllm_team_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("team.json");
+        let team = vec![creature(1, "Ember", vec![Element::Fire], 6), creature(1, "Drop", vec![Element::Water], 4)];
+        std::fs::write(&path, serde_json::to_string(&team).unwrap()).unwrap();
+        let loaded = load_team(&path, &catalog, &storage_dir).await.unwrap();
+        assert_eq!(loaded.iter().map(|creature| creature.name.as_str()).collect::<Vec<_>>(), vec!["Ember", "Drop"]);
+
+        std::fs::write(&path, serde_json::to_string(&team[0]).unwrap()).unwrap();
+        assert_eq!(load_team(&path, &catalog, &storage_dir).await.unwrap().len(), 1);
+
+        // A creation request is checked before anything is filled
+        let request = std::fs::read_to_string("examples/creature.json").unwrap().replace("\"wisdom\": 5", "\"wisdom\": 9");
+        std::fs::write(&path, request).unwrap();
+        assert!(load_team(&path, &catalog, &storage_dir).await.unwrap_err().to_string().contains("add up to"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_inspect_and_export() {
+        let repo = MemoryRepository::new();
+        let (game_id, Seat { id: owner, .. }) = repo.create_game("Owner", &RuleSet::default(), "v1").unwrap();
+        let guest = repo.join_game(game_id, "Guest").unwrap().id;
+        repo.save_creature(game_id, owner, &creature(owner, "Ember", vec![Element::Fire], 6)).unwrap();
+        repo.save_creature(game_id, guest, &creature(guest, "Drop", vec![Element::Water], 4)).unwrap();
+
+        let mut out = Vec::new();
+        inspect_game(&repo, game_id, false, &mut out).unwrap();
+        let inspection: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(inspection["state"]["game_id"], game_id);
+        assert_eq!(inspection["catalog_version"], "v1");
+        assert!(inspection.get("events").is_none());
+        assert!(inspect_game(&repo, game_id + 1, false, &mut Vec::new()).is_err());
+
+        // Only finished games have a replay
+        let mut battle = BattleState::new(repo.get_seed(game_id).unwrap(), vec![
+            (owner, repo.get_creatures(game_id, owner).unwrap()),
+            (guest, repo.get_creatures(game_id, guest).unwrap()),
+        ]).unwrap();
+        repo.save_battle(game_id, "battle", &battle).unwrap();
+        assert!(export_replay(&repo, game_id, &mut Vec::new()).is_err());
+
+        let player_id = battle.teams[battle.current].player_id;
+        battle.apply(battle.current, Decision::Concede).unwrap();
+        repo.append_ledger(game_id, Some(player_id), &GameEvent::DecisionMade { player_id, decision: Decision::Concede }).unwrap();
+        repo.save_battle(game_id, "finished", &battle).unwrap();
+
+        let mut out = Vec::new();
+        export_replay(&repo, game_id, &mut out).unwrap();
+        let replay: Replay = serde_json::from_slice(&out).unwrap();
+        assert_eq!(replay.decisions.len(), 1);
+        assert!(replay.verify().unwrap().is_finished());
+
+        let mut out = Vec::new();
+        inspect_game(&repo, game_id, true, &mut out).unwrap();
+        let inspection: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(inspection["events"].as_array().unwrap().len(), 1);
+    }
+}
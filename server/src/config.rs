@@ -0,0 +1,325 @@
+// src/config.rs
+//! Server settings. Each one comes from, in order of precedence: a command line flag, an environment
+//! variable, the config file, or its default. See README "# Configuration".
+use log::LevelFilter;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::catalog::CATALOG_PATH;
+
+/// Config file read when none is given and it exists.
+pub const CONFIG_PATH: &str = "battllm.toml";
+/// Prefix of the environment variables setting a key, e.g. `BATTLLM_PORT`.
+pub const ENV_PREFIX: &str = "BATTLLM_";
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ConfigError {
+    #[error("Failed to read {0}: {1}")]
+    Io(String, String),
+    #[error("Failed to parse {0}: {1}")]
+    Parse(String, String),
+    #[error("Unknown setting `{0}`")]
+    UnknownKey(String),
+    #[error("Invalid value `{value}` for `{key}`: {reason}")]
+    InvalidValue { key: String, value: String, reason: String },
+    #[error("Invalid config:\n{}", .0.join("\n"))]
+    Invalid(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address the server listens on
+    pub host: String,
+    pub port: u16,
+    /// Worker threads serving requests, one per core when unset
+    pub workers: Option<usize>,
+    /// SQLite file, or `postgres://` URL with the `postgres` feature
+    pub database_url: String,
+    /// Connections kept open to the database
+    pub pool_size: u32,
+    pub catalog_path: PathBuf,
+    /// Directory of the serialized embeddings
+    pub storage_dir: PathBuf,
+    pub log_dir: PathBuf,
+    /// `off`, `error`, `warn`, `info`, `debug` or `trace`
+    pub log_level: String,
+    /// Token of the admin endpoints, which are disabled without one
+    pub admin_token: Option<String>,
+    /// OpenAI-compatible API the LLM agents play with, up to the version, e.g. `http://localhost:11434/v1`
+    pub agent_base_url: String,
+    pub agent_model: String,
+    /// Times an agent asks again after a reply that isn't a legal decision
+    pub agent_retries: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            workers: None,
+            database_url: String::new(),
+            pool_size: 8,
+            catalog_path: PathBuf::from(CATALOG_PATH),
+            storage_dir: PathBuf::from("database"),
+            log_dir: PathBuf::from("logs"),
+            log_level: "info".to_string(),
+            admin_token: None,
+            agent_base_url: "https://api.openai.com/v1".to_string(),
+            agent_model: "gpt-4o-mini".to_string(),
+            agent_retries: 2,
+        }
+    }
+}
+
+/// Environment variables the server read before it had a config, still honored below `BATTLLM_*`.
+const LEGACY_ENV: &[(&str, &str)] = &[("DATABASE_URL", "database_url"), ("ADMIN_TOKEN", "admin_token")];
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, ConfigError>
+where
+    T::Err: std::fmt::Display,
+{
+    value.parse().map_err(|e: T::Err| ConfigError::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+impl Config {
+    /// Reads the config file at `path`. Keys missing from the file keep their default.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.display().to_string(), e.to_string()))?;
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path.display().to_string(), e.message().to_string()))
+    }
+
+    /// Sets a key by its name in the config file, e.g. `port`. Dashes count as underscores.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let key = key.replace('-', "_");
+        match key.as_str() {
+            "host" => self.host = value.to_string(),
+            "port" => self.port = parse(&key, value)?,
+            "workers" => self.workers = Some(parse(&key, value)?),
+            "database_url" => self.database_url = value.to_string(),
+            "pool_size" => self.pool_size = parse(&key, value)?,
+            "catalog_path" => self.catalog_path = PathBuf::from(value),
+            "storage_dir" => self.storage_dir = PathBuf::from(value),
+            "log_dir" => self.log_dir = PathBuf::from(value),
+            "log_level" => self.log_level = value.to_string(),
+            "admin_token" => self.admin_token = Some(value.to_string()).filter(|token| !token.is_empty()),
+            "agent_base_url" => self.agent_base_url = value.to_string(),
+            "agent_model" => self.agent_model = value.to_string(),
+            "agent_retries" => self.agent_retries = parse(&key, value)?,
+            _ => return Err(ConfigError::UnknownKey(key)),
+        }
+        Ok(())
+    }
+
+    /// Applies the `BATTLLM_*` variables of `vars`, and the legacy ones they don't override.
+    pub fn apply_env(&mut self, vars: impl IntoIterator<Item = (String, String)>) -> Result<(), ConfigError> {
+        let vars: Vec<(String, String)> = vars.into_iter().collect();
+        for (legacy, key) in LEGACY_ENV {
+            if let Some((_, value)) = vars.iter().find(|(name, _)| name == legacy) {
+                self.set(key, value)?;
+            }
+        }
+        for (name, value) in &vars {
+            if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+                // The config file is chosen before the rest is read
+                if key != "CONFIG" {
+                    self.set(&key.to_lowercase(), value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies `--key value` and `--key=value` flags, e.g. `--port 8081`. `--config` is skipped.
+    pub fn apply_args(&mut self, args: &[String]) -> Result<(), ConfigError> {
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let flag = arg.strip_prefix("--").ok_or_else(|| ConfigError::UnknownKey(arg.clone()))?;
+            let (key, value) = match flag.split_once('=') {
+                Some((key, value)) => (key.to_string(), value.to_string()),
+                None => {
+                    let value = args.next().ok_or_else(|| ConfigError::InvalidValue {
+                        key: flag.to_string(),
+                        value: String::new(),
+                        reason: "missing value".to_string(),
+                    })?;
+                    (flag.to_string(), value.clone())
+                }
+            };
+            if key != "config" {
+                self.set(&key, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the config from the file, `vars` and `args`, then validates it.
+    /// The file is the one given by `--config` or `BATTLLM_CONFIG`, else `battllm.toml` if it exists.
+    pub fn load(args: &[String], vars: impl IntoIterator<Item = (String, String)>) -> Result<Self, ConfigError> {
+        let vars: Vec<(String, String)> = vars.into_iter().collect();
+        let path = config_flag(args)?
+            .or_else(|| vars.iter().find(|(name, _)| *name == format!("{}CONFIG", ENV_PREFIX)).map(|(_, value)| value.clone()))
+            .map(PathBuf::from);
+
+        let mut config = match path {
+            Some(path) => Config::from_file(&path)?,
+            None if Path::new(CONFIG_PATH).exists() => Config::from_file(Path::new(CONFIG_PATH))?,
+            None => Config::default(),
+        };
+        config.apply_env(vars)?;
+        config.apply_args(args)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks every setting, listing all the problems at once.
+    /// `database_url` is checked by `database_url`, since not every command opens the database.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors = Vec::new();
+        if self.host.trim().is_empty() {
+            errors.push("host must not be empty".to_string());
+        }
+        if self.port == 0 {
+            errors.push("port must not be 0".to_string());
+        }
+        if self.workers == Some(0) {
+            errors.push("workers must be at least 1".to_string());
+        }
+        if self.pool_size == 0 {
+            errors.push("pool_size must be at least 1".to_string());
+        }
+        if !self.catalog_path.is_file() {
+            errors.push(format!("catalog_path {} is not a file", self.catalog_path.display()));
+        }
+        if self.storage_dir.exists() && !self.storage_dir.is_dir() {
+            errors.push(format!("storage_dir {} is not a directory", self.storage_dir.display()));
+        }
+        if self.log_dir.exists() && !self.log_dir.is_dir() {
+            errors.push(format!("log_dir {} is not a directory", self.log_dir.display()));
+        }
+        if !self.agent_base_url.starts_with("http://") && !self.agent_base_url.starts_with("https://") {
+            errors.push(format!("agent_base_url `{}` is not an http(s) URL", self.agent_base_url));
+        }
+        if LevelFilter::from_str(&self.log_level).is_err() {
+            errors.push(format!("log_level `{}` is not one of off, error, warn, info, debug, trace", self.log_level));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(errors))
+        }
+    }
+
+    /// The database URL, which must be set to open the database.
+    pub fn database_url(&self) -> Result<&str, ConfigError> {
+        match self.database_url.trim() {
+            "" => Err(ConfigError::Invalid(vec!["database_url must be set, e.g. with DATABASE_URL".to_string()])),
+            url => Ok(url),
+        }
+    }
+
+    /// Whether the database is PostgreSQL rather than a SQLite file.
+    pub fn is_postgres(&self) -> bool {
+        self.database_url.starts_with("postgres://") || self.database_url.starts_with("postgresql://")
+    }
+
+    pub fn log_level(&self) -> LevelFilter {
+        LevelFilter::from_str(&self.log_level).unwrap_or(LevelFilter::Info)
+    }
+}
+
+/// The value of `--config`, if given.
+fn config_flag(args: &[String]) -> Result<Option<String>, ConfigError> {
+    for (i, arg) in args.iter().enumerate() {
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Ok(Some(path.to_string()));
+        }
+        if arg == "--config" {
+            return args.get(i + 1).cloned().map(Some).ok_or_else(|| ConfigError::InvalidValue {
+                key: "config".to_string(),
+                value: String::new(),
+                reason: "missing value".to_string(),
+            });
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_precedence() {
+        let path = std::env::temp_dir().join(format!("battllm_config_{}.toml", std::process::id()));
+        std::fs::write(&path, "port = 9000\nhost = \"0.0.0.0\"\nlog_level = \"debug\"\ndatabase_url = \"file.db\"\n").unwrap();
+
+        let config = Config::load(
+            &args(&["--config", path.to_str().unwrap(), "--port=9002"]),
+            vars(&[("BATTLLM_PORT", "9001"), ("BATTLLM_LOG_LEVEL", "warn"), ("DATABASE_URL", "legacy.db")]),
+        );
+        std::fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        // Flags beat the environment, which beats the file, which beats the defaults
+        assert_eq!(config.port, 9002);
+        assert_eq!(config.log_level(), LevelFilter::Warn);
+        assert_eq!(config.host, "0.0.0.0");
+        assert_eq!(config.database_url, "legacy.db");
+        assert_eq!(config.pool_size, Config::default().pool_size);
+    }
+
+    #[test]
+    fn test_prefixed_env_beats_legacy() {
+        let mut config = Config::default();
+        config.apply_env(vars(&[("BATTLLM_DATABASE_URL", "new.db"), ("DATABASE_URL", "old.db"), ("HOME", "/root")])).unwrap();
+        assert_eq!(config.database_url, "new.db");
+    }
+
+    #[test]
+    fn test_rejects_unknown_and_malformed() {
+        let mut config = Config::default();
+        assert_eq!(config.apply_args(&args(&["--prot", "80"])), Err(ConfigError::UnknownKey("prot".to_string())));
+        assert!(matches!(config.apply_args(&args(&["--port", "eighty"])), Err(ConfigError::InvalidValue { .. })));
+        assert!(matches!(config.apply_args(&args(&["--port"])), Err(ConfigError::InvalidValue { .. })));
+        assert!(toml::from_str::<Config>("prot = 80").is_err());
+    }
+
+    #[test]
+    fn test_validate_lists_every_problem() {
+        let config = Config {
+            port: 0,
+            pool_size: 0,
+            log_level: "loud".to_string(),
+            catalog_path: PathBuf::from("missing.json"),
+            ..Config::default()
+        };
+        match config.validate() {
+            Err(ConfigError::Invalid(errors)) => assert_eq!(errors.len(), 4, "{:?}", errors),
+            other => panic!("expected errors, got {:?}", other),
+        }
+
+        // Only the commands opening the database need it
+        let config = Config::default();
+        assert_eq!(config.validate(), Ok(()));
+        assert!(config.database_url().is_err());
+        let config = Config { database_url: "battllm.db".to_string(), ..Config::default() };
+        assert_eq!(config.database_url(), Ok("battllm.db"));
+    }
+}
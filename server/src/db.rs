@@ -1,8 +1,18 @@
-use rusqlite::{Connection, Result};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, types::Type, Connection, OptionalExtension, Result, Row, Transaction, TransactionBehavior};
+use serde::{de::DeserializeOwned, Serialize};
 use thiserror::Error;
-use std::fs;
+use std::collections::HashMap;
 
-use crate::models::creature::Creature;
+use crate::battle::{BattleState, Decision};
+use crate::migrations;
+use crate::events::{EventEnvelope, GameEvent};
+use crate::models::ability::Ability;
+use crate::models::creature::{Attribute, Creature};
+use crate::models::game::{GamePhase, GameState, PlayerState};
+use crate::ratings::{self, GameResult, Rating, RatingKind};
+use crate::repository::{self, BattleUpdate, Seat};
+use crate::rules::RuleSet;
 
 
 #[derive(Error, Debug)]
@@ -11,129 +21,592 @@ pub enum DbError {
     DatabaseError(#[from] rusqlite::Error),
     #[error("Invalid game state")]
     InvalidGameState,
+    #[error("Not found")]
+    NotFound,
+    #[error("The game is full")]
+    GameFull,
+    #[error("Serialization error: {0}")]
+    SerializationError(#[from] serde_json::Error),
+    #[error("Connection pool error: {0}")]
+    PoolError(#[from] r2d2::Error),
+    #[error("Replay error: {0}")]
+    ReplayError(#[from] crate::replay::ReplayError),
+    #[cfg(feature = "postgres")]
+    #[error("PostgreSQL error: {0}")]
+    PostgresError(#[from] postgres::Error),
 }
 
-/// Initializes the database schema by reading the provided schema file.
-pub fn initialize_database(conn: &Connection, schema_file_path: &str) {
-    let schema = fs::read_to_string(schema_file_path)
-        .expect(&format!("Failed to read schema file: {}", schema_file_path));
+pub type Pool = r2d2::Pool<SqliteConnectionManager>;
+
+/// Opens a pool of `size` connections to the database file at `path`.
+/// WAL lets readers go on while a writer commits, and writers wait for each other instead of failing.
+pub fn open_pool(path: &str, size: u32) -> Result<Pool, r2d2::Error> {
+    let manager = SqliteConnectionManager::file(path).with_init(|conn| {
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             PRAGMA busy_timeout = 5000;
+             PRAGMA foreign_keys = ON;"
+        )
+    });
+    r2d2::Pool::builder().max_size(size).build(manager)
+}
 
-    conn.execute_batch(&schema)
-        .expect("Failed to initialize database schema.");
+/// Starts a transaction that takes the write lock up front. A deferred one that reads first can't
+/// wait for another writer in WAL mode and fails with `SQLITE_BUSY` instead.
+pub fn write_transaction(conn: &Connection) -> Result<Transaction<'_>> {
+    Transaction::new_unchecked(conn, TransactionBehavior::Immediate)
 }
 
-/// Creates a new game and returns its unique ID and an owner token.
-pub fn create_game(conn: &Connection, name: &str) -> Result<(i64, i64)> {
-    conn.execute(
-        "INSERT INTO Game (phase) VALUES (?1)",
-        &["setup"],
+/// Brings the database schema up to date and enables foreign keys on the connection.
+pub fn initialize_database(conn: &Connection) {
+    conn.pragma_update(None, "foreign_keys", true)
+        .expect("Failed to enable foreign keys.");
+    migrations::migrate(conn)
+        .expect("Failed to migrate database schema.");
+    migrate_creature_blobs(conn)
+        .expect("Failed to move creatures out of the Player table.");
+}
+
+/// Moves the creatures stored as JSON on `Player` to the `Creature` and `Ability` tables.
+/// Blobs that don't parse are left in place and logged. Returns how many creatures moved.
+pub fn migrate_creature_blobs(conn: &Connection) -> Result<usize> {
+    let mut stmt = conn.prepare(
+        "SELECT id, game_id, creatures FROM Player WHERE creatures IS NOT NULL AND creatures NOT IN ('', '[]')"
     )?;
-    let game_id = conn.last_insert_rowid();
-    
-    conn.execute(
-        "INSERT INTO Player (game_id, name) VALUES (?1, ?2)",
-        // Avoid converting `game_id` to `String`
-        &[&game_id as &dyn rusqlite::ToSql, &name as &dyn rusqlite::ToSql],
+    let blobs = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<Vec<(i64, i64, String)>>>()?;
+
+    let mut moved = 0;
+    for (player_id, game_id, blob) in blobs {
+        let creatures: Vec<Creature> = match serde_json::from_str(&blob) {
+            Ok(creatures) => creatures,
+            Err(e) => {
+                log::error!("Creatures of player {} don't parse, leaving them in place: {}", player_id, e);
+                continue;
+            }
+        };
+
+        let tx = conn.unchecked_transaction()?;
+        for creature in &creatures {
+            insert_creature(&tx, game_id, player_id, creature)?;
+        }
+        tx.execute("UPDATE Player SET creatures = '[]' WHERE id = ?1", [player_id])?;
+        tx.commit()?;
+        moved += creatures.len();
+    }
+    Ok(moved)
+}
+
+/// Creates a new game and returns its unique ID and the owner's seat.
+pub fn create_game(conn: &Connection, name: &str) -> Result<(i64, Seat)> {
+    create_game_with_rules(conn, name, &RuleSet::default(), "")
+}
+
+/// Seat of the player who created the game.
+pub const OWNER_SEAT: i64 = 0;
+/// Seat of the player who joined the game. A game has no other seats.
+pub const GUEST_SEAT: i64 = 1;
+
+/// Creates a new game played with `rules`, and the catalog of `catalog_version`.
+pub fn create_game_with_rules(conn: &Connection, name: &str, rules: &RuleSet, catalog_version: &str) -> Result<(i64, Seat)> {
+    // Seed of every random roll in the battle
+    let seed: i64 = rand::random();
+    let rules = serde_json::to_string(rules).expect("rules always serialize");
+
+    // A game is never left without its owner
+    let tx = write_transaction(conn)?;
+    tx.execute(
+        "INSERT INTO Game (phase, seed, rules, catalog_version) VALUES (?1, ?2, ?3, ?4)",
+        params!["setup", seed, rules, catalog_version],
     )?;
-    let owner_token = conn.last_insert_rowid();
+    let game_id = tx.last_insert_rowid();
+    let token = repository::new_token();
+    tx.execute(
+        "INSERT INTO Player (game_id, name, seat, token) VALUES (?1, ?2, ?3, ?4)",
+        params![game_id, name, OWNER_SEAT, token],
+    )?;
+    let owner = Seat { id: tx.last_insert_rowid(), token };
+    tx.commit()?;
 
-    Ok((game_id, owner_token))
+    Ok((game_id, owner))
 }
 
 /// Allows a player to join a game if there's exactly one player already in it. \
-/// Returns a unique player token for the joining player.
-pub fn join_game(conn: &Connection, game_id: i64, name: &str) -> Result<i64> {
-    // Fetch the number of Player in the game
-    let mut stmt = conn.prepare("SELECT COUNT(*) FROM Player WHERE game_id = ?1")?;
-    let player_count: i64 = stmt.query_row(&[&game_id], |row| row.get(0))?;
+/// Returns the seat of the joining player.
+pub fn join_game(conn: &Connection, game_id: i64, name: &str) -> Result<Seat, DbError> {
+    // Joins take the write lock in turn, so each one sees the players of the previous
+    let tx = write_transaction(conn)?;
+    // An unknown game has no players either, tell both apart
+    tx.query_row("SELECT id FROM Game WHERE id = ?1", [game_id], |row| row.get::<_, i64>(0))
+        .optional()?
+        .ok_or(DbError::NotFound)?;
+    let player_count: i64 = tx.query_row("SELECT COUNT(*) FROM Player WHERE game_id = ?1", [game_id], |row| row.get(0))?;
 
     // Ensure there is exactly one player currently in the game
     if player_count != 1 {
-        return Err(rusqlite::Error::InvalidQuery);
+        return Err(DbError::GameFull);
     }
 
-    // Insert the new player into the Player table
-    conn.execute(
-        "INSERT INTO Player (game_id, name) VALUES (?1, ?2)",
-        &[&game_id as &dyn rusqlite::ToSql, &name as &dyn rusqlite::ToSql],
-    )?;
+    // The seat is unique, should another joiner get past the count anyway
+    let token = repository::new_token();
+    let inserted = tx.execute(
+        "INSERT INTO Player (game_id, name, seat, token) VALUES (?1, ?2, ?3, ?4)",
+        params![game_id, name, GUEST_SEAT, token],
+    );
+    match inserted {
+        Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == rusqlite::ErrorCode::ConstraintViolation => {
+            return Err(DbError::GameFull);
+        }
+        inserted => inserted?,
+    };
 
     // Retrieve the ID of the newly added player
-    let player_token = conn.last_insert_rowid();
+    let seat = Seat { id: tx.last_insert_rowid(), token };
+    touch_game(&tx, game_id)?;
+    tx.commit()?;
 
-    Ok(player_token)
+    Ok(seat)
 }
 
-/// Polls the game state to check if it has changed since a given timestamp.
-pub fn poll_game_state(conn: &Connection, game_id: i64, timestamp: i64) -> Result<bool> {
-    let exists: bool = conn.query_row(
-        "SELECT EXISTS(SELECT 1 FROM Game WHERE id = ?1 AND timestamp > ?2)",
-        &[&game_id, &timestamp],
+/// Polls the game state to check if it has changed since a given version.
+pub fn poll_game_state(conn: &Connection, game_id: i64, version: i64) -> Result<bool> {
+    let changed: bool = conn.query_row(
+        "SELECT version > ?2 FROM Game WHERE id = ?1",
+        [game_id, version],
         |row| row.get(0),
     )?;
-    Ok(exists)
+    Ok(changed)
 }
 
-/// This is temporary
-pub fn create_creature(conn: &Connection, game_id: i64, user_id: i64, creature: &Creature) -> Result<i64> {
-    // Fetch the current creatures JSON string for the player
-    let current_creatures: String = conn.query_row(
-        "SELECT creatures FROM Player WHERE game_id = ?1 AND id = ?2",
-        &[&game_id, &user_id],
-        |row| row.get(0),
+/// Marks the game as changed, bumping its version and timestamp.
+fn touch_game(conn: &Connection, game_id: i64) -> Result<()> {
+    conn.execute(
+        "UPDATE Game SET version = version + 1, timestamp = CURRENT_TIMESTAMP WHERE id = ?1",
+        [game_id],
     )?;
+    Ok(())
+}
+
+/// Returns the whole state of the game.
+pub fn get_game_state(conn: &Connection, game_id: i64) -> Result<GameState, DbError> {
+    let (phase, version, rules): (String, i64, String) = conn.query_row(
+        "SELECT phase, version, rules FROM Game WHERE id = ?1",
+        [game_id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    let rules: RuleSet = serde_json::from_str(&rules)?;
+    let ready = ready_players(conn, game_id)?;
+    let players: Vec<PlayerState> = get_players(conn, game_id)?
+        .into_iter()
+        .map(|(id, name)| PlayerState { id, name, ready: ready.contains(&id) })
+        .collect();
+    let mut entities = Vec::new();
+    for player in &players {
+        entities.extend(get_creatures(conn, game_id, player.id)?);
+    }
+    let battle = load_battle(conn, game_id)?;
 
-    // Deserialize the current creatures into a Vec<Creature>
-    let mut creatures: Vec<Creature> = serde_json::from_str(&current_creatures).unwrap_or_else(|_| vec![]);
+    let phase = game_phase(&phase, players.len());
+    let current_turn = current_turn(battle.as_ref());
 
-    // Add the new creature
-    creatures.push(creature.clone());
+    Ok(GameState { game_id, version, phase, current_turn, players, entities, battle, rules })
+}
+
+/// The phase of a game from the phase it is stored with and its number of players.
+pub(crate) fn game_phase(stored: &str, players: usize) -> GamePhase {
+    match stored {
+        "battle" => GamePhase::Battle,
+        "finish" => GamePhase::Finish,
+        _ if players < 2 => GamePhase::Waiting,
+        _ => GamePhase::Creation,
+    }
+}
+
+/// The player whose decision the battle waits for, if any.
+pub(crate) fn current_turn(battle: Option<&BattleState>) -> Option<i64> {
+    battle
+        .filter(|battle| !battle.is_finished())
+        .map(|battle| battle.teams[battle.current].player_id)
+}
 
-    // Serialize the updated creatures back into a JSON string
-    let updated_creatures = serde_json::to_string(&creatures).unwrap();
+/// Attributes in the order of their columns in the `Creature` table.
+pub(crate) const ATTRIBUTE_COLUMNS: [Attribute; 5] = [
+    Attribute::Strength,
+    Attribute::Defense,
+    Attribute::Perception,
+    Attribute::Intelligence,
+    Attribute::Wisdom,
+];
+
+pub(crate) fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("model values always serialize")
+}
 
-    // Update the Player table with the new creatures JSON
+/// Reads a column holding JSON, failing on malformed data rather than guessing.
+fn json_column<T: DeserializeOwned>(row: &Row, index: usize) -> Result<T> {
+    let text: String = row.get(index)?;
+    serde_json::from_str(&text).map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e)))
+}
+
+/// Inserts a creature with its abilities. Run inside a transaction.
+fn insert_creature(conn: &Connection, game_id: i64, player_id: i64, creature: &Creature) -> Result<i64> {
+    let attributes: Vec<Option<u8>> = ATTRIBUTE_COLUMNS.iter().map(|attribute| creature.attributes.get(attribute).copied()).collect();
     conn.execute(
-        "UPDATE Player SET creatures = ?1 WHERE game_id = ?2 AND id = ?3",
-        &[&updated_creatures, &game_id as &dyn rusqlite::ToSql, &user_id as &dyn rusqlite::ToSql],
+        "INSERT INTO Creature (game_id, player_id, name, description, image, max_health, \
+         strength, defense, perception, intelligence, wisdom, elements) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        params![
+            game_id,
+            player_id,
+            creature.name,
+            creature.description,
+            creature.image,
+            creature.max_health,
+            attributes[0],
+            attributes[1],
+            attributes[2],
+            attributes[3],
+            attributes[4],
+            to_json(&creature.elements),
+        ],
     )?;
-
     let creature_id = conn.last_insert_rowid();
 
+    for (position, ability) in creature.abilities.iter().enumerate() {
+        conn.execute(
+            "INSERT INTO Ability (creature_id, position, name, description, base_damage, available, max_available, \
+             elements, modifiers, category, target) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                creature_id,
+                position,
+                ability.name,
+                ability.description,
+                ability.base_damage,
+                ability.available.0,
+                ability.available.1,
+                to_json(&ability.elements),
+                to_json(&ability.modifiers),
+                to_json(&ability.category),
+                to_json(&ability.target),
+            ],
+        )?;
+    }
+
+    Ok(creature_id)
+}
+
+/// Stores a creature of `user_id` and returns its id.
+pub fn create_creature(conn: &Connection, game_id: i64, user_id: i64, creature: &Creature) -> Result<i64> {
+    let tx = write_transaction(conn)?;
+    if !is_player(&tx, game_id, user_id)? {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    let creature_id = insert_creature(&tx, game_id, user_id, creature)?;
+    touch_game(&tx, game_id)?;
+    tx.commit()?;
+
     Ok(creature_id)
 }
 
+/// Returns the creatures of `user_id`, in the order they were created.
 pub fn get_creatures(conn: &Connection, game_id: i64, user_id: i64) -> Result<Vec<Creature>> {
-    let creatures: String = conn.query_row(
-        "SELECT creatures FROM Player WHERE game_id = ?1 AND id = ?2",
-        &[&game_id, &user_id],
-        |row| row.get(0),
+    let mut stmt = conn.prepare(
+        "SELECT id, player_id, name, description, image, max_health, \
+         strength, defense, perception, intelligence, wisdom, elements \
+         FROM Creature WHERE game_id = ?1 AND player_id = ?2 ORDER BY id"
+    )?;
+    let mut creatures = stmt
+        .query_map([game_id, user_id], |row| {
+            let mut attributes = HashMap::new();
+            for (offset, attribute) in ATTRIBUTE_COLUMNS.iter().enumerate() {
+                if let Some(value) = row.get::<_, Option<u8>>(6 + offset)? {
+                    attributes.insert(attribute.clone(), value);
+                }
+            }
+            Ok(Creature {
+                id: row.get(0)?,
+                owner: row.get(1)?,
+                name: row.get(2)?,
+                description: row.get(3)?,
+                image: row.get(4)?,
+                max_health: row.get(5)?,
+                attributes,
+                elements: json_column(row, 11)?,
+                abilities: Vec::new(),
+            })
+        })?
+        .collect::<Result<Vec<Creature>>>()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT name, description, base_damage, available, max_available, elements, modifiers, category, target \
+         FROM Ability WHERE creature_id = ?1 ORDER BY position"
     )?;
-    let creatures: Vec<Creature> = serde_json::from_str(&creatures).unwrap_or_else(|_| vec![]);
+    for creature in &mut creatures {
+        creature.abilities = stmt
+            .query_map([creature.id], |row| {
+                Ok(Ability {
+                    name: row.get(0)?,
+                    description: row.get(1)?,
+                    base_damage: row.get(2)?,
+                    available: (row.get(3)?, row.get(4)?),
+                    elements: json_column(row, 5)?,
+                    modifiers: json_column(row, 6)?,
+                    category: json_column(row, 7)?,
+                    target: json_column(row, 8)?,
+                })
+            })?
+            .collect::<Result<Vec<Ability>>>()?;
+    }
     Ok(creatures)
 }
 
+/// Returns the `(id, name)` of every player in the game, owner first.
+pub fn get_players(conn: &Connection, game_id: i64) -> Result<Vec<(i64, String)>> {
+    let mut stmt = conn.prepare("SELECT id, name FROM Player WHERE game_id = ?1 ORDER BY id")?;
+    let players = stmt
+        .query_map([game_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(i64, String)>>>()?;
+    Ok(players)
+}
+
+/// Whether `player_id` plays in the game.
+pub fn is_player(conn: &Connection, game_id: i64, player_id: i64) -> Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM Player WHERE game_id = ?1 AND id = ?2)",
+        [game_id, player_id],
+        |row| row.get(0),
+    )
+}
+
+/// Returns the id of the player of `token` in the game.
+pub fn authenticate(conn: &Connection, game_id: i64, token: &str) -> Result<Option<i64>> {
+    conn.query_row(
+        "SELECT id FROM Player WHERE game_id = ?1 AND token = ?2",
+        params![game_id, token],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Returns the seed the game's battle is played with.
+pub fn get_seed(conn: &Connection, game_id: i64) -> Result<u64> {
+    let seed: i64 = conn.query_row("SELECT seed FROM Game WHERE id = ?1", [game_id], |row| row.get(0))?;
+    Ok(seed as u64)
+}
+
+/// Returns the version of the catalog the game is played with.
+pub fn get_catalog_version(conn: &Connection, game_id: i64) -> Result<String> {
+    conn.query_row("SELECT catalog_version FROM Game WHERE id = ?1", [game_id], |row| row.get(0))
+}
+
+/// Stores the battle along with the phase of the game.
+pub fn save_battle(conn: &Connection, game_id: i64, phase: &str, battle: &BattleState) -> Result<(), DbError> {
+    let battle = serde_json::to_string(battle)?;
+    conn.execute(
+        "UPDATE Game SET phase = ?1, battle = ?2 WHERE id = ?3",
+        params![phase, battle, game_id],
+    )?;
+    touch_game(conn, game_id)?;
+    Ok(())
+}
+
+/// Returns the battle of the game, if it has started.
+pub fn load_battle(conn: &Connection, game_id: i64) -> Result<Option<BattleState>, DbError> {
+    let battle: Option<String> = conn.query_row("SELECT battle FROM Game WHERE id = ?1", [game_id], |row| row.get(0))?;
+    match battle {
+        Some(battle) => Ok(Some(serde_json::from_str(&battle)?)),
+        None => Ok(None),
+    }
+}
+
+/// Loads the battle, lets `update` change it, then stores it and appends its events in one write transaction.
+pub fn update_battle(
+    conn: &Connection,
+    game_id: i64,
+    update: &mut dyn FnMut(Option<BattleState>) -> Option<BattleUpdate>,
+) -> Result<Vec<EventEnvelope>, DbError> {
+    // Taking the write lock before reading makes concurrent updates wait for each other
+    let tx = write_transaction(conn)?;
+    let BattleUpdate { phase, battle, events } = match update(load_battle(&tx, game_id)?) {
+        Some(update) => update,
+        None => return Ok(Vec::new()),
+    };
+    save_battle(&tx, game_id, phase, &battle)?;
+    let appended = events
+        .iter()
+        .map(|(player_id, event)| append_event(&tx, game_id, *player_id, event))
+        .collect::<Result<Vec<_>, DbError>>()?;
+    tx.commit()?;
+    Ok(appended)
+}
+
+/// Appends an event to the game's ledger and returns it with its sequence number.
+pub fn append_event(conn: &Connection, game_id: i64, player_id: Option<i64>, event: &GameEvent) -> Result<EventEnvelope, DbError> {
+    let payload = serde_json::to_string(event)?;
+    conn.execute(
+        "INSERT INTO Ledger (game_id, player_id, command, payload) VALUES (?1, ?2, ?3, ?4)",
+        params![game_id, player_id, event.kind(), payload],
+    )?;
+    let id = conn.last_insert_rowid();
+    let timestamp: String = conn.query_row("SELECT timestamp FROM Ledger WHERE id = ?1", [id], |row| row.get(0))?;
+    touch_game(conn, game_id)?;
+
+    Ok(EventEnvelope { id, game_id, timestamp, event: event.clone() })
+}
+
+/// Returns every event of the game recorded after the event `after`, oldest first.
+pub fn events_since(conn: &Connection, game_id: i64, after: i64) -> Result<Vec<EventEnvelope>, DbError> {
+    let mut stmt = conn.prepare(
+        "SELECT id, timestamp, payload FROM Ledger WHERE game_id = ?1 AND id > ?2 ORDER BY id"
+    )?;
+    let rows = stmt
+        .query_map([game_id, after], |row| Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?)))?
+        .collect::<Result<Vec<(i64, String, String)>>>()?;
+
+    rows.into_iter()
+        .map(|(id, timestamp, payload)| {
+            Ok(EventEnvelope { id, game_id, timestamp, event: serde_json::from_str(&payload)? })
+        })
+        .collect()
+}
+
+/// Returns the last `limit` events of the game, oldest first.
+pub fn recent_events(conn: &Connection, game_id: i64, limit: i64) -> Result<Vec<EventEnvelope>, DbError> {
+    let after: i64 = conn.query_row(
+        "SELECT COALESCE(MIN(id) - 1, 0) FROM (SELECT id FROM Ledger WHERE game_id = ?1 ORDER BY id DESC LIMIT ?2)",
+        [game_id, limit],
+        |row| row.get(0),
+    )?;
+    events_since(conn, game_id, after)
+}
+
+/// Returns every `(player_id, decision)` made in the game's battle, in order.
+pub fn get_decisions(conn: &Connection, game_id: i64) -> Result<Vec<(i64, Decision)>, DbError> {
+    let mut stmt = conn.prepare(
+        "SELECT payload FROM Ledger WHERE game_id = ?1 AND command = 'decision_made' ORDER BY id"
+    )?;
+    let payloads = stmt.query_map([game_id], |row| row.get::<_, String>(0))?.collect::<Result<Vec<String>>>()?;
+
+    payloads
+        .iter()
+        .map(|payload| match serde_json::from_str(payload)? {
+            GameEvent::DecisionMade { player_id, decision } => Ok((player_id, decision)),
+            _ => Err(DbError::InvalidGameState),
+        })
+        .collect()
+}
+
+/// Returns the players who declared themselves ready to battle.
+pub fn ready_players(conn: &Connection, game_id: i64) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT player_id FROM Ledger WHERE game_id = ?1 AND command = 'player_ready' ORDER BY player_id"
+    )?;
+    let players = stmt.query_map([game_id], |row| row.get(0))?.collect::<Result<Vec<i64>>>()?;
+    Ok(players)
+}
+
+/// Returns the ids of the finished games, in the order they finished.
+pub fn finished_games(conn: &Connection) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare(
+        "SELECT game_id FROM Ledger WHERE command = 'game_over' GROUP BY game_id ORDER BY MIN(id)"
+    )?;
+    let games = stmt.query_map([], |row| row.get(0))?.collect::<Result<Vec<i64>>>()?;
+    Ok(games)
+}
+
+fn rating_from_row(kind: RatingKind, name: String, row: &Row) -> Result<Rating> {
+    Ok(Rating { kind, name, rating: row.get(0)?, deviation: row.get(1)?, games: row.get(2)?, wins: row.get(3)? })
+}
+
+/// Counts the game in the ratings, unless it already was. Returns whether it was counted now.
+pub fn rate_game(conn: &Connection, result: &GameResult) -> Result<bool, DbError> {
+    let tx = write_transaction(conn)?;
+    if tx.execute("INSERT OR IGNORE INTO RatedGame (game_id) VALUES (?1)", [result.game_id])? == 0 {
+        return Ok(false);
+    }
+
+    let mut current = Vec::new();
+    for (kind, name) in result.subjects() {
+        let rating = tx.query_row(
+            "SELECT rating, deviation, games, wins FROM Rating WHERE kind = ?1 AND name = ?2",
+            params![kind.as_str(), name],
+            |row| rating_from_row(kind, name.clone(), row),
+        ).optional()?;
+        current.extend(rating);
+    }
+    for rating in ratings::rate(result, &current) {
+        tx.execute(
+            "INSERT INTO Rating (kind, name, rating, deviation, games, wins) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT (kind, name) DO UPDATE SET rating = excluded.rating, deviation = excluded.deviation,
+             games = excluded.games, wins = excluded.wins, updated_at = CURRENT_TIMESTAMP",
+            params![rating.kind.as_str(), rating.name, rating.rating, rating.deviation, rating.games, rating.wins],
+        )?;
+    }
+    tx.commit()?;
+    Ok(true)
+}
+
+/// Returns the `limit` best ratings of `kind`, best first.
+pub fn leaderboard(conn: &Connection, kind: RatingKind, limit: i64) -> Result<Vec<Rating>> {
+    let mut stmt = conn.prepare(
+        "SELECT rating, deviation, games, wins, name FROM Rating WHERE kind = ?1 ORDER BY rating DESC, name LIMIT ?2"
+    )?;
+    let ratings = stmt
+        .query_map(params![kind.as_str(), limit], |row| rating_from_row(kind, row.get(4)?, row))?
+        .collect::<Result<Vec<Rating>>>()?;
+    Ok(ratings)
+}
+
+/// Forgets every rating and which games were counted.
+pub fn clear_ratings(conn: &Connection) -> Result<()> {
+    let tx = write_transaction(conn)?;
+    tx.execute_batch("DELETE FROM Rating; DELETE FROM RatedGame;")?;
+    tx.commit()
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use rusqlite::Connection;
-    use std::env;
-    use dotenv::dotenv;
+    use std::sync::{Arc, Barrier};
 
     fn setup_test_db() -> Connection {
         let conn = Connection::open_in_memory().expect("Failed to open in-memory database");
-        initialize_database(&conn, "database/schema.sql");
+        initialize_database(&conn);
         conn
     }
 
+    /// A pooled database in a file, so connections really run side by side. Removed on drop.
+    pub(crate) struct TempDatabase {
+        path: std::path::PathBuf,
+        pub(crate) pool: Pool,
+    }
+
+    impl TempDatabase {
+        pub(crate) fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("battllm_{}_{}.db", name, std::process::id()));
+            let pool = open_pool(path.to_str().unwrap(), 8).unwrap();
+            initialize_database(&pool.get().unwrap());
+            TempDatabase { path, pool }
+        }
+    }
+
+    impl Drop for TempDatabase {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", self.path.display(), suffix));
+            }
+        }
+    }
+
     #[test]
     fn test_initialize_database() {
         let conn = Connection::open_in_memory().expect("Failed to open in-memory database");
-        
-        dotenv().ok();
-        let schema_file_path = env::var("SCHEMA_FILE_PATH").expect("SCHEMA_FILE_PATH must be set in .env");
 
-        initialize_database(&conn, &schema_file_path);
+        initialize_database(&conn);
 
         // Verify that the Game and Player tables exist
         let game_table_exists: bool = conn
@@ -199,9 +672,10 @@ mod tests {
         let result = join_game(&conn, game_id, "test2");
         assert!(result.is_ok());
 
-        let new_player_token = result.unwrap();
+        let new_player = result.unwrap();
 
-        println!("new_player_token: {}", new_player_token);
+        println!("new_player: {}", new_player.id);
+        assert_eq!(authenticate(&conn, game_id, &new_player.token).unwrap(), Some(new_player.id));
 
         // Verify the new player was added
         let new_player_exists: bool = conn
@@ -226,18 +700,231 @@ mod tests {
         join_game(&conn, game_id, "test").unwrap();
         let result = join_game(&conn, game_id, "test");
 
-        assert!(result.is_err());
+        assert!(matches!(result, Err(DbError::GameFull)));
+        assert!(matches!(join_game(&conn, game_id + 1000, "test"), Err(DbError::NotFound)));
+    }
+
+    #[test]
+    fn test_concurrent_joins_seat_one_player() {
+        const JOINERS: usize = 8;
+        let database = TempDatabase::new("joins");
+
+        for _ in 0..10 {
+            let (game_id, _) = create_game(&database.pool.get().unwrap(), "owner").unwrap();
+            let barrier = Arc::new(Barrier::new(JOINERS));
+            let results: Vec<Result<Seat, DbError>> = std::thread::scope(|scope| {
+                let joiners: Vec<_> = (0..JOINERS)
+                    .map(|i| {
+                        let (pool, barrier) = (database.pool.clone(), Arc::clone(&barrier));
+                        scope.spawn(move || {
+                            let conn = pool.get().unwrap();
+                            barrier.wait();
+                            join_game(&conn, game_id, &format!("joiner {}", i))
+                        })
+                    })
+                    .collect();
+                joiners.into_iter().map(|joiner| joiner.join().unwrap()).collect()
+            });
+
+            // The others are turned away as the game is full, not because the database was busy
+            assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+            assert!(results.iter().all(|result| matches!(result, Ok(_) | Err(DbError::GameFull))));
+            assert_eq!(get_players(&database.pool.get().unwrap(), game_id).unwrap().len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_seat_is_unique() {
+        let conn = setup_test_db();
+        let (game_id, _) = create_game(&conn, "owner").unwrap();
+        join_game(&conn, game_id, "guest").unwrap();
+
+        // Even without the player count check, a second guest can't sit down
+        let result = conn.execute("INSERT INTO Player (game_id, name, seat) VALUES (?1, 'third', ?2)", params![game_id, GUEST_SEAT]);
+        assert!(matches!(result, Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == rusqlite::ErrorCode::ConstraintViolation));
+    }
+
+    #[test]
+    fn test_create_game_is_atomic() {
+        let conn = setup_test_db();
+        conn.execute_batch(
+            "CREATE TRIGGER no_players BEFORE INSERT ON Player BEGIN SELECT RAISE(ABORT, 'no players'); END;"
+        ).unwrap();
+
+        assert!(create_game(&conn, "owner").is_err());
+        let games: i64 = conn.query_row("SELECT COUNT(*) FROM Game", [], |row| row.get(0)).unwrap();
+        assert_eq!(games, 0);
     }
 
     #[test]
     fn test_poll_game_state() {
         let conn = setup_test_db();
 
-        let (game_id, _owner_token) = create_game(&conn, "test").unwrap();
+        let (game_id, _owner) = create_game(&conn, "test").unwrap();
 
         // Check initial state (should not have been updated)
         let result = poll_game_state(&conn, game_id, 0);
         assert!(result.is_ok());
         assert!(result.unwrap());
     }
+
+    #[test]
+    fn test_every_change_bumps_version() {
+        let conn = setup_test_db();
+        let (game_id, Seat { id: owner, .. }) = create_game(&conn, "test").unwrap();
+        let version = get_game_state(&conn, game_id).unwrap().version;
+        assert!(!poll_game_state(&conn, game_id, version).unwrap());
+
+        let other = join_game(&conn, game_id, "test2").unwrap().id;
+        assert!(poll_game_state(&conn, game_id, version).unwrap());
+
+        let version = get_game_state(&conn, game_id).unwrap().version;
+        append_event(&conn, game_id, Some(other), &GameEvent::PlayerReady { player_id: other }).unwrap();
+        assert!(poll_game_state(&conn, game_id, version).unwrap());
+
+        let state = get_game_state(&conn, game_id).unwrap();
+        assert_eq!(state.phase, GamePhase::Creation);
+        assert!(!state.players[0].ready);
+        assert!(state.players[1].ready);
+        assert_eq!(state.players[0].id, owner);
+
+
+        // Unknown games are an error rather than "unchanged"
+        assert!(poll_game_state(&conn, game_id + 1, 0).is_err());
+    }
+
+    #[test]
+    fn test_create_game_with_rules() {
+        let conn = setup_test_db();
+        let (game_id, _) = create_game(&conn, "test").unwrap();
+        assert_eq!(get_game_state(&conn, game_id).unwrap().rules, RuleSet::default());
+
+        let rules = RuleSet { fog_of_war: crate::rules::FogOfWar::none() };
+        let (game_id, _) = create_game_with_rules(&conn, "open", &rules, "abc").unwrap();
+        assert_eq!(get_game_state(&conn, game_id).unwrap().rules, rules);
+        assert_eq!(get_catalog_version(&conn, game_id).unwrap(), "abc");
+    }
+
+    #[test]
+    fn test_save_and_load_battle() {
+        use crate::battle::tests::creature;
+        use crate::models::creature::Element;
+
+        let conn = setup_test_db();
+        let (game_id, Seat { id: owner, .. }) = create_game(&conn, "test").unwrap();
+        let other = join_game(&conn, game_id, "test2").unwrap().id;
+        assert!(load_battle(&conn, game_id).unwrap().is_none());
+
+        let seed = get_seed(&conn, game_id).unwrap();
+        let battle = BattleState::new(seed, vec![
+            (owner, vec![creature(owner, "Ember", vec![Element::Fire], 5)]),
+            (other, vec![creature(other, "Drop", vec![Element::Water], 5)]),
+        ]).unwrap();
+        save_battle(&conn, game_id, "battle", &battle).unwrap();
+
+        let loaded = load_battle(&conn, game_id).unwrap().unwrap();
+        assert_eq!(loaded.seed, seed);
+        assert_eq!(loaded.teams[1].player_id, other);
+        assert_eq!(get_players(&conn, game_id).unwrap(), vec![(owner, "test".to_string()), (other, "test2".to_string())]);
+        assert!(is_player(&conn, game_id, other).unwrap());
+        assert!(!is_player(&conn, game_id + 1, other).unwrap());
+        assert_eq!(authenticate(&conn, game_id, &other.to_string()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_ready_players() {
+        let conn = setup_test_db();
+        let (game_id, Seat { id: owner, .. }) = create_game(&conn, "test").unwrap();
+        let other = join_game(&conn, game_id, "test2").unwrap().id;
+
+        append_event(&conn, game_id, Some(owner), &GameEvent::PlayerReady { player_id: owner }).unwrap();
+        append_event(&conn, game_id, Some(owner), &GameEvent::PlayerReady { player_id: owner }).unwrap();
+        assert_eq!(ready_players(&conn, game_id).unwrap(), vec![owner]);
+
+        append_event(&conn, game_id, Some(other), &GameEvent::PlayerReady { player_id: other }).unwrap();
+        assert_eq!(ready_players(&conn, game_id).unwrap(), vec![owner, other]);
+
+        let decision = GameEvent::DecisionMade { player_id: other, decision: Decision::Wait };
+        append_event(&conn, game_id, Some(other), &decision).unwrap();
+        assert_eq!(get_decisions(&conn, game_id).unwrap(), vec![(other, Decision::Wait)]);
+
+        let recent = recent_events(&conn, game_id, 2).unwrap();
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].event, GameEvent::PlayerReady { player_id: other });
+        assert_eq!(recent[1].event, decision);
+    }
+
+    #[test]
+    fn test_create_and_get_creatures() {
+        use crate::battle::tests::creature;
+        use crate::models::creature::Element;
+
+        let conn = setup_test_db();
+        let (game_id, Seat { id: owner, .. }) = create_game(&conn, "test").unwrap();
+        let other = join_game(&conn, game_id, "test2").unwrap().id;
+
+        let mut ember = creature(owner, "Ember", vec![Element::Fire], 6);
+        ember.attributes.remove(&Attribute::Wisdom);
+        let first = create_creature(&conn, game_id, owner, &ember).unwrap();
+        let second = create_creature(&conn, game_id, owner, &creature(owner, "Pebble", vec![Element::Earth], 4)).unwrap();
+        create_creature(&conn, game_id, other, &creature(other, "Drop", vec![Element::Water], 4)).unwrap();
+        assert!(second > first);
+
+        let creatures = get_creatures(&conn, game_id, owner).unwrap();
+        assert_eq!(creatures.iter().map(|c| c.id).collect::<Vec<_>>(), vec![first, second]);
+        let stored = &creatures[0];
+        assert_eq!(stored.name, "Ember");
+        assert_eq!(stored.owner, owner);
+        assert_eq!(stored.attributes, ember.attributes);
+        assert_eq!(stored.elements, ember.elements);
+        assert_eq!(
+            serde_json::to_value(&stored.abilities).unwrap(),
+            serde_json::to_value(&ember.abilities).unwrap()
+        );
+
+        // Players of other games can't add creatures here
+        let (other_game, Seat { id: stranger, .. }) = create_game(&conn, "other").unwrap();
+        assert!(create_creature(&conn, game_id, stranger, &ember).is_err());
+        assert!(get_creatures(&conn, other_game, stranger).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_migrate_creature_blobs() {
+        use crate::battle::tests::creature;
+        use crate::models::creature::Element;
+
+        let conn = setup_test_db();
+        let (game_id, Seat { id: owner, .. }) = create_game(&conn, "test").unwrap();
+        let other = join_game(&conn, game_id, "test2").unwrap().id;
+
+        let blob = serde_json::to_string(&vec![
+            creature(owner, "Ember", vec![Element::Fire], 6),
+            creature(owner, "Pebble", vec![Element::Earth], 4),
+        ]).unwrap();
+        conn.execute("UPDATE Player SET creatures = ?1 WHERE id = ?2", params![blob, owner]).unwrap();
+        conn.execute("UPDATE Player SET creatures = 'not json' WHERE id = ?1", [other]).unwrap();
+
+        assert_eq!(migrate_creature_blobs(&conn).unwrap(), 2);
+        let names: Vec<String> = get_creatures(&conn, game_id, owner).unwrap().into_iter().map(|c| c.name).collect();
+        assert_eq!(names, vec!["Ember", "Pebble"]);
+
+        // Migrated blobs are emptied, corrupt ones kept for inspection
+        let blob = |player: i64| conn.query_row("SELECT creatures FROM Player WHERE id = ?1", [player], |row| row.get::<_, String>(0)).unwrap();
+        assert_eq!(blob(owner), "[]");
+        assert_eq!(blob(other), "not json");
+        assert_eq!(migrate_creature_blobs(&conn).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_corrupt_creature_is_an_error() {
+        use crate::battle::tests::creature;
+        use crate::models::creature::Element;
+
+        let conn = setup_test_db();
+        let (game_id, Seat { id: owner, .. }) = create_game(&conn, "test").unwrap();
+        create_creature(&conn, game_id, owner, &creature(owner, "Ember", vec![Element::Fire], 6)).unwrap();
+        conn.execute("UPDATE Creature SET elements = 'oops'", []).unwrap();
+
+        assert!(get_creatures(&conn, game_id, owner).is_err());
+    }
 }
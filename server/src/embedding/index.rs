@@ -0,0 +1,394 @@
+// src/embedding/index.rs
+use ndarray::Array1;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+/// A similarity score paired with the position of the vector it belongs to.
+/// Ordered by score first so it can be used in a `BinaryHeap`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Scored {
+    score: f32,
+    id: usize,
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then_with(|| other.id.cmp(&self.id))
+    }
+}
+
+/// Returns the `k` most similar vectors to `query` as `(position, score)` pairs, best first.
+/// Scans every vector but only keeps a heap of size `k` instead of sorting everything.
+pub fn brute_force(vectors: &[Array1<f32>], query: &Array1<f32>, k: usize) -> Vec<(usize, f32)> {
+    top_k(
+        vectors
+            .iter()
+            .enumerate()
+            .map(|(id, vector)| (id, vector.dot(query))),
+        k,
+    )
+}
+
+/// Selects the `k` highest scores from an iterator without sorting all of it.
+pub fn top_k(scores: impl IntoIterator<Item = (usize, f32)>, k: usize) -> Vec<(usize, f32)> {
+    if k == 0 {
+        return Vec::new();
+    }
+
+    // Min-heap holding the best `k` seen so far
+    let mut heap: BinaryHeap<Reverse<Scored>> = BinaryHeap::with_capacity(k + 1);
+    for (id, score) in scores {
+        let scored = Scored { score, id };
+        if heap.len() < k {
+            heap.push(Reverse(scored));
+        } else if let Some(Reverse(worst)) = heap.peek() {
+            if scored > *worst {
+                heap.pop();
+                heap.push(Reverse(scored));
+            }
+        }
+    }
+
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(scored)| (scored.id, scored.score))
+        .collect()
+}
+
+/// Tuning knobs for the HNSW graph.
+#[derive(Debug, Clone, Copy)]
+pub struct HnswParams {
+    /// Maximum number of links per node on the upper layers. Layer 0 allows twice as many.
+    pub m: usize,
+    /// Size of the candidate list while inserting.
+    pub ef_construction: usize,
+    /// Size of the candidate list while searching. Raised to `k` when `k` is larger.
+    pub ef_search: usize,
+    /// Seed for the level generator, so the same vectors always build the same graph.
+    pub seed: u64,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        HnswParams {
+            m: 16,
+            ef_construction: 100,
+            ef_search: 64,
+            seed: 0x5eed,
+        }
+    }
+}
+
+/// Hierarchical Navigable Small World graph over a set of vectors, using the dot product
+/// as similarity (OpenAI embeddings are normalized, so this is the cosine similarity).
+///
+/// The graph only stores links; the vectors are passed in on every call so the index can
+/// live next to the storage that owns them.
+#[derive(Debug, Clone)]
+pub struct HnswIndex {
+    params: HnswParams,
+    /// `links[node][layer]` are the neighbours of `node` on `layer`.
+    links: Vec<Vec<Vec<usize>>>,
+    entry_point: Option<usize>,
+    rng_state: u64,
+}
+
+impl Default for HnswIndex {
+    fn default() -> Self {
+        HnswIndex::new(HnswParams::default())
+    }
+}
+
+impl HnswIndex {
+    /// Creates an empty index.
+    pub fn new(params: HnswParams) -> Self {
+        HnswIndex {
+            params,
+            links: Vec::new(),
+            entry_point: None,
+            rng_state: params.seed,
+        }
+    }
+
+    /// Builds an index over all the given vectors.
+    pub fn build(vectors: &[Array1<f32>], params: HnswParams) -> Self {
+        let mut index = HnswIndex::new(params);
+        for id in 0..vectors.len() {
+            index.insert(vectors, id);
+        }
+        index
+    }
+
+    /// Number of vectors in the graph.
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    fn top_layer(&self) -> usize {
+        self.entry_point.map_or(0, |entry| self.links[entry].len() - 1)
+    }
+
+    /// Draws the top layer of a new node from an exponentially decaying distribution.
+    fn random_level(&mut self) -> usize {
+        // splitmix64
+        self.rng_state = self.rng_state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+
+        let uniform = ((z >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        let level_mult = 1.0 / (self.params.m.max(2) as f64).ln();
+        (-uniform.ln() * level_mult).floor() as usize
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.params.m * 2
+        } else {
+            self.params.m
+        }
+    }
+
+    /// Inserts `vectors[id]` into the graph. Vectors must be inserted in order.
+    pub fn insert(&mut self, vectors: &[Array1<f32>], id: usize) {
+        assert_eq!(id, self.links.len(), "Vectors must be inserted in order");
+
+        let level = self.random_level();
+        self.links.push(vec![Vec::new(); level + 1]);
+
+        let entry_point = match self.entry_point {
+            Some(entry_point) => entry_point,
+            None => {
+                self.entry_point = Some(id);
+                return;
+            }
+        };
+
+        let query = &vectors[id];
+        let top_layer = self.top_layer();
+        let mut entry_points = vec![entry_point];
+
+        // Greedily descend through the layers above the new node
+        for layer in (level + 1..=top_layer).rev() {
+            let closest = self.search_layer(vectors, query, &entry_points, 1, layer);
+            entry_points = vec![closest[0].id];
+        }
+
+        for layer in (0..=level.min(top_layer)).rev() {
+            let candidates = self.search_layer(vectors, query, &entry_points, self.params.ef_construction, layer);
+            let max_links = self.max_links(layer);
+
+            let neighbours: Vec<usize> = candidates.iter().take(max_links).map(|c| c.id).collect();
+            for &neighbour in &neighbours {
+                self.links[neighbour][layer].push(id);
+                if self.links[neighbour][layer].len() > max_links {
+                    self.prune(vectors, neighbour, layer, max_links);
+                }
+            }
+            self.links[id][layer] = neighbours;
+
+            entry_points = candidates.into_iter().map(|c| c.id).collect();
+        }
+
+        if level > top_layer {
+            self.entry_point = Some(id);
+        }
+    }
+
+    /// Keeps only the `max_links` closest neighbours of `node` on `layer`.
+    fn prune(&mut self, vectors: &[Array1<f32>], node: usize, layer: usize, max_links: usize) {
+        let base = &vectors[node];
+        let kept = top_k(
+            self.links[node][layer]
+                .iter()
+                .map(|&neighbour| (neighbour, vectors[neighbour].dot(base))),
+            max_links,
+        );
+        self.links[node][layer] = kept.into_iter().map(|(id, _)| id).collect();
+    }
+
+    /// Best-first search on a single layer. Returns up to `ef` nodes, best first.
+    fn search_layer(
+        &self,
+        vectors: &[Array1<f32>],
+        query: &Array1<f32>,
+        entry_points: &[usize],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Scored> {
+        let mut visited = vec![false; self.links.len()];
+        for &id in entry_points {
+            visited[id] = true;
+        }
+        // Max-heap of nodes still to expand
+        let mut candidates: BinaryHeap<Scored> = BinaryHeap::new();
+        // Min-heap of the best `ef` nodes found so far
+        let mut found: BinaryHeap<Reverse<Scored>> = BinaryHeap::new();
+
+        for &id in entry_points {
+            let scored = Scored { score: vectors[id].dot(query), id };
+            candidates.push(scored);
+            found.push(Reverse(scored));
+        }
+        while found.len() > ef {
+            found.pop();
+        }
+
+        while let Some(candidate) = candidates.pop() {
+            let worst = found.peek().map(|Reverse(worst)| *worst);
+            if matches!(worst, Some(worst) if found.len() >= ef && candidate < worst) {
+                break;
+            }
+
+            for &neighbour in &self.links[candidate.id][layer] {
+                if visited[neighbour] {
+                    continue;
+                }
+                visited[neighbour] = true;
+
+                let scored = Scored { score: vectors[neighbour].dot(query), id: neighbour };
+                let worst = found.peek().map(|Reverse(worst)| *worst);
+                if found.len() < ef || matches!(worst, Some(worst) if scored > worst) {
+                    candidates.push(scored);
+                    found.push(Reverse(scored));
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        found.into_sorted_vec().into_iter().map(|Reverse(scored)| scored).collect()
+    }
+
+    /// Returns the approximate `k` most similar vectors as `(position, score)` pairs, best first.
+    pub fn search(&self, vectors: &[Array1<f32>], query: &Array1<f32>, k: usize) -> Vec<(usize, f32)> {
+        let entry_point = match self.entry_point {
+            Some(entry_point) if k > 0 => entry_point,
+            _ => return Vec::new(),
+        };
+
+        // A graph walk would visit every node anyway, so just scan them
+        let ef = self.params.ef_search.max(k);
+        if self.len() <= ef {
+            return brute_force(&vectors[..self.len()], query, k);
+        }
+
+        let mut entry_points = vec![entry_point];
+        for layer in (1..=self.top_layer()).rev() {
+            let closest = self.search_layer(vectors, query, &entry_points, 1, layer);
+            entry_points = vec![closest[0].id];
+        }
+
+        self.search_layer(vectors, query, &entry_points, ef, 0)
+            .into_iter()
+            .take(k)
+            .map(|scored| (scored.id, scored.score))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Deterministic pseudo-random unit vectors so the tests don't need the embedding API.
+    fn random_vectors(count: usize, dimensions: usize, seed: u64) -> Vec<Array1<f32>> {
+        let mut state = seed;
+        (0..count)
+            .map(|_| {
+                let vector: Array1<f32> = (0..dimensions)
+                    .map(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 7;
+                        state ^= state << 17;
+                        (state % 2000) as f32 / 1000.0 - 1.0
+                    })
+                    .collect();
+                let norm = vector.dot(&vector).sqrt();
+                vector / norm
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_top_k() {
+        let scores = vec![(0, 0.1), (1, 0.9), (2, 0.5), (3, 0.7), (4, -0.2)];
+        assert_eq!(top_k(scores.clone(), 3), vec![(1, 0.9), (3, 0.7), (2, 0.5)]);
+        assert_eq!(top_k(scores.clone(), 10).len(), 5);
+        assert!(top_k(scores, 0).is_empty());
+    }
+
+    #[test]
+    fn test_brute_force_matches_full_sort() {
+        let vectors = random_vectors(200, 32, 7);
+        let query = &vectors[42];
+
+        let mut sorted: Vec<(usize, f32)> = vectors.iter().enumerate().map(|(id, v)| (id, v.dot(query))).collect();
+        sorted.sort_by(|a, b| b.1.total_cmp(&a.1));
+        sorted.truncate(5);
+
+        assert_eq!(brute_force(&vectors, query, 5), sorted);
+        assert_eq!(brute_force(&vectors, query, 1)[0].0, 42);
+    }
+
+    #[test]
+    fn test_search_empty_and_small() {
+        let index = HnswIndex::build(&[], HnswParams::default());
+        assert!(index.search(&[], &Array1::zeros(4), 3).is_empty());
+
+        let vectors = random_vectors(4, 16, 3);
+        let index = HnswIndex::build(&vectors, HnswParams::default());
+        assert_eq!(index.len(), 4);
+        // With fewer vectors than `ef` the search is exact
+        assert_eq!(index.search(&vectors, &vectors[2], 4), brute_force(&vectors, &vectors[2], 4));
+    }
+
+    #[test]
+    fn test_search_recall() {
+        let vectors = random_vectors(1000, 32, 11);
+        let queries = random_vectors(50, 32, 99);
+        let index = HnswIndex::build(&vectors, HnswParams::default());
+
+        let k = 10;
+        let mut hits = 0;
+        for query in &queries {
+            let exact: HashSet<usize> = brute_force(&vectors, query, k).into_iter().map(|(id, _)| id).collect();
+            hits += index
+                .search(&vectors, query, k)
+                .into_iter()
+                .filter(|(id, _)| exact.contains(id))
+                .count();
+        }
+
+        let recall = hits as f32 / (queries.len() * k) as f32;
+        assert!(recall > 0.9, "Recall too low: {}", recall);
+    }
+
+    #[test]
+    fn test_incremental_insert() {
+        let vectors = random_vectors(100, 16, 5);
+        let mut index = HnswIndex::new(HnswParams::default());
+        for id in 0..vectors.len() {
+            index.insert(&vectors, id);
+        }
+        assert_eq!(index.len(), 100);
+        assert_eq!(index.search(&vectors, &vectors[77], 1)[0].0, 77);
+    }
+}
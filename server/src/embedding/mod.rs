@@ -0,0 +1,522 @@
+// src/embedding/mod.rs
+use ndarray::Array1;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::{env, fs};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use arc_swap::ArcSwap;
+use lazy_static::lazy_static;
+use std::error::Error;
+
+use crate::catalog::{Catalog, CatalogEntry, CategoryDeclaration};
+use index::{HnswIndex, HnswParams};
+
+pub mod index;
+
+/// Represents the category of the embedding.
+/// Categories are declared in the catalog file; the built-in ones have constants.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Category(Cow<'static, str>);
+
+impl Category {
+    pub const ELEMENT: Category = Category(Cow::Borrowed("Element"));
+    pub const ABILITY: Category = Category(Cow::Borrowed("Ability"));
+
+    pub fn new(name: impl Into<String>) -> Self {
+        Category(Cow::Owned(name.into()))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Structure to hold the embeddings and their corresponding queries.
+/// Only the queries and vectors are persisted; the index is rebuilt on load.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct EmbeddingStorage {
+    queries: Vec<String>,
+    vectors: Vec<Array1<f32>>,
+    #[serde(skip)]
+    index: HnswIndex,
+}
+
+impl EmbeddingStorage {
+    /// Creates a new, empty EmbeddingStorage.
+    fn new() -> Self {
+        EmbeddingStorage {
+            queries: Vec::new(),
+            vectors: Vec::new(),
+            index: HnswIndex::new(HnswParams::default()),
+        }
+    }
+
+    /// Rebuilds the index from the stored vectors. Called after deserializing.
+    fn build_index(&mut self) {
+        self.index = HnswIndex::build(&self.vectors, HnswParams::default());
+    }
+
+    /// Appends a new vector and its query to the storage.
+    fn append(&mut self, query: String, vector: Array1<f32>) {
+        assert_eq!(
+            vector.len(),
+            1536,
+            "Vector must have 1536 dimensions"
+        );
+        self.queries.push(query);
+        self.vectors.push(vector);
+        self.index.insert(&self.vectors, self.vectors.len() - 1);
+    }
+
+    /// Returns the `top_n` most similar queries along with their similarity score
+    fn search(&self, query_vector: &Array1<f32>, top_n: usize) -> Vec<(String, f32)> {
+        self.index
+            .search(&self.vectors, query_vector, top_n)
+            .into_iter()
+            .map(|(position, score)| (self.queries[position].clone(), score))
+            .collect()
+    }
+}
+
+/// Immutable snapshots of every loaded category.
+/// Readers grab the current snapshot without locking; writers copy it, modify the copy and
+/// swap it in, so a search never waits on another search or on a write.
+struct Storage {
+    categories: ArcSwap<HashMap<Category, Arc<EmbeddingStorage>>>,
+}
+
+impl Storage {
+    fn new() -> Self {
+        Storage {
+            categories: ArcSwap::from_pointee(HashMap::new()),
+        }
+    }
+
+    /// Returns the current snapshot of a category.
+    fn get(&self, category: &Category) -> Option<Arc<EmbeddingStorage>> {
+        self.categories.load().get(category).cloned()
+    }
+
+    fn contains(&self, category: &Category) -> bool {
+        self.categories.load().contains_key(category)
+    }
+
+    /// Publishes `storage` for `category` unless another caller already did.
+    fn insert_if_absent(&self, category: &Category, storage: Arc<EmbeddingStorage>) {
+        self.categories.rcu(|categories| {
+            let mut categories = HashMap::clone(categories);
+            categories.entry(category.clone()).or_insert_with(|| Arc::clone(&storage));
+            categories
+        });
+    }
+
+    /// Publishes `storage` for `category`, replacing the current snapshot.
+    fn insert(&self, category: &Category, storage: Arc<EmbeddingStorage>) {
+        self.categories.rcu(|categories| {
+            let mut categories = HashMap::clone(categories);
+            categories.insert(category.clone(), Arc::clone(&storage));
+            categories
+        });
+    }
+
+    /// Publishes a new snapshot of `category` with the vector appended.
+    fn append(&self, category: &Category, query: &str, vector: &Array1<f32>) {
+        self.categories.rcu(|categories| {
+            let mut categories = HashMap::clone(categories);
+            let entry = categories.entry(category.clone()).or_insert_with(|| Arc::new(EmbeddingStorage::new()));
+            Arc::make_mut(entry).append(query.to_string(), vector.clone());
+            categories
+        });
+    }
+
+    fn remove(&self, category: &Category) {
+        self.categories.rcu(|categories| {
+            let mut categories = HashMap::clone(categories);
+            categories.remove(category);
+            categories
+        });
+    }
+}
+
+lazy_static! {
+    /// In-memory storage shared by every search.
+    static ref STORAGE: Storage = Storage::new();
+
+    /// Categories declared by the catalog file.
+    /// The built-in ones are known up front so they can be searched before the catalog is read.
+    static ref DECLARATIONS: ArcSwap<HashMap<Category, CategoryDeclaration>> = ArcSwap::from_pointee(HashMap::from([
+        (Category::ELEMENT, CategoryDeclaration { storage: "elements.bin".to_string() }),
+        (Category::ABILITY, CategoryDeclaration { storage: "abilities.bin".to_string() }),
+    ]));
+}
+
+pub async fn embed(query: &str) -> Result<Array1<f32>, Box<dyn std::error::Error>> {
+    dotenv::dotenv().ok();
+    // Get the OpenAI API key from the environment variable
+    let api_key = env::var("OPENAI_API_KEY").map_err(|_| "Environment variable `OPENAI_API_KEY` must be set")?;
+
+    // Create the HTTP client
+    let client = Client::new();
+
+    // OpenAI endpoint for embeddings
+    let endpoint = "https://api.openai.com/v1/embeddings";
+
+    // JSON payload for the POST request
+    let payload = json!({
+        "model": "text-embedding-3-small",
+        "input": query
+    });
+
+    // Send the POST request
+    let response = client
+        .post(endpoint)
+        .header("Authorization", format!("Bearer {}", api_key))
+        .header("Content-Type", "application/json")
+        .json(&payload)
+        .send()
+        .await?;
+
+    // Check for HTTP errors
+    if !response.status().is_success() {
+        let error_message = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        return Err(format!("OpenAI API request failed: {}", error_message).into());
+    }
+
+    // Parse the response body
+    let response_body: serde_json::Value = response.json().await?;
+
+    // Extract the embedding vector
+    if let Some(embedding_vec) = response_body["data"][0]["embedding"].as_array() {
+        let embedding: Vec<f32> = embedding_vec
+            .iter()
+            .filter_map(|v| v.as_f64().map(|f| f as f32)) // Convert from JSON numbers to f32
+            .collect();
+        return Ok(Array1::from(embedding));
+    }
+
+    // If embedding is missing in the response
+    Err("Failed to retrieve embedding from the OpenAI response".into())
+}
+
+pub enum Query<'a> {
+    Text(&'a str),
+    Vector(&'a Array1<f32>)
+}
+/// Search for the most similar query to the given query
+/// Returns a list of tuples with the query and the similarity score
+pub async fn search<'a>(query: Query<'a>, category: Category, top_n: usize) -> Result<Vec<(String, f32)>, Box<dyn Error>> {
+    // Call the async embed function before touching the storage
+    let query_vector = match query {
+        Query::Text(text) => embed(text).await?,
+        Query::Vector(vector) => vector.clone()
+    };
+
+    let embedding_storage = match STORAGE.get(&category) {
+        Some(storage) => storage,
+        None => return Ok(Vec::new()), // Category not found
+    };
+
+    Ok(embedding_storage.search(&query_vector, top_n))
+}
+
+pub fn append_embedding(vector: Array1<f32>, query: &str, category: Category) {
+    STORAGE.append(&category, query, &vector);
+}
+
+/// Loads a category from the storage directory `dir` unless it is in memory already.
+pub fn load(category: Category, dir: &Path) -> Result<(), Box<dyn Error>> {
+    if STORAGE.contains(&category) {
+        // Already loaded
+        return Ok(());
+    }
+
+    STORAGE.insert_if_absent(&category, Arc::new(read_storage(&category, dir)?));
+    Ok(())
+}
+
+/// Loads a category from disk, replacing whatever was in memory.
+pub fn reload(category: Category, dir: &Path) -> Result<(), Box<dyn Error>> {
+    STORAGE.insert(&category, Arc::new(read_storage(&category, dir)?));
+    Ok(())
+}
+
+/// Reads and indexes a category outside of the storage so searches carry on meanwhile.
+fn read_storage(category: &Category, dir: &Path) -> Result<EmbeddingStorage, Box<dyn Error>> {
+    let path = get_storage_path(category, dir);
+    if Path::new(&path).exists() {
+        let data = fs::read(&path)?;
+        let mut loaded_storage: EmbeddingStorage = bincode::deserialize(&data)?;
+        loaded_storage.build_index();
+        Ok(loaded_storage)
+    } else {
+        // Initialize empty storage if file does not exist
+        Ok(EmbeddingStorage::new())
+    }
+}
+
+/// Writes a category to the storage directory `dir`.
+pub fn save(category: &Category, dir: &Path) -> Result<(), Box<dyn Error>> {
+    if let Some(embedding_storage) = STORAGE.get(category) {
+        let encoded: Vec<u8> = bincode::serialize(embedding_storage.as_ref())?;
+        fs::create_dir_all(dir)?;
+        let path = get_storage_path(category, dir);
+        fs::write(path, encoded)?;
+    }
+    Ok(())
+}
+
+pub fn unload(category: &Category) {
+    STORAGE.remove(category);
+}
+
+/// Registers how a category is stored. Must happen before the category is loaded.
+pub fn declare(category: Category, declaration: CategoryDeclaration) {
+    DECLARATIONS.rcu(|declarations| {
+        let mut declarations = HashMap::clone(declarations);
+        declarations.insert(category.clone(), declaration.clone());
+        declarations
+    });
+}
+
+/// All categories declared so far.
+pub fn declared_categories() -> Vec<Category> {
+    DECLARATIONS.load().keys().cloned().collect()
+}
+
+fn get_storage_path(category: &Category, dir: &Path) -> PathBuf {
+    let filename = match DECLARATIONS.load().get(category) {
+        Some(declaration) => declaration.storage.clone(),
+        // Undeclared categories still get their own file
+        None => format!("{}.bin", category.name().to_lowercase()),
+    };
+    dir.join(filename)
+}
+
+/// Embeds every entry that isn't stored yet and saves the category.
+/// Returns the number of newly embedded entries.
+pub async fn seed(category: &Category, entries: &[CatalogEntry], dir: &Path) -> Result<usize, Box<dyn Error>> {
+    load(category.clone(), dir)?;
+    let stored = STORAGE.get(category).map(|storage| storage.queries.clone()).unwrap_or_default();
+
+    let mut seeded = 0;
+    for entry in entries.iter().filter(|entry| !stored.iter().any(|query| query == entry.name())) {
+        log::info!("Seeding {} `{}`", category, entry.name());
+        let embedding = embed(&entry.embedding_text()).await?;
+        append_embedding(embedding, entry.name(), category.clone());
+        seeded += 1;
+    }
+
+    if seeded > 0 {
+        save(category, dir)?;
+    }
+    Ok(seeded)
+}
+
+/// Declares and (re)loads every category of the catalog from the storage directory `dir`.
+pub fn initialize_storage(catalog: &Catalog, dir: &Path) -> Result<(), Box<dyn Error>> {
+    for declared in &catalog.categories {
+        declare(declared.category.clone(), declared.declaration.clone());
+        reload(declared.category.clone(), dir)?;
+
+        let stored = STORAGE.get(&declared.category).map_or(0, |storage| storage.queries.len());
+        if stored < declared.entries.len() {
+            log::warn!(
+                "Category {} has {} of {} entries embedded, it needs seeding",
+                declared.category, stored, declared.entries.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::CATALOG_PATH;
+
+    fn catalog() -> Catalog {
+        Catalog::load(CATALOG_PATH).expect("Failed to load catalog")
+    }
+
+    /// The default storage directory, which the seeded embeddings live in.
+    fn storage_dir() -> &'static Path {
+        Path::new("database")
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the embedding API, set OPENAI_API_KEY"]
+    async fn test_initialize_database() {
+        println!("Initializing storage");
+        let catalog = catalog();
+        initialize_storage(&catalog, storage_dir()).expect("Failed to initialize storage");
+
+        // Embed whatever the catalog file has that the storage doesn't
+        for declared in &catalog.categories {
+            println!("Seeding {}", declared.category);
+            seed(&declared.category, &declared.entries, storage_dir()).await.expect("Failed to seed category");
+            assert!(STORAGE.get(&declared.category).unwrap().vectors.len() >= declared.entries.len());
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the embedding API, set OPENAI_API_KEY"]
+    async fn test_search_from_disk() {
+        initialize_storage(&catalog(), storage_dir()).expect("Failed to initialize storage");
+        let query_embedding = embed("Flame Wall: Summons a barrier of fire that reduces incoming physical damage for 2 turns").await.unwrap();
+        let abilities = search(Query::Vector(&query_embedding), Category::ABILITY, 4).await.unwrap();
+        println!("{:?}", abilities);
+        assert_eq!(abilities.len(), 4);
+
+        let elements = search(Query::Vector(&query_embedding), Category::ELEMENT, 2).await.unwrap();
+        println!("{:?}", elements);
+        assert_eq!(elements.len(), 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the embedding API, set OPENAI_API_KEY"]
+    async fn test_append_and_search() {
+        // Initialize storage
+        initialize_storage(&catalog(), storage_dir()).expect("Failed to initialize storage");
+
+        // Create and append embeddings
+        let query1 = "fireball";
+        let category = Category::ABILITY;
+        let embedding1 = embed(query1).await.unwrap();
+        append_embedding(embedding1.clone(), query1, category.clone());
+
+        let query2 = "iceblast";
+        let embedding2 = embed(query2).await.unwrap();
+        append_embedding(embedding2.clone(), query2, category.clone());
+
+        // Search for similar abilities
+        let results = search(Query::Text("fireball"), category.clone(), 2).await.unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, "fireball");
+        assert_eq!(results[1].0, "iceblast");
+
+        // Save embeddings
+        save(&category, storage_dir()).expect("Failed to save embeddings");
+    }
+
+    #[tokio::test]
+    async fn test_load() {
+        // Load embeddings for Element category (should be empty initially)
+        let category = Category::ELEMENT;
+        load(category.clone(), storage_dir()).expect("Failed to load embeddings");
+        let embedding_storage = STORAGE.get(&category).unwrap();
+        assert_eq!(embedding_storage.vectors.len(), 0);
+        assert_eq!(embedding_storage.queries.len(), 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the embedding API, set OPENAI_API_KEY"]
+    async fn test_persistence() {
+        // Initialize storage
+        initialize_storage(&catalog(), storage_dir()).expect("Failed to initialize storage");
+
+        // Append and save
+        let query = "thunderstrike";
+        let category = Category::ABILITY;
+        let embedding = embed(query).await.unwrap();
+        append_embedding(embedding.clone(), query, category.clone());
+        save(&category, storage_dir()).expect("Failed to save embeddings");
+
+        // Clear in-memory storage
+        STORAGE.remove(&category);
+
+        // Load from disk
+        load(category.clone(), storage_dir()).expect("Failed to load embeddings");
+        let embedding_storage = STORAGE.get(&category).unwrap();
+        assert_eq!(embedding_storage.vectors.len(), 1);
+        assert_eq!(embedding_storage.queries.len(), 1);
+        assert_eq!(embedding_storage.queries[0], "thunderstrike");
+    }
+
+    #[test]
+    fn test_declared_storage_path() {
+        assert_eq!(get_storage_path(&Category::ELEMENT, storage_dir()), Path::new("database/elements.bin"));
+        assert_eq!(get_storage_path(&Category::new("Weather"), storage_dir()), Path::new("database/weather.bin"));
+
+        declare(Category::new("Terrain"), CategoryDeclaration { storage: "terrain_v2.bin".to_string() });
+        assert_eq!(get_storage_path(&Category::new("Terrain"), storage_dir()), Path::new("database/terrain_v2.bin"));
+        assert!(declared_categories().contains(&Category::new("Terrain")));
+    }
+
+    /// A unit vector pointing along `axis`, so tests don't need the embedding API.
+    fn axis_vector(axis: usize) -> Array1<f32> {
+        let mut vector = Array1::zeros(1536);
+        vector[axis] = 1.0;
+        vector
+    }
+
+    #[test]
+    fn test_snapshot_unaffected_by_append() {
+        let storage = Storage::new();
+        let category = Category::ABILITY;
+        storage.append(&category, "first", &axis_vector(0));
+
+        let snapshot = storage.get(&category).unwrap();
+        storage.append(&category, "second", &axis_vector(1));
+
+        // The old snapshot is immutable, the new one sees both
+        assert_eq!(snapshot.queries, vec!["first"]);
+        assert_eq!(storage.get(&category).unwrap().queries, vec!["first", "second"]);
+        assert_eq!(snapshot.search(&axis_vector(1), 2).len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_search_and_append() {
+        let storage = Arc::new(Storage::new());
+        let category = Category::ABILITY;
+        storage.insert_if_absent(&category, Arc::new(EmbeddingStorage::new()));
+
+        let writer = {
+            let storage = Arc::clone(&storage);
+            let category = category.clone();
+            tokio::spawn(async move {
+                for axis in 0..64 {
+                    storage.append(&category, &format!("axis {}", axis), &axis_vector(axis));
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+
+        let readers: Vec<_> = (0..8)
+            .map(|reader| {
+                let storage = Arc::clone(&storage);
+                let category = category.clone();
+                tokio::spawn(async move {
+                    for _ in 0..200 {
+                        let snapshot = storage.get(&category).unwrap();
+                        let results = snapshot.search(&axis_vector(reader), 3);
+                        // Every snapshot is internally consistent
+                        assert_eq!(snapshot.queries.len(), snapshot.vectors.len());
+                        assert_eq!(results.len(), snapshot.queries.len().min(3));
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+
+        writer.await.unwrap();
+        for reader in readers {
+            reader.await.unwrap();
+        }
+
+        let snapshot = storage.get(&category).unwrap();
+        assert_eq!(snapshot.queries.len(), 64);
+        assert_eq!(snapshot.search(&axis_vector(10), 1)[0].0, "axis 10");
+    }
+}
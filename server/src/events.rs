@@ -0,0 +1,181 @@
+// src/events.rs
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+use crate::battle::{Decision, TurnReport};
+use crate::bot::BotKind;
+use crate::db;
+use crate::repository::GameRepository;
+
+/// How many events a slow subscriber may fall behind before it has to catch up from the ledger.
+const CHANNEL_CAPACITY: usize = 64;
+
+/// Something that happened in a game. Recorded in the ledger and pushed to subscribers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GameEvent {
+    PlayerJoined { player_id: i64, name: String },
+    /// The player is played by the server
+    BotJoined { player_id: i64, bot: BotKind },
+    /// The player declared it is played by an agent, e.g. the model it runs
+    AgentJoined { player_id: i64, agent: String },
+    CreatureCreated { player_id: i64, name: String },
+    PlayerReady { player_id: i64 },
+    BattleStarted { first_player_id: i64 },
+    DecisionMade { player_id: i64, decision: Decision },
+    TurnResolved { report: TurnReport },
+    GameOver { winner_player_id: i64 },
+}
+
+impl GameEvent {
+    /// Name of the event, stored as the ledger command.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            GameEvent::PlayerJoined { .. } => "player_joined",
+            GameEvent::BotJoined { .. } => "bot_joined",
+            GameEvent::AgentJoined { .. } => "agent_joined",
+            GameEvent::CreatureCreated { .. } => "creature_created",
+            GameEvent::PlayerReady { .. } => "player_ready",
+            GameEvent::BattleStarted { .. } => "battle_started",
+            GameEvent::DecisionMade { .. } => "decision_made",
+            GameEvent::TurnResolved { .. } => "turn_resolved",
+            GameEvent::GameOver { .. } => "game_over",
+        }
+    }
+}
+
+/// An event as stored in the ledger. `id` is the sequence number clients resume from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventEnvelope {
+    pub id: i64,
+    pub game_id: i64,
+    pub timestamp: String,
+    pub event: GameEvent,
+}
+
+impl EventEnvelope {
+    /// Formats the event as a Server-Sent Event, resumable through `Last-Event-ID`.
+    pub fn to_sse(&self) -> String {
+        let data = serde_json::to_string(self).expect("events always serialize");
+        format!("id: {}\nevent: {}\ndata: {}\n\n", self.id, self.event.kind(), data)
+    }
+}
+
+/// One broadcast channel per game, created when the first subscriber arrives.
+#[derive(Default)]
+pub struct EventHub {
+    channels: Mutex<HashMap<i64, broadcast::Sender<Arc<EventEnvelope>>>>,
+}
+
+impl EventHub {
+    pub fn new() -> Self {
+        EventHub::default()
+    }
+
+    /// Receives every event published for `game_id` from now on.
+    pub fn subscribe(&self, game_id: i64) -> broadcast::Receiver<Arc<EventEnvelope>> {
+        let mut channels = self.channels.lock().unwrap();
+        channels
+            .entry(game_id)
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Pushes an event to the subscribers of its game. Channels nobody listens to are dropped.
+    pub fn publish(&self, envelope: EventEnvelope) {
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(sender) = channels.get(&envelope.game_id) {
+            let game_id = envelope.game_id;
+            if sender.send(Arc::new(envelope)).is_err() {
+                channels.remove(&game_id);
+            }
+        }
+    }
+}
+
+/// Records an event in the ledger, then publishes it.
+pub fn record<R: GameRepository + ?Sized>(
+    repo: &R,
+    hub: &EventHub,
+    game_id: i64,
+    player_id: Option<i64>,
+    event: GameEvent,
+) -> Result<EventEnvelope, db::DbError> {
+    let envelope = repo.append_ledger(game_id, player_id, &event)?;
+    hub.publish(envelope.clone());
+    Ok(envelope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::MemoryRepository;
+    use crate::rules::RuleSet;
+
+    fn create_game(repo: &MemoryRepository, name: &str) -> (i64, i64) {
+        let (game_id, owner) = repo.create_game(name, &RuleSet::default(), "").unwrap();
+        (game_id, owner.id)
+    }
+
+    #[tokio::test]
+    async fn test_record_persists_and_publishes() {
+        let repo = MemoryRepository::new();
+        let hub = EventHub::new();
+        let (game_id, owner) = create_game(&repo, "test");
+
+        let mut receiver = hub.subscribe(game_id);
+        let first = record(&repo, &hub, game_id, Some(owner), GameEvent::PlayerReady { player_id: owner }).unwrap();
+        let second = record(&repo, &hub, game_id, None, GameEvent::GameOver { winner_player_id: owner }).unwrap();
+
+        assert!(second.id > first.id);
+        assert_eq!(*receiver.recv().await.unwrap(), first);
+        assert_eq!(*receiver.recv().await.unwrap(), second);
+
+        // Resuming after the first event only returns the second
+        assert_eq!(repo.events_since(game_id, first.id).unwrap(), vec![second]);
+    }
+
+    #[test]
+    fn test_to_sse() {
+        let envelope = EventEnvelope {
+            id: 7,
+            game_id: 1,
+            timestamp: "2024-01-01 12:00:00".to_string(),
+            event: GameEvent::GameOver { winner_player_id: 2 },
+        };
+
+        let sse = envelope.to_sse();
+        assert!(sse.starts_with("id: 7\nevent: game_over\ndata: {"));
+        assert!(sse.ends_with("}\n\n"));
+        let data = sse.lines().nth(2).unwrap().strip_prefix("data: ").unwrap();
+        assert_eq!(serde_json::from_str::<EventEnvelope>(data).unwrap(), envelope);
+    }
+
+    #[test]
+    fn test_publish_without_subscribers() {
+        let repo = MemoryRepository::new();
+        let hub = EventHub::new();
+        let (game_id, owner) = create_game(&repo, "test");
+
+        let receiver = hub.subscribe(game_id);
+        drop(receiver);
+        record(&repo, &hub, game_id, Some(owner), GameEvent::PlayerReady { player_id: owner }).unwrap();
+        assert!(hub.channels.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_events_are_isolated_per_game() {
+        let repo = MemoryRepository::new();
+        let hub = EventHub::new();
+        let (first_game, first_owner) = create_game(&repo, "first");
+        let (second_game, _) = create_game(&repo, "second");
+
+        let mut receiver = hub.subscribe(second_game);
+        record(&repo, &hub, first_game, Some(first_owner), GameEvent::PlayerReady { player_id: first_owner }).unwrap();
+
+        assert!(receiver.try_recv().is_err());
+        assert!(repo.events_since(second_game, 0).unwrap().is_empty());
+    }
+}
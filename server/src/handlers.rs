@@ -1,90 +1,1238 @@
 // src/handlers.rs
-use actix_web::{web, HttpRequest, HttpResponse, Responder};
-use rusqlite::Connection;
-use std::sync::Mutex;
-use crate::{db, models::{creature::CreateRequest, game::{NameRequest, PollRequest}, room::{Created, Joined}}};
+use actix_web::{http::StatusCode, web, web::Bytes, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tokio::sync::broadcast::{self, error::RecvError};
+use crate::{
+    battle::{BattleError, BattleState, Decision, TurnReport},
+    bot::{self, BotKind, BOT_TEAM_SIZE},
+    catalog::{Catalog, CatalogError, SharedCatalog},
+    config::Config,
+    db,
+    repository::{BattleUpdate, GameRepository},
+    events::{self, EventEnvelope, EventHub, GameEvent},
+    models::{creature::CreateRequest, game::{CreateGameRequest, LeaderboardRequest, NameRequest, PollRequest}, room::{Created, CreatureCreated, Joined}},
+    ratings::{self, RatingKind},
+    replay::Replay,
+    visibility::{self, EventFilter},
+    visualization,
+};
+
+/// How many of the latest events come with the state.
+const RECENT_EVENTS: usize = 20;
+/// How long a poll waits for a change when the client doesn't say.
+const DEFAULT_POLL_TIMEOUT: u64 = 30;
+/// Longest a poll may wait for a change.
+const MAX_POLL_TIMEOUT: u64 = 60;
+/// Ratings listed by the leaderboard when the client doesn't say.
+const DEFAULT_LEADERBOARD_SIZE: usize = 20;
+/// Most ratings the leaderboard lists at once.
+const MAX_LEADERBOARD_SIZE: usize = 100;
+/// How often an idle WebSocket is pinged to keep it open.
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+/// How often an event stream sends a comment, so proxies don't close it.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Reads the player token from the `Authorization` header.
+fn player_token(request: &HttpRequest) -> Option<String> {
+    Some(request.headers().get("Authorization")?.to_str().ok()?.to_string())
+}
+
+/// Why a request failed. Decided on blocking threads, where an `HttpResponse` can't be built.
+#[derive(Debug)]
+pub struct Failure {
+    status: StatusCode,
+    message: String,
+}
+
+impl Failure {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Failure { status, message: message.into() }
+    }
+
+    fn response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).body(self.message.clone())
+    }
+}
+
+impl From<db::DbError> for Failure {
+    fn from(e: db::DbError) -> Self {
+        match e {
+            db::DbError::NotFound => Failure::new(StatusCode::NOT_FOUND, "Game not found."),
+            db::DbError::GameFull => Failure::new(StatusCode::CONFLICT, "The game is full."),
+            db::DbError::PoolError(e) => {
+                log::error!("No database connection available: {}", e);
+                Failure::new(StatusCode::SERVICE_UNAVAILABLE, "Database unavailable.")
+            }
+            db::DbError::ReplayError(e) => {
+                log::error!("Failed to record a replay: {}", e);
+                Failure::new(StatusCode::INTERNAL_SERVER_ERROR, "Failed to record replay.")
+            }
+            e => {
+                log::error!("Database error: {}", e);
+                Failure::new(StatusCode::INTERNAL_SERVER_ERROR, "Database error.")
+            }
+        }
+    }
+}
+
+/// Runs `f` on a blocking thread, so slow queries never stall the async workers.
+async fn blocking<R, T, F>(repo: &web::Data<R>, f: F) -> Result<T, Failure>
+where
+    R: GameRepository,
+    T: Send + 'static,
+    F: FnOnce(&R) -> Result<T, Failure> + Send + 'static,
+{
+    let repo = repo.clone();
+    web::block(move || f(&repo))
+        .await
+        .unwrap_or_else(|_| Err(Failure::new(StatusCode::INTERNAL_SERVER_ERROR, "Request canceled.")))
+}
+
+/// Responds with `value` as JSON, or the failure.
+fn respond<T: Serialize>(result: Result<T, Failure>) -> HttpResponse {
+    match result {
+        Ok(value) => HttpResponse::Ok().json(value),
+        Err(failure) => failure.response(),
+    }
+}
+
+/// Records an event, logging rather than failing the request when it can't be.
+fn record_or_log(repo: &impl GameRepository, hub: &EventHub, game_id: i64, player_id: Option<i64>, event: GameEvent) {
+    if let Err(e) = events::record(repo, hub, game_id, player_id, event) {
+        log::error!("Failed to record event: {}", e);
+    }
+}
 
 // Handle the "/create" endpoint
-pub async fn handle_create(data: web::Data<Mutex<Connection>>, payload: web::Json<NameRequest>) -> impl Responder {
-    let conn = data.lock().unwrap();
-    let name = payload.name.clone(); // Extract the name from the request body
+pub async fn handle_create<R: GameRepository>(
+    repo: web::Data<R>,
+    hub: web::Data<EventHub>,
+    catalog: web::Data<SharedCatalog>,
+    payload: web::Json<CreateGameRequest>,
+) -> impl Responder {
+    let CreateGameRequest { name, rules, bot, agent } = payload.into_inner();
+    let catalog = catalog.current();
 
-    match db::create_game(&conn, &name) {
-        Ok((game_id, owner_token)) => {
-            HttpResponse::Ok().json(Created {
-                game_id: game_id,
-                token: owner_token,
-            })
+    respond(blocking(&repo, move |repo| {
+        let (game_id, owner) = repo.create_game(&name, &rules, &catalog.version)
+            .map_err(|_| Failure::new(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create game."))?;
+        record_or_log(repo, &hub, game_id, Some(owner.id), GameEvent::PlayerJoined { player_id: owner.id, name });
+        record_agent(repo, &hub, game_id, owner.id, agent);
+        if let Some(bot) = bot {
+            add_bot(repo, &hub, &catalog, game_id, bot)?;
         }
-        Err(_) => HttpResponse::InternalServerError().body("Failed to create game."),
+        Ok(Created {
+            game_id,
+            player_id: owner.id,
+            token: owner.token,
+        })
+    }).await)
+}
+
+/// Records the agent a player declared, if any.
+fn record_agent(repo: &impl GameRepository, hub: &EventHub, game_id: i64, player_id: i64, agent: Option<String>) {
+    if let Some(agent) = agent.filter(|agent| !agent.trim().is_empty()) {
+        record_or_log(repo, hub, game_id, Some(player_id), GameEvent::AgentJoined { player_id, agent });
     }
 }
 
+/// Seats `bot` in the game with a team built from the catalog, ready to battle.
+fn add_bot(repo: &impl GameRepository, hub: &EventHub, catalog: &Catalog, game_id: i64, bot: BotKind) -> Result<(), db::DbError> {
+    let name = format!("{} bot", bot);
+    let player_id = repo.join_game(game_id, &name)?.id;
+    record_or_log(repo, hub, game_id, Some(player_id), GameEvent::PlayerJoined { player_id, name });
+    events::record(repo, hub, game_id, Some(player_id), GameEvent::BotJoined { player_id, bot })?;
+
+    for creature in bot::team(catalog, player_id, BOT_TEAM_SIZE, repo.get_seed(game_id)?) {
+        repo.save_creature(game_id, player_id, &creature)?;
+        record_or_log(repo, hub, game_id, Some(player_id), GameEvent::CreatureCreated { player_id, name: creature.name });
+    }
+    events::record(repo, hub, game_id, Some(player_id), GameEvent::PlayerReady { player_id })?;
+    Ok(())
+}
+
 // Handle the "/join/{game_id}" endpoint
-pub async fn handle_join(
+pub async fn handle_join<R: GameRepository>(
     path: web::Path<i64>,
-    data: web::Data<Mutex<Connection>>,
+    repo: web::Data<R>,
+    hub: web::Data<EventHub>,
     payload: web::Json<NameRequest>,
 ) -> impl Responder {
     let game_id = path.into_inner();
-    let conn = data.lock().unwrap();
-    let name = payload.name.clone(); // Extract the name from the request body
+    let NameRequest { name, agent } = payload.into_inner();
 
-    match db::join_game(&conn, game_id, &name) {
-        Ok(player_token) => {
-            HttpResponse::Ok().json(Joined {
-                token: player_token,
-            })
-        }
-        Err(_) => HttpResponse::NotFound().body("Game not found or invalid state."),
-    }
+    respond(blocking(&repo, move |repo| {
+        let seat = repo.join_game(game_id, &name)?;
+        record_or_log(repo, &hub, game_id, Some(seat.id), GameEvent::PlayerJoined { player_id: seat.id, name });
+        record_agent(repo, &hub, game_id, seat.id, agent);
+        Ok(Joined {
+            player_id: seat.id,
+            token: seat.token,
+        })
+    }).await)
+}
+
+/// Reads the state of the game as `viewer` may see it.
+fn read_view(repo: &impl GameRepository, game_id: i64, viewer: Option<i64>) -> Result<visibility::GameView, db::DbError> {
+    let state = repo.load_state(game_id)?;
+    let events = repo.recent_events(game_id, RECENT_EVENTS)?;
+    Ok(visibility::view(&state, viewer, events))
+}
+
+/// Returns the player of the optional token, `None` for spectators, who send no token.
+/// Fails if the token isn't one of the game's.
+fn check_viewer(repo: &impl GameRepository, game_id: i64, token: Option<&str>) -> Result<Option<i64>, Failure> {
+    token.map(|token| check_player(repo, game_id, token)).transpose()
+}
+
+/// Returns the player of `token`, failing unless they play in the game.
+fn check_player(repo: &impl GameRepository, game_id: i64, token: &str) -> Result<i64, Failure> {
+    repo.authenticate(game_id, token)?.ok_or_else(|| Failure::new(StatusCode::UNAUTHORIZED, ""))
+}
+
+// Handle the "/{game_id}/state" endpoint
+// The opponent's team is redacted according to the game's rules. Spectators send no token
+pub async fn handle_state<R: GameRepository>(
+    request: HttpRequest,
+    path: web::Path<i64>,
+    repo: web::Data<R>,
+) -> impl Responder {
+    let game_id = path.into_inner();
+    let token = player_token(&request);
+
+    respond(blocking(&repo, move |repo| {
+        let viewer = check_viewer(repo, game_id, token.as_deref())?;
+        Ok(read_view(repo, game_id, viewer)?)
+    }).await)
 }
 
 // Handle the "/poll" endpoint
-pub async fn handle_poll(
+// Waits until the game changes past the client's version, then returns the new state
+pub async fn handle_poll<R: GameRepository>(
+    request: HttpRequest,
     path: web::Path<i64>,
     web::Query(params): web::Query<PollRequest>,
-    data: web::Data<Mutex<Connection>>,
+    repo: web::Data<R>,
+    hub: web::Data<EventHub>,
 ) -> impl Responder {
-    let conn = data.lock().unwrap();
     let game_id = path.into_inner();
+    let token = player_token(&request);
+    let version = params.version;
+    let timeout = Duration::from_secs(params.timeout.unwrap_or(DEFAULT_POLL_TIMEOUT).min(MAX_POLL_TIMEOUT));
+    let deadline = tokio::time::Instant::now() + timeout;
 
-    match db::poll_game_state(&conn, game_id, params.timestamp) {
-        Ok(true) => HttpResponse::Ok().finish(),
-        Ok(false) => HttpResponse::NoContent().finish(),
-        Err(_) => HttpResponse::InternalServerError().body("Failed to poll game state."),
+    // Every change is followed by an event, so subscribe before checking
+    let mut receiver = hub.subscribe(game_id);
+    loop {
+        let token = token.clone();
+        let changed = blocking(&repo, move |repo| {
+            let viewer = check_viewer(repo, game_id, token.as_deref())?;
+            if repo.poll_game_state(game_id, version)? {
+                Ok(Some(read_view(repo, game_id, viewer)?))
+            } else {
+                Ok(None)
+            }
+        }).await;
+        match changed {
+            Ok(Some(view)) => return HttpResponse::Ok().json(view),
+            Ok(None) => {}
+            Err(failure) => return failure.response(),
+        }
+
+        match tokio::time::timeout_at(deadline, receiver.recv()).await {
+            Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) => continue,
+            Ok(Err(RecvError::Closed)) | Err(_) => return HttpResponse::NoContent().finish(),
+        }
     }
 }
 
-pub async fn handle_check_creatures(
+pub async fn handle_check_creatures<R: GameRepository>(
     request: HttpRequest,
     path: web::Path<i64>,
-    data: web::Data<Mutex<Connection>>,
+    repo: web::Data<R>,
 ) -> impl Responder {
-    let user_id = request.headers().get("Authorization").unwrap().to_str().unwrap().parse::<i64>().unwrap();
+    let token = match player_token(&request) {
+        Some(token) => token,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
     let game_id = path.into_inner();
-    let conn = data.lock().unwrap();
-
-    let creatures = db::get_creatures(&conn, game_id, user_id).unwrap();
 
-    HttpResponse::Ok().json(creatures)
+    respond(blocking(&repo, move |repo| {
+        let user_id = check_player(repo, game_id, &token)?;
+        Ok(repo.get_creatures(game_id, user_id)?)
+    }).await)
 }
 
-pub async fn handle_create_creature(
+pub async fn handle_create_creature<R: GameRepository>(
     request: HttpRequest,
     path: web::Path<i64>,
-    data: web::Data<Mutex<Connection>>,
+    repo: web::Data<R>,
+    hub: web::Data<EventHub>,
+    catalog: web::Data<SharedCatalog>,
     payload: web::Json<CreateRequest>
 ) -> impl Responder {
 
-    // Get the user id from the request under `Authorization`
-    let user_id = request.headers().get("Authorization").unwrap().to_str().unwrap().parse::<i64>().unwrap();
+    // Get the user's token from the request under `Authorization`
+    let token = match player_token(&request) {
+        Some(token) => token,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
     let game_id = path.into_inner();
-    let conn = data.lock().unwrap();
     let creature = payload.into_inner();
-    let creature = creature.transform().await;
+    // Embedding round-trips happen here, before any connection is taken
+    let creature = match creature.transform(&catalog.current()).await {
+        Ok(creature) => creature,
+        Err(e) => {
+            log::error!("Failed to create creature: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to create creature.");
+        }
+    };
+
+    respond(blocking(&repo, move |repo| {
+        let user_id = check_player(repo, game_id, &token)?;
+        let creature_id = match repo.save_creature(game_id, user_id, &creature) {
+            Ok(creature_id) => creature_id,
+            Err(db::DbError::NotFound) => return Err(Failure::new(StatusCode::NOT_FOUND, "Player not found in this game.")),
+            Err(e) => return Err(e.into()),
+        };
+        record_or_log(repo, &hub, game_id, Some(user_id), GameEvent::CreatureCreated { player_id: user_id, name: creature.name.clone() });
+        Ok(CreatureCreated { id: creature_id })
+    }).await)
+}
+
+// Handle the "/{game_id}/ready" endpoint
+// The battle starts once both players are ready
+pub async fn handle_ready<R: GameRepository>(
+    request: HttpRequest,
+    path: web::Path<i64>,
+    repo: web::Data<R>,
+    hub: web::Data<EventHub>,
+) -> impl Responder {
+    let game_id = path.into_inner();
+    let token = match player_token(&request) {
+        Some(token) => token,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+
+    let result = blocking(&repo, move |repo| {
+        let player_id = check_player(repo, game_id, &token)?;
+        if repo.load_battle(game_id)?.is_some() {
+            return Err(Failure::new(StatusCode::CONFLICT, "The battle has already started."));
+        }
+        if repo.get_creatures(game_id, player_id)?.is_empty() {
+            return Err(Failure::new(StatusCode::CONFLICT, "Create a creature first."));
+        }
+
+        events::record(repo, &hub, game_id, Some(player_id), GameEvent::PlayerReady { player_id })?;
+        if start_battle(repo, &hub, game_id)? {
+            play_bot_turns(repo, &hub, game_id)?;
+        }
+        Ok(())
+    }).await;
+
+    match result {
+        Ok(()) => HttpResponse::Ok().body("OK"),
+        Err(failure) => failure.response(),
+    }
+}
+
+/// Starts the battle if both players are ready. Returns whether it started.
+fn start_battle(repo: &impl GameRepository, hub: &EventHub, game_id: i64) -> Result<bool, db::DbError> {
+    let players = repo.get_players(game_id)?;
+    let ready = repo.ready_players(game_id)?;
+    if players.len() != 2 || players.iter().any(|(player_id, _)| !ready.contains(player_id)) {
+        return Ok(false);
+    }
+
+    let teams = players
+        .iter()
+        .map(|(player_id, _)| Ok((*player_id, repo.get_creatures(game_id, *player_id)?)))
+        .collect::<Result<Vec<_>, db::DbError>>()?;
+    let battle = BattleState::new(repo.get_seed(game_id)?, teams).map_err(|_| db::DbError::InvalidGameState)?;
+    let first_player_id = battle.teams[battle.current].player_id;
+
+    // Both players can get here at once, only the first one starts the battle
+    let started = repo.update_battle(game_id, &mut |current| current.is_none().then(|| BattleUpdate {
+        phase: "battle",
+        battle: battle.clone(),
+        events: vec![(None, GameEvent::BattleStarted { first_player_id })],
+    }))?;
+    let started_now = !started.is_empty();
+    publish(hub, started);
+    Ok(started_now)
+}
+
+/// Publishes events already stored in the ledger.
+fn publish(hub: &EventHub, events: Vec<EventEnvelope>) {
+    for envelope in events {
+        hub.publish(envelope);
+    }
+}
+
+// Handle the "/{game_id}/decision" endpoint
+pub async fn handle_decision<R: GameRepository>(
+    request: HttpRequest,
+    path: web::Path<i64>,
+    repo: web::Data<R>,
+    hub: web::Data<EventHub>,
+    payload: web::Json<Decision>,
+) -> impl Responder {
+    let game_id = path.into_inner();
+    let token = match player_token(&request) {
+        Some(token) => token,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    let decision = payload.into_inner();
+
+    respond(blocking(&repo, move |repo| {
+        let player_id = check_player(repo, game_id, &token)?;
+        let report = resolve_decision(repo, &hub, game_id, player_id, decision)?;
+        play_bot_turns(repo, &hub, game_id)?;
+        Ok(report)
+    }).await)
+}
+
+/// Applies the decision of `player_id` to the battle and lists what to record of it.
+fn apply_decision(battle: Option<BattleState>, player_id: i64, decision: Decision) -> Result<(TurnReport, BattleUpdate), Failure> {
+    let mut battle = battle.ok_or_else(|| Failure::new(StatusCode::CONFLICT, "The battle has not started."))?;
+    let team = battle.team_of(player_id).ok_or_else(|| Failure::new(StatusCode::UNAUTHORIZED, ""))?;
+
+    let report = match battle.apply(team, decision.clone()) {
+        Ok(report) => report,
+        Err(e @ (BattleError::NotYourTurn | BattleError::Finished)) => return Err(Failure::new(StatusCode::CONFLICT, e.to_string())),
+        Err(e) => return Err(Failure::new(StatusCode::BAD_REQUEST, e.to_string())),
+    };
+    let phase = if battle.is_finished() { "finish" } else { "battle" };
+
+    let mut events = vec![
+        (Some(player_id), GameEvent::DecisionMade { player_id, decision }),
+        (None, GameEvent::TurnResolved { report: report.clone() }),
+    ];
+    if let Some(team) = report.winner {
+        events.push((None, GameEvent::GameOver { winner_player_id: battle.teams[team].player_id }));
+    }
+    Ok((report, BattleUpdate { phase, battle, events }))
+}
+
+/// Applies the decision of `player_id`, stores the battle and records what happened, all at once
+/// so a decision sent twice is only resolved once.
+fn resolve_decision(repo: &impl GameRepository, hub: &EventHub, game_id: i64, player_id: i64, decision: Decision) -> Result<TurnReport, Failure> {
+    let mut outcome = Err(Failure::new(StatusCode::CONFLICT, "The battle has not started."));
+    let events = repo.update_battle(game_id, &mut |battle| match apply_decision(battle, player_id, decision.clone()) {
+        Ok((report, update)) => {
+            outcome = Ok(report);
+            Some(update)
+        }
+        Err(failure) => {
+            outcome = Err(failure);
+            None
+        }
+    })?;
+    let report = outcome?;
+    publish(hub, events);
+
+    if report.winner.is_some() {
+        // The ratings can be recomputed from the ledger, a failure here isn't the player's
+        if let Err(e) = ratings::rate_finished(repo, game_id) {
+            log::error!("Failed to rate game {}: {}", game_id, e);
+        }
+    }
+    Ok(report)
+}
+
+/// Plays the turns of the game's bot until a player has to decide or the battle is over.
+fn play_bot_turns(repo: &impl GameRepository, hub: &EventHub, game_id: i64) -> Result<(), Failure> {
+    let bots = repo.bots(game_id)?;
+    if bots.is_empty() {
+        return Ok(());
+    }
+    while let Some(battle) = repo.load_battle(game_id)?.filter(|battle| !battle.is_finished()) {
+        let player_id = battle.teams[battle.current].player_id;
+        let kind = match bots.iter().find(|(bot_id, _)| *bot_id == player_id) {
+            Some((_, kind)) => *kind,
+            None => break,
+        };
+        let decision = kind.bot().decide(&battle, battle.current, &mut bot::turn_rng(&battle));
+        resolve_decision(repo, hub, game_id, player_id, decision)?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct SubscribeRequest {
+    /// Player token, for clients that cannot set headers on a WebSocket handshake
+    pub token: Option<String>,
+    /// Id of the last event received, only later events are sent
+    #[serde(default)]
+    pub since: i64,
+}
+
+// Handle the "/{game_id}/ws" endpoint
+// Sends every event recorded after `since`, then pushes new events as they happen
+pub async fn handle_ws<R: GameRepository>(
+    request: HttpRequest,
+    body: web::Payload,
+    path: web::Path<i64>,
+    web::Query(params): web::Query<SubscribeRequest>,
+    repo: web::Data<R>,
+    hub: web::Data<EventHub>,
+) -> actix_web::Result<HttpResponse> {
+    let game_id = path.into_inner();
+    let token = match params.token.or_else(|| player_token(&request)) {
+        Some(token) => token,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+    let since = params.since;
+
+    // Subscribe before reading the backlog so no event falls in between
+    let receiver = hub.subscribe(game_id);
+    let backlog = blocking(&repo, move |repo| {
+        let player_id = check_player(repo, game_id, &token)?;
+        let filter = EventFilter::new(&repo.load_state(game_id)?, Some(player_id));
+        Ok((filter, repo.events_since(game_id, since)?))
+    }).await;
+    let (filter, backlog) = match backlog {
+        Ok(backlog) => backlog,
+        Err(failure) => return Ok(failure.response()),
+    };
+
+    let (response, session, messages) = actix_ws::handle(&request, body)?;
+    actix_web::rt::spawn(run_ws_session(session, messages, receiver, repo, game_id, since, filter, backlog));
+    Ok(response)
+}
+
+/// Builds `filter` again from the latest state if `events` changed what the state shows.
+/// Events are published once stored, so the state already includes them.
+async fn refresh_filter<R: GameRepository>(repo: &web::Data<R>, game_id: i64, filter: &mut EventFilter, events: &[EventEnvelope]) -> Result<(), Failure> {
+    if events.iter().any(|envelope| EventFilter::is_outdated_by(&envelope.event)) {
+        let viewer = filter.viewer();
+        *filter = blocking(repo, move |repo| Ok(EventFilter::new(&repo.load_state(game_id)?, viewer))).await?;
+    }
+    Ok(())
+}
+
+/// Forwards events to the socket until either side goes away.
+#[allow(clippy::too_many_arguments)]
+async fn run_ws_session<R: GameRepository>(
+    mut session: actix_ws::Session,
+    mut messages: actix_ws::MessageStream,
+    mut receiver: broadcast::Receiver<Arc<EventEnvelope>>,
+    repo: web::Data<R>,
+    game_id: i64,
+    mut last_id: i64,
+    mut filter: EventFilter,
+    backlog: Vec<EventEnvelope>,
+) {
+    if send_events(&mut session, &mut last_id, &filter, backlog).await.is_err() {
+        return;
+    }
+
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    let reason = loop {
+        tokio::select! {
+            event = receiver.recv() => {
+                let events = match event {
+                    Ok(envelope) => vec![envelope.as_ref().clone()],
+                    // Fell behind the channel, catch up from the ledger
+                    Err(RecvError::Lagged(_)) => {
+                        let after = last_id;
+                        match blocking(&repo, move |repo| Ok(repo.events_since(game_id, after)?)).await {
+                            Ok(events) => events,
+                            Err(_) => break None,
+                        }
+                    }
+                    Err(RecvError::Closed) => break None,
+                };
+                if refresh_filter(&repo, game_id, &mut filter, &events).await.is_err() {
+                    break None;
+                }
+                if send_events(&mut session, &mut last_id, &filter, events).await.is_err() {
+                    return;
+                }
+            }
+            message = messages.recv() => match message {
+                Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                    if session.pong(&bytes).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Ok(actix_ws::Message::Close(reason))) => break reason,
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => break None,
+            },
+            _ = ping.tick() => {
+                if session.ping(b"").await.is_err() {
+                    return;
+                }
+            }
+        }
+    };
+    let _ = session.close(reason).await;
+}
+
+/// Sends the events the client has not seen yet, as JSON text messages.
+async fn send_events(
+    session: &mut actix_ws::Session,
+    last_id: &mut i64,
+    filter: &EventFilter,
+    events: Vec<EventEnvelope>,
+) -> Result<(), actix_ws::Closed> {
+    for envelope in events {
+        if envelope.id <= *last_id {
+            continue;
+        }
+        *last_id = envelope.id;
+        if let Some(envelope) = filter.apply(envelope) {
+            let text = serde_json::to_string(&envelope).expect("events always serialize");
+            session.text(text).await?;
+        }
+    }
+    Ok(())
+}
 
-    let _ = db::create_creature(&conn, game_id, user_id, &creature);
+// Handle the "/admin/catalog/reload" endpoint
+// Only available when `admin_token` is configured, and the request carries it under `Authorization`
+pub async fn handle_reload_catalog(
+    request: HttpRequest,
+    config: web::Data<Config>,
+    catalog: web::Data<SharedCatalog>,
+) -> impl Responder {
+    let admin_token = match &config.admin_token {
+        Some(token) => token,
+        None => return HttpResponse::Forbidden().body("Admin endpoints are disabled."),
+    };
+    let authorized = request.headers().get("Authorization")
+        .and_then(|header| header.to_str().ok())
+        .is_some_and(|header| bool::from(header.as_bytes().ct_eq(admin_token.as_bytes())));
+    if !authorized {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    match catalog.reload() {
+        Ok(catalog) => HttpResponse::Ok().json(serde_json::json!({
+            "abilities": catalog.abilities.len(),
+            "elements": catalog.elements.len(),
+            "categories": catalog.categories.len(),
+        })),
+        Err(CatalogError::Invalid(errors)) => HttpResponse::UnprocessableEntity().json(errors),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// Handle the "/{game_id}/events" endpoint
+// Server-Sent Events carrying the same events as the WebSocket. Open to spectators, redacted
+// for the player of the optional token. Resumes after the event sent under `Last-Event-ID`
+pub async fn handle_events<R: GameRepository>(
+    request: HttpRequest,
+    path: web::Path<i64>,
+    repo: web::Data<R>,
+    hub: web::Data<EventHub>,
+) -> impl Responder {
+    let game_id = path.into_inner();
+    let token = player_token(&request);
+    let last_id = request.headers().get("Last-Event-ID")
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.parse::<i64>().ok())
+        .unwrap_or(0);
+
+    // Subscribe before reading the backlog so no event falls in between
+    let receiver = hub.subscribe(game_id);
+    let backlog = blocking(&repo, move |repo| {
+        let viewer = check_viewer(repo, game_id, token.as_deref())?;
+        let filter = EventFilter::new(&repo.load_state(game_id)?, viewer);
+        Ok((filter, repo.events_since(game_id, last_id)?))
+    }).await;
+    let (filter, backlog) = match backlog {
+        Ok(backlog) => backlog,
+        Err(failure) => return failure.response(),
+    };
+
+    let stream = EventStream {
+        receiver,
+        pending: backlog.into(),
+        last_id,
+        filter,
+        heartbeat: tokio::time::interval(HEARTBEAT_INTERVAL),
+        repo,
+        game_id,
+    };
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(futures_util::stream::unfold(stream, EventStream::next))
+}
+
+/// State of a Server-Sent Events response.
+struct EventStream<R> {
+    receiver: broadcast::Receiver<Arc<EventEnvelope>>,
+    /// Events to send before waiting on the channel again
+    pending: VecDeque<EventEnvelope>,
+    last_id: i64,
+    filter: EventFilter,
+    heartbeat: tokio::time::Interval,
+    repo: web::Data<R>,
+    game_id: i64,
+}
+
+impl<R: GameRepository> EventStream<R> {
+    /// Waits for the next chunk to send. Ends the response when the channel closes.
+    async fn next(mut self) -> Option<(Result<Bytes, actix_web::Error>, Self)> {
+        loop {
+            if let Some(envelope) = self.pending.pop_front() {
+                if envelope.id <= self.last_id {
+                    continue;
+                }
+                self.last_id = envelope.id;
+                match self.filter.apply(envelope) {
+                    Some(envelope) => return Some((Ok(Bytes::from(envelope.to_sse())), self)),
+                    None => continue,
+                }
+            }
+
+            tokio::select! {
+                event = self.receiver.recv() => match event {
+                    Ok(envelope) => self.pending.push_back(envelope.as_ref().clone()),
+                    // Fell behind the channel, catch up from the ledger
+                    Err(RecvError::Lagged(_)) => {
+                        let (game_id, after) = (self.game_id, self.last_id);
+                        self.pending = blocking(&self.repo, move |repo| Ok(repo.events_since(game_id, after)?)).await.ok()?.into();
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+                _ = self.heartbeat.tick() => return Some((Ok(Bytes::from_static(b": heartbeat\n\n")), self)),
+            }
+            refresh_filter(&self.repo, self.game_id, &mut self.filter, self.pending.make_contiguous()).await.ok()?;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct VisualizationRequest {
+    /// Player whose team is shown as "player", the first player by default
+    pub player: Option<i64>,
+    /// Only return the frame of this turn
+    pub turn: Option<u32>,
+}
+
+// Handle the "/{game_id}/visualization" endpoint
+// Every turn of the battle in the Godot visualization's format
+// Hidden information would leak, so only available once the battle is over or when the game has no fog of war
+pub async fn handle_visualization<R: GameRepository>(
+    path: web::Path<i64>,
+    web::Query(params): web::Query<VisualizationRequest>,
+    repo: web::Data<R>,
+) -> impl Responder {
+    let game_id = path.into_inner();
+    let read = blocking(&repo, move |repo| Ok((repo.load_state(game_id)?, repo.get_decisions(game_id)?))).await;
+    let (state, decisions) = match read {
+        Ok(read) => read,
+        Err(failure) => return failure.response(),
+    };
+
+    let battle = match &state.battle {
+        Some(battle) => battle,
+        None => return HttpResponse::Conflict().body("The battle has not started."),
+    };
+    let fog = &state.rules.fog_of_war;
+    if !battle.is_finished() && (fog.abilities || fog.bench || fog.attributes) {
+        return HttpResponse::Conflict().body("The battle is not over.");
+    }
+    let perspective = match params.player {
+        Some(player_id) => match battle.team_of(player_id) {
+            Some(team) => team,
+            None => return HttpResponse::NotFound().body("Player not found."),
+        },
+        None => 0,
+    };
+
+    let states = match battle.restart().and_then(|start| start.replay(&decisions)) {
+        Ok(states) => states,
+        Err(e) => {
+            log::error!("Failed to replay game {}: {}", game_id, e);
+            return HttpResponse::InternalServerError().body("Failed to replay battle.");
+        }
+    };
+    let frames = visualization::frames(&states, perspective);
+
+    match params.turn {
+        Some(turn) => match frames.into_iter().find(|frame| frame.turn == turn) {
+            Some(frame) => HttpResponse::Ok().json(frame),
+            None => HttpResponse::NotFound().body("Turn not found."),
+        },
+        None => HttpResponse::Ok().json(frames),
+    }
+}
+
+// Handle the "/leaderboard" endpoint
+// Best ratings of players, agents or creature designs, see `ratings`
+pub async fn handle_leaderboard<R: GameRepository>(
+    repo: web::Data<R>,
+    query: web::Query<LeaderboardRequest>,
+) -> impl Responder {
+    let kind = query.kind.unwrap_or(RatingKind::Player);
+    let limit = query.limit.unwrap_or(DEFAULT_LEADERBOARD_SIZE).min(MAX_LEADERBOARD_SIZE);
+
+    respond(blocking(&repo, move |repo| Ok(repo.leaderboard(kind, limit)?)).await)
+}
+
+// Handle the "/{game_id}/replay" endpoint
+// The replay file of a finished game, see `battllm_server replay`
+pub async fn handle_replay<R: GameRepository>(
+    path: web::Path<i64>,
+    repo: web::Data<R>,
+) -> impl Responder {
+    let game_id = path.into_inner();
+
+    respond(blocking(&repo, move |repo| {
+        export_replay(repo, game_id)?.ok_or_else(|| Failure::new(StatusCode::CONFLICT, "The battle is not over."))
+    }).await)
+}
+
+/// Records the replay of a game, once its battle is over.
+pub fn export_replay(repo: &(impl GameRepository + ?Sized), game_id: i64) -> Result<Option<Replay>, db::DbError> {
+    let state = repo.load_state(game_id)?;
+    if !state.battle.as_ref().is_some_and(BattleState::is_finished) {
+        return Ok(None);
+    }
+    let decisions = repo.get_decisions(game_id)?;
+    let catalog_version = repo.get_catalog_version(game_id)?;
+
+    Ok(Some(Replay::record(&state, &catalog_version, &decisions)?))
+}
+
+/// Registers every endpoint, with the games stored in `R`.
+/// The app also needs `web::Data` of `R`, `Config`, `EventHub` and `SharedCatalog`.
+pub fn routes<R: GameRepository>(config: &mut web::ServiceConfig) {
+    config
+        .route("/create", web::post().to(handle_create::<R>))
+        .route("/leaderboard", web::get().to(handle_leaderboard::<R>))
+        .route("/{game_id}/join", web::post().to(handle_join::<R>))
+        .route("/{game_id}/poll", web::get().to(handle_poll::<R>))
+        .route("/{game_id}/state", web::get().to(handle_state::<R>))
+        .route("/{game_id}/visualization", web::get().to(handle_visualization::<R>))
+        .route("/{game_id}/replay", web::get().to(handle_replay::<R>))
+        .route("/{game_id}/creatures", web::get().to(handle_check_creatures::<R>))
+        .route("/{game_id}/creatures/create", web::post().to(handle_create_creature::<R>))
+        .route("/{game_id}/ready", web::post().to(handle_ready::<R>))
+        .route("/{game_id}/decision", web::post().to(handle_decision::<R>))
+        .route("/{game_id}/ws", web::get().to(handle_ws::<R>))
+        .route("/{game_id}/events", web::get().to(handle_events::<R>))
+        .route("/admin/catalog/reload", web::post().to(handle_reload_catalog));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battle::tests::creature;
+    use crate::catalog::CATALOG_PATH;
+    use crate::db::tests::TempDatabase;
+    use crate::models::creature::Element;
+    use crate::repository::{MemoryRepository, SqliteRepository};
+    use actix_web::{test, App};
+    use serde_json::{json, Value};
+    use std::time::Instant;
+
+    /// The `(player_id, token)` of the player a `/create` or `/join` response seats.
+    fn seat(response: &Value) -> (i64, String) {
+        (response["player_id"].as_i64().unwrap(), response["token"].as_str().unwrap().to_string())
+    }
+
+    /// The catalog shipped with the server, without its embeddings.
+    fn catalog() -> web::Data<SharedCatalog> {
+        web::Data::new(SharedCatalog::fixed(CATALOG_PATH, Config::default().storage_dir, Catalog::load(CATALOG_PATH).unwrap()))
+    }
+
+    #[actix_web::test]
+    async fn test_play_a_game_in_memory() {
+        let repo = web::Data::new(MemoryRepository::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(repo.clone())
+                .app_data(web::Data::new(EventHub::new()))
+                .app_data(catalog())
+                .configure(routes::<MemoryRepository>),
+        ).await;
+
+        let create = test::TestRequest::post().uri("/create").set_json(json!({ "name": "Owner" })).to_request();
+        let created: Value = test::call_and_read_body_json(&app, create).await;
+        let game_id = created["game_id"].as_i64().unwrap();
+        let (owner, owner_token) = seat(&created);
+        let join = test::TestRequest::post().uri(&format!("/{}/join", game_id)).set_json(json!({ "name": "Guest" })).to_request();
+        let joined: Value = test::call_and_read_body_json(&app, join).await;
+        let (guest, guest_token) = seat(&joined);
+
+        // Creating creatures through the API needs the embeddings, store them directly
+        repo.save_creature(game_id, owner, &creature(owner, "Ember", vec![Element::Fire], 6)).unwrap();
+        repo.save_creature(game_id, guest, &creature(guest, "Drop", vec![Element::Water], 4)).unwrap();
+        for token in [&owner_token, &guest_token] {
+            let ready = test::TestRequest::post().uri(&format!("/{}/ready", game_id))
+                .insert_header(("Authorization", token.as_str())).to_request();
+            assert!(test::call_service(&app, ready).await.status().is_success());
+        }
 
-    HttpResponse::Ok().body("OK")
-}
\ No newline at end of file
+        let state = test::TestRequest::get().uri(&format!("/{}/state", game_id))
+            .insert_header(("Authorization", owner_token.as_str())).to_request();
+        let state: Value = test::call_and_read_body_json(&app, state).await;
+        assert_eq!(state["phase"], "Battle");
+        assert_eq!(state["viewer"], owner);
+        let current = state["current_turn"].as_i64().unwrap();
+        let (current_token, waiting_token) = if current == owner { (&owner_token, &guest_token) } else { (&guest_token, &owner_token) };
+
+        let early = test::TestRequest::post().uri(&format!("/{}/decision", game_id))
+            .insert_header(("Authorization", waiting_token.as_str()))
+            .set_json(Decision::Wait).to_request();
+        assert_eq!(test::call_service(&app, early).await.status(), StatusCode::CONFLICT);
+        // The public id of the player doesn't authenticate
+        let impostor = test::TestRequest::post().uri(&format!("/{}/decision", game_id))
+            .insert_header(("Authorization", current.to_string()))
+            .set_json(Decision::Wait).to_request();
+        assert_eq!(test::call_service(&app, impostor).await.status(), StatusCode::UNAUTHORIZED);
+        for (token, decision) in [(current_token, Decision::Wait), (waiting_token, Decision::Concede)] {
+            let request = test::TestRequest::post().uri(&format!("/{}/decision", game_id))
+                .insert_header(("Authorization", token.as_str()))
+                .set_json(decision).to_request();
+            assert!(test::call_service(&app, request).await.status().is_success());
+        }
+
+        let replay = test::TestRequest::get().uri(&format!("/{}/replay", game_id)).to_request();
+        let replay: Replay = test::call_and_read_body_json(&app, replay).await;
+        assert_eq!(replay.decisions.len(), 2);
+        let battle = replay.verify().unwrap();
+        assert_eq!(battle.winner.map(|team| battle.teams[team].player_id), Some(current));
+
+        let leaderboard = test::TestRequest::get().uri("/leaderboard?kind=player").to_request();
+        let leaderboard: Vec<Value> = test::call_and_read_body_json(&app, leaderboard).await;
+        let winner = if current == owner { "Owner" } else { "Guest" };
+        assert_eq!(leaderboard[0]["name"], winner);
+        assert_eq!(leaderboard.len(), 2);
+        let creatures = test::TestRequest::get().uri("/leaderboard?kind=creature&limit=1").to_request();
+        let creatures: Vec<Value> = test::call_and_read_body_json(&app, creatures).await;
+        assert_eq!(creatures.len(), 1);
+
+        let missing = test::TestRequest::get().uri(&format!("/{}/state", game_id + 1)).to_request();
+        assert_eq!(test::call_service(&app, missing).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_play_against_a_bot() {
+        let repo = web::Data::new(MemoryRepository::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(repo.clone())
+                .app_data(web::Data::new(EventHub::new()))
+                .app_data(catalog())
+                .configure(routes::<MemoryRepository>),
+        ).await;
+
+        let create = test::TestRequest::post().uri("/create").set_json(json!({ "name": "Solo", "bot": "greedy" })).to_request();
+        let created: Value = test::call_and_read_body_json(&app, create).await;
+        let game_id = created["game_id"].as_i64().unwrap();
+        let (owner, owner_token) = seat(&created);
+        let bots = repo.bots(game_id).unwrap();
+        assert_eq!(bots.len(), 1);
+        let bot_id = bots[0].0;
+        assert_eq!(repo.get_creatures(game_id, bot_id).unwrap().len(), BOT_TEAM_SIZE);
+
+        // The game is full, and the battle starts as soon as the player is ready
+        let join = test::TestRequest::post().uri(&format!("/{}/join", game_id)).set_json(json!({ "name": "Guest" })).to_request();
+        assert_eq!(test::call_service(&app, join).await.status(), StatusCode::CONFLICT);
+        let missing = test::TestRequest::post().uri(&format!("/{}/join", game_id + 1000)).set_json(json!({ "name": "Guest" })).to_request();
+        assert_eq!(test::call_service(&app, missing).await.status(), StatusCode::NOT_FOUND);
+        repo.save_creature(game_id, owner, &creature(owner, "Ember", vec![Element::Fire], 6)).unwrap();
+        let ready = test::TestRequest::post().uri(&format!("/{}/ready", game_id))
+            .insert_header(("Authorization", owner_token.as_str())).to_request();
+        assert!(test::call_service(&app, ready).await.status().is_success());
+
+        // The bot answers every decision of the player right away
+        for _ in 0..3 {
+            let battle = repo.load_battle(game_id).unwrap().unwrap();
+            if battle.is_finished() {
+                break;
+            }
+            assert_eq!(battle.teams[battle.current].player_id, owner);
+            let decision = test::TestRequest::post().uri(&format!("/{}/decision", game_id))
+                .insert_header(("Authorization", owner_token.as_str()))
+                .set_json(Decision::Wait).to_request();
+            assert!(test::call_service(&app, decision).await.status().is_success());
+        }
+        let decisions = repo.get_decisions(game_id).unwrap();
+        assert!(decisions.iter().any(|(player_id, _)| *player_id == bot_id));
+    }
+
+    #[actix_web::test]
+    async fn test_concurrent_games_do_not_stall() {
+        const GAMES: usize = 12;
+        let database = TempDatabase::new("load");
+        let repo = SqliteRepository::new(database.pool.clone());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(repo.clone()))
+                .app_data(web::Data::new(EventHub::new()))
+                .app_data(catalog())
+                .configure(routes::<SqliteRepository>),
+        ).await;
+
+        let create = test::TestRequest::post().uri("/create").set_json(json!({ "name": "Watcher" })).to_request();
+        let watched: Value = test::call_and_read_body_json(&app, create).await;
+        let watched_id = watched["game_id"].as_i64().unwrap();
+
+        // Plays one game from creation to its first resolved turn
+        let play = |index: usize| {
+            let app = &app;
+            let repo = repo.clone();
+            async move {
+                let create = test::TestRequest::post().uri("/create").set_json(json!({ "name": format!("Owner {}", index) })).to_request();
+                let created: Value = test::call_and_read_body_json(app, create).await;
+                let game_id = created["game_id"].as_i64().unwrap();
+                let join = test::TestRequest::post().uri(&format!("/{}/join", game_id)).set_json(json!({ "name": "Guest" })).to_request();
+                let joined: Value = test::call_and_read_body_json(app, join).await;
+                let seats = [seat(&created), seat(&joined)];
+
+                for ((player, token), elements) in seats.iter().zip([vec![Element::Fire], vec![Element::Water]]) {
+                    repo.save_creature(game_id, *player, &creature(*player, "Mob", elements, 5)).unwrap();
+                    let ready = test::TestRequest::post().uri(&format!("/{}/ready", game_id))
+                        .insert_header(("Authorization", token.as_str())).to_request();
+                    assert!(test::call_service(app, ready).await.status().is_success());
+                }
+
+                let battle = repo.load_battle(game_id).unwrap().unwrap();
+                let (_, token) = &seats[battle.current];
+                let decision = test::TestRequest::post().uri(&format!("/{}/decision", game_id))
+                    .insert_header(("Authorization", token.as_str()))
+                    .set_json(Decision::Wait).to_request();
+                assert!(test::call_service(app, decision).await.status().is_success());
+                Instant::now()
+            }
+        };
+        let started = Instant::now();
+        let poll = async {
+            let poll = test::TestRequest::get().uri(&format!("/{}/poll?version=100&timeout=3", watched_id)).to_request();
+            let status = test::call_service(&app, poll).await.status();
+            (status, Instant::now())
+        };
+        let games = futures_util::future::join_all((0..GAMES).map(play));
+        let ((status, polled), finished) = futures_util::future::join(poll, games).await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(polled.duration_since(started) >= Duration::from_secs(3));
+        // Every game was played out while the poll of the watched game was still waiting
+        for finished in finished {
+            assert!(finished < polled, "a game waited for the long poll of another");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_reload_catalog_needs_the_admin_token() {
+        let config = Config { admin_token: Some("secret".to_string()), ..Config::default() };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .app_data(catalog())
+                .route("/admin/catalog/reload", web::post().to(handle_reload_catalog)),
+        ).await;
+
+        for token in [None, Some("secre"), Some("secret "), Some("SECRET")] {
+            let mut reload = test::TestRequest::post().uri("/admin/catalog/reload");
+            if let Some(token) = token {
+                reload = reload.insert_header(("Authorization", token));
+            }
+            assert_eq!(test::call_service(&app, reload.to_request()).await.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_websocket_needs_a_token() {
+        let repo = web::Data::new(MemoryRepository::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(repo.clone())
+                .app_data(web::Data::new(EventHub::new()))
+                .app_data(catalog())
+                .configure(routes::<MemoryRepository>),
+        ).await;
+
+        let create = test::TestRequest::post().uri("/create").set_json(json!({ "name": "Owner" })).to_request();
+        let created: Value = test::call_and_read_body_json(&app, create).await;
+        let game_id = created["game_id"].as_i64().unwrap();
+        let (owner, owner_token) = seat(&created);
+
+        let handshake = |token: &str| test::TestRequest::get()
+            .uri(&format!("/{}/ws?token={}", game_id, token))
+            .insert_header(("Upgrade", "websocket"))
+            .insert_header(("Connection", "Upgrade"))
+            .insert_header(("Sec-WebSocket-Version", "13"))
+            .insert_header(("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="))
+            .to_request();
+        assert_eq!(test::call_service(&app, handshake(&owner.to_string())).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(test::call_service(&app, handshake(&owner_token)).await.status(), StatusCode::SWITCHING_PROTOCOLS);
+    }
+
+    /// Reads the body of `/{game_id}/events` until it has sent `count` more events.
+    async fn read_events<B: actix_web::body::MessageBody + Unpin>(body: &mut B, count: usize) -> Vec<EventEnvelope> {
+        let mut events = Vec::new();
+        while events.len() < count {
+            let chunk = std::future::poll_fn(|cx| std::pin::Pin::new(&mut *body).poll_next(cx)).await;
+            let chunk = String::from_utf8(chunk.unwrap().ok().unwrap().to_vec()).unwrap();
+            if let Some(data) = chunk.lines().find_map(|line| line.strip_prefix("data: ")) {
+                events.push(serde_json::from_str(data).unwrap());
+            }
+        }
+        events
+    }
+
+    #[actix_web::test]
+    async fn test_event_stream_hides_opponent_creatures() {
+        let repo = web::Data::new(MemoryRepository::new());
+        let hub = web::Data::new(EventHub::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(repo.clone())
+                .app_data(hub.clone())
+                .app_data(catalog())
+                .configure(routes::<MemoryRepository>),
+        ).await;
+
+        let create = test::TestRequest::post().uri("/create").set_json(json!({ "name": "Owner" })).to_request();
+        let created: Value = test::call_and_read_body_json(&app, create).await;
+        let game_id = created["game_id"].as_i64().unwrap();
+        let (owner, owner_token) = seat(&created);
+        let join = test::TestRequest::post().uri(&format!("/{}/join", game_id)).set_json(json!({ "name": "Guest" })).to_request();
+        let (guest, _) = seat(&test::call_and_read_body_json(&app, join).await);
+        for (player_id, name) in [(owner, "Ember"), (guest, "Drop")] {
+            events::record(repo.get_ref(), &hub, game_id, Some(player_id), GameEvent::CreatureCreated { player_id, name: name.to_string() }).unwrap();
+        }
+        let created = |events: &[EventEnvelope]| -> Vec<(i64, String)> {
+            events.iter().filter_map(|envelope| match &envelope.event {
+                GameEvent::CreatureCreated { player_id, name } => Some((*player_id, name.clone())),
+                _ => None,
+            }).collect()
+        };
+
+        let spectator = test::TestRequest::get().uri(&format!("/{}/events", game_id)).to_request();
+        let mut spectator = test::call_service(&app, spectator).await.into_body();
+        assert_eq!(created(&read_events(&mut spectator, 4).await), vec![(owner, String::new()), (guest, String::new())]);
+
+        let player = test::TestRequest::get().uri(&format!("/{}/events", game_id))
+            .insert_header(("Authorization", owner_token.as_str())).to_request();
+        let mut player = test::call_service(&app, player).await.into_body();
+        assert_eq!(created(&read_events(&mut player, 4).await), vec![(owner, "Ember".to_string()), (guest, String::new())]);
+
+        // Live events are redacted like the backlog
+        events::record(repo.get_ref(), &hub, game_id, Some(guest), GameEvent::CreatureCreated { player_id: guest, name: "Puddle".to_string() }).unwrap();
+        assert_eq!(created(&read_events(&mut spectator, 1).await), vec![(guest, String::new())]);
+        assert_eq!(created(&read_events(&mut player, 1).await), vec![(guest, String::new())]);
+
+        // Neither a made-up token nor a player's public id gets in
+        for stranger in ["not a token".to_string(), owner.to_string()] {
+            let stranger = test::TestRequest::get().uri(&format!("/{}/events", game_id))
+                .insert_header(("Authorization", stranger)).to_request();
+            assert_eq!(test::call_service(&app, stranger).await.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_event_stream_follows_the_battle() {
+        let repo = web::Data::new(MemoryRepository::new());
+        let hub = web::Data::new(EventHub::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(repo.clone())
+                .app_data(hub.clone())
+                .app_data(catalog())
+                .configure(routes::<MemoryRepository>),
+        ).await;
+
+        let create = test::TestRequest::post().uri("/create").set_json(json!({ "name": "Solo", "bot": "greedy" })).to_request();
+        let created: Value = test::call_and_read_body_json(&app, create).await;
+        let game_id = created["game_id"].as_i64().unwrap();
+        let (owner, owner_token) = seat(&created);
+        let bot_id = repo.bots(game_id).unwrap()[0].0;
+
+        // A spectator watching from before the battle sees the bot's creatures unnamed
+        let spectator = test::TestRequest::get().uri(&format!("/{}/events", game_id)).to_request();
+        let mut spectator = test::call_service(&app, spectator).await.into_body();
+        let backlog = read_events(&mut spectator, repo.events_since(game_id, 0).unwrap().len()).await;
+        assert!(backlog.iter().any(|envelope| envelope.event == GameEvent::CreatureCreated { player_id: bot_id, name: String::new() }));
+
+        repo.save_creature(game_id, owner, &creature(owner, "Ember", vec![Element::Fire], 6)).unwrap();
+        for (uri, decision) in [("ready", None), ("decision", Some(Decision::Concede))] {
+            let request = test::TestRequest::post().uri(&format!("/{}/{}", game_id, uri))
+                .insert_header(("Authorization", owner_token.as_str()));
+            let request = match decision {
+                Some(decision) => request.set_json(decision),
+                None => request,
+            };
+            assert!(test::call_service(&app, request.to_request()).await.status().is_success());
+        }
+
+        // Every turn of the battle reaches them, up to its end
+        let count = repo.events_since(game_id, backlog.last().unwrap().id).unwrap().len();
+        let kinds: Vec<&str> = read_events(&mut spectator, count).await.iter().map(|envelope| envelope.event.kind()).collect();
+        assert!(kinds.contains(&"battle_started") && kinds.contains(&"turn_resolved"));
+        assert_eq!(kinds.last(), Some(&"game_over"));
+
+        // Then the fog is lifted
+        let late = GameEvent::CreatureCreated { player_id: bot_id, name: "Late".to_string() };
+        events::record(repo.get_ref(), &hub, game_id, Some(bot_id), late.clone()).unwrap();
+        assert_eq!(read_events(&mut spectator, 1).await[0].event, late);
+    }
+
+    /// Every number and string in `value`, as text.
+    fn scalars(value: &Value) -> Vec<String> {
+        match value {
+            Value::Array(values) => values.iter().flat_map(scalars).collect(),
+            Value::Object(values) => values.values().flat_map(scalars).collect(),
+            Value::Number(number) => vec![number.to_string()],
+            Value::String(string) => vec![string.clone()],
+            _ => Vec::new(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_spectator_stream_never_carries_a_token() {
+        let repo = web::Data::new(MemoryRepository::new());
+        let hub = web::Data::new(EventHub::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(repo.clone())
+                .app_data(hub.clone())
+                .app_data(catalog())
+                .configure(routes::<MemoryRepository>),
+        ).await;
+
+        let create = test::TestRequest::post().uri("/create").set_json(json!({ "name": "Solo", "bot": "greedy" })).to_request();
+        let created: Value = test::call_and_read_body_json(&app, create).await;
+        let game_id = created["game_id"].as_i64().unwrap();
+        let (owner, owner_token) = seat(&created);
+        repo.save_creature(game_id, owner, &creature(owner, "Ember", vec![Element::Fire], 6)).unwrap();
+        let ready = test::TestRequest::post().uri(&format!("/{}/ready", game_id))
+            .insert_header(("Authorization", owner_token.as_str())).to_request();
+        assert!(test::call_service(&app, ready).await.status().is_success());
+        // A few turns against the bot, then the player gives up
+        for decision in [Decision::Wait, Decision::Wait, Decision::Concede] {
+            if repo.load_battle(game_id).unwrap().unwrap().is_finished() {
+                break;
+            }
+            let decision = test::TestRequest::post().uri(&format!("/{}/decision", game_id))
+                .insert_header(("Authorization", owner_token.as_str()))
+                .set_json(decision).to_request();
+            assert!(test::call_service(&app, decision).await.status().is_success());
+        }
+
+        let count = repo.events_since(game_id, 0).unwrap().len();
+        let spectator = test::TestRequest::get().uri(&format!("/{}/events", game_id)).to_request();
+        let mut spectator = test::call_service(&app, spectator).await.into_body();
+        let events = read_events(&mut spectator, count).await;
+        assert!(events.iter().any(|envelope| matches!(envelope.event, GameEvent::PlayerJoined { player_id, .. } if player_id == owner)));
+
+        let values: Vec<String> = events.iter().flat_map(|envelope| scalars(&serde_json::to_value(envelope).unwrap())).collect();
+        for value in &values {
+            assert_eq!(repo.authenticate(game_id, value).unwrap(), None, "{} authenticates", value);
+            assert!(!value.contains(&owner_token));
+        }
+    }
+}
@@ -0,0 +1,20 @@
+pub mod models;
+pub mod agent;
+pub mod cli;
+pub mod config;
+pub mod db;
+pub mod repository;
+pub mod migrations;
+pub mod embedding;
+pub mod catalog;
+pub mod battle;
+pub mod bot;
+pub mod events;
+pub mod replay;
+pub mod ratings;
+pub mod rules;
+pub mod simulation;
+pub mod tournament;
+pub mod visibility;
+pub mod visualization;
+pub mod handlers;
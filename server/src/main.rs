@@ -1,25 +1,19 @@
 use actix_web::{web, App, HttpServer};
 use chrono::Local;
+use clap::Parser;
 use dotenv::dotenv;
-use handlers::{handle_check_creatures, handle_create, handle_create_creature, handle_join, handle_poll};
-use log::LevelFilter;
-use std::{env, fs, path::Path, sync::Mutex};
-use rusqlite::Connection;
-pub mod models;
-pub mod db;
-pub mod embedding;
-mod handlers;
-use db::initialize_database;
-
-fn configure_logging() -> Result<(), Box<dyn std::error::Error>> {
-    let log_dir = Path::new("./logs");
-    if !log_dir.exists() {
-        fs::create_dir_all(log_dir)?;
-    }
-
-    let log_file_path = format!("{}/{}.log", log_dir.display(), Local::now().format("%Y-%m-%d_%H-%M-%S"));
+use battllm_server::catalog::SharedCatalog;
+use battllm_server::cli::{self, Cli, Command};
+use battllm_server::config::Config;
+use battllm_server::events::EventHub;
+use battllm_server::handlers;
+use battllm_server::repository::{GameRepository, SqliteRepository};
+use std::{env, fs};
+use battllm_server::db::{self, initialize_database};
 
-    fern::Dispatch::new()
+/// Logs to stderr, and to a new file of the log directory when `to_file`.
+fn configure_logging(config: &Config, to_file: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut dispatch = fern::Dispatch::new()
         .format(|out, message, record| {
             out.finish(format_args!(
                 "[{} {}] {}",
@@ -28,39 +22,164 @@ fn configure_logging() -> Result<(), Box<dyn std::error::Error>> {
                 message
             ))
         })
-        .level(LevelFilter::Info) // Log all INFO-level messages or higher
-        .chain(std::io::stderr()) // Log to stderr
-        .chain(fern::log_file(log_file_path)?) // Log to the file
-        .apply()?;
+        .level(config.log_level())
+        .chain(std::io::stderr()); // Log to stderr
+
+    if to_file {
+        let log_dir = config.log_dir.as_path();
+        if !log_dir.exists() {
+            fs::create_dir_all(log_dir)?;
+        }
+        let log_file_path = format!("{}/{}.log", log_dir.display(), Local::now().format("%Y-%m-%d_%H-%M-%S"));
+        dispatch = dispatch.chain(fern::log_file(log_file_path)?); // Log to the file
+    }
+    dispatch.apply()?;
 
     Ok(())
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
+/// Reloads the catalog whenever the process receives SIGHUP.
+#[cfg(unix)]
+fn spawn_reload_on_hangup(catalog: web::Data<SharedCatalog>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    actix_web::rt::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                log::warn!("Catalog reload on SIGHUP is unavailable: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            if let Err(e) = catalog.reload() {
+                log::error!("Failed to reload catalog, keeping the current one: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn spawn_reload_on_hangup(_catalog: web::Data<SharedCatalog>) {}
+
+fn main() {
     dotenv().ok();
-    configure_logging().expect("Failed to configure logging");
 
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set in .env");
-    let schema_file_path = env::var("SCHEMA_FILE_PATH").expect("SCHEMA_FILE_PATH must be set in .env");
+    let cli = Cli::parse();
+    let config = match Config::load(&cli.settings.to_args(), env::vars()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let command = cli.command.unwrap_or(Command::Serve);
+    configure_logging(&config, command == Command::Serve).expect("Failed to configure logging");
 
+    let code = match command {
+        Command::Serve => run_serve(config),
+        Command::Migrate => cli::run_migrate(&config),
+        Command::SeedCatalog => {
+            let runtime = tokio::runtime::Runtime::new().expect("Failed to start the async runtime");
+            runtime.block_on(cli::run_seed_catalog(&config))
+        }
+        Command::Simulate(args) => {
+            let runtime = tokio::runtime::Runtime::new().expect("Failed to start the async runtime");
+            runtime.block_on(cli::run_simulate(&config, &args))
+        }
+        Command::Tournament(args) => {
+            let runtime = tokio::runtime::Runtime::new().expect("Failed to start the async runtime");
+            runtime.block_on(cli::run_tournament(&config, &args))
+        }
+        Command::InspectGame { game_id, events } => cli::run_with_repository(
+            &config,
+            |repo, out| cli::inspect_game(repo, game_id, events, out),
+            None,
+        ),
+        Command::ExportReplay { game_id, output } => cli::run_with_repository(
+            &config,
+            |repo, out| cli::export_replay(repo, game_id, out),
+            output.as_deref(),
+        ),
+        Command::Replay { file } => cli::run_replay(&config, &file),
+        Command::RecomputeRatings => cli::run_with_repository(&config, cli::recompute_ratings, None),
+        Command::Agent { game_id, token, server } => {
+            let runtime = tokio::runtime::Runtime::new().expect("Failed to start the async runtime");
+            runtime.block_on(cli::run_agent(&config, game_id, &token, server.as_deref()))
+        }
+    };
+    std::process::exit(code);
+}
+
+/// `serve`: opens the database and serves the API until stopped. Returns the exit code.
+fn run_serve(config: Config) -> i32 {
     log::info!("Starting server with logging enabled");
 
-    let conn = Connection::open(database_url).expect("Failed to connect to the database.");
-    initialize_database(&conn, &schema_file_path);
+    let database_url = match config.database_url() {
+        Ok(database_url) => database_url.to_string(),
+        Err(e) => {
+            log::error!("{}", e);
+            return 2;
+        }
+    };
+
+    let catalog = match SharedCatalog::load(&config.catalog_path, &config.storage_dir) {
+        Ok(catalog) => web::Data::new(catalog),
+        Err(e) => {
+            log::error!("Failed to load {}: {}", config.catalog_path.display(), e);
+            return 1;
+        }
+    };
+
+    // The PostgreSQL client blocks, so the database is opened before the async runtime starts
+    let system = actix_web::rt::System::new();
+    let result = if config.is_postgres() {
+        let repository = match cli::open_postgres(&config) {
+            Ok((repository, _)) => repository,
+            Err(e) => {
+                log::error!("Failed to open the PostgreSQL database: {}", e);
+                return 1;
+            }
+        };
+        system.block_on(serve(config, repository, catalog))
+    } else {
+        let pool = db::open_pool(&database_url, config.pool_size).expect("Failed to connect to the database.");
+        initialize_database(&pool.get().expect("Failed to connect to the database."));
+        system.block_on(serve(config, SqliteRepository::new(pool), catalog))
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("Server failed: {}", e);
+            1
+        }
+    }
+}
+
+/// Serves the API with the games stored in `repository`.
+async fn serve<R: GameRepository>(config: Config, repository: R, catalog: web::Data<SharedCatalog>) -> std::io::Result<()> {
+    spawn_reload_on_hangup(catalog.clone());
 
-    let data = web::Data::new(Mutex::new(conn));
+    let address = (config.host.clone(), config.port);
+    let workers = config.workers;
+    let config = web::Data::new(config);
+    let data = web::Data::new(repository);
+    let hub = web::Data::new(EventHub::new());
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
+            .app_data(config.clone())
             .app_data(data.clone())
-            .route("/create", web::post().to(handle_create))
-            .route("/{game_id}/join", web::post().to(handle_join))
-            .route("/{game_id}/poll", web::get().to(handle_poll))
-            .route("/{game_id}/creatures", web::get().to(handle_check_creatures))
-            .route("/{game_id}/creatures/create", web::post().to(handle_create_creature))
-    })
-    .bind(("127.0.0.1", 8080))?
-    .run()
-    .await
+            .app_data(catalog.clone())
+            .app_data(hub.clone())
+            .configure(handlers::routes::<R>)
+    });
+    let server = match workers {
+        Some(workers) => server.workers(workers),
+        None => server,
+    };
+    log::info!("Listening on {}:{}", address.0, address.1);
+    server.bind(address)?.run().await
 }
@@ -0,0 +1,141 @@
+// src/migrations.rs
+use rusqlite::{Connection, OptionalExtension, Result};
+
+/// Every migration in `database/migrations`, in order. Never edit one that has shipped, add a new one.
+pub const MIGRATIONS: &[(u32, &str, &str)] = &[
+    (1, "initial", include_str!("../database/migrations/0001_initial.sql")),
+    (2, "battle", include_str!("../database/migrations/0002_battle.sql")),
+    (3, "creatures", include_str!("../database/migrations/0003_creatures.sql")),
+    (4, "seats", include_str!("../database/migrations/0004_seats.sql")),
+    (5, "ratings", include_str!("../database/migrations/0005_ratings.sql")),
+    (6, "tokens", include_str!("../database/migrations/0006_tokens.sql")),
+];
+
+/// Version the schema is at once every migration is applied.
+pub fn head() -> u32 {
+    MIGRATIONS.last().map_or(0, |(version, _, _)| *version)
+}
+
+/// Returns the version of the schema, `0` for an empty database.
+pub fn schema_version(conn: &Connection) -> Result<u32> {
+    let tracked: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version')",
+        [],
+        |row| row.get(0),
+    )?;
+    if tracked {
+        let version: Option<u32> = conn.query_row("SELECT MAX(version) FROM schema_version", [], |row| row.get(0))?;
+        return Ok(version.unwrap_or(0));
+    }
+
+    // Databases created from the single schema file predate versioning, and only ever had the first one
+    let legacy = conn
+        .query_row("SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'Game'", [], |row| row.get::<_, String>(0))
+        .optional()?
+        .is_some();
+    Ok(if legacy { 1 } else { 0 })
+}
+
+/// Applies every migration the database is missing, each in its own transaction.
+/// Returns the versions applied.
+pub fn migrate(conn: &Connection) -> Result<Vec<u32>> {
+    let current = schema_version(conn)?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );"
+    )?;
+    if current > 0 && !has_version(conn, current)? {
+        // Record the legacy schema so it isn't detected again
+        conn.execute("INSERT INTO schema_version (version, name) VALUES (?1, 'legacy')", [current])?;
+    }
+
+    let mut applied = Vec::new();
+    for (version, name, sql) in MIGRATIONS.iter().filter(|(version, _, _)| *version > current) {
+        let tx = conn.unchecked_transaction()?;
+        tx.execute_batch(sql)?;
+        tx.execute("INSERT INTO schema_version (version, name) VALUES (?1, ?2)", rusqlite::params![version, name])?;
+        tx.commit()?;
+        log::info!("Applied migration {} ({})", version, name);
+        applied.push(*version);
+    }
+    Ok(applied)
+}
+
+fn has_version(conn: &Connection, version: u32) -> Result<bool> {
+    conn.query_row("SELECT EXISTS(SELECT 1 FROM schema_version WHERE version = ?1)", [version], |row| row.get(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table)).unwrap();
+        stmt.query_map([], |row| row.get(1)).unwrap().collect::<Result<Vec<String>>>().unwrap()
+    }
+
+    #[test]
+    fn test_migrations_are_numbered_in_order() {
+        for (i, (version, _, _)) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(*version as usize, i + 1);
+        }
+    }
+
+    #[test]
+    fn test_migrate_empty_database() {
+        let conn = Connection::open_in_memory().unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), 0);
+
+        let applied = migrate(&conn).unwrap();
+        assert_eq!(applied, (1..=head()).collect::<Vec<_>>());
+        assert_eq!(schema_version(&conn).unwrap(), head());
+        assert!(columns(&conn, "Game").contains(&"version".to_string()));
+        assert!(columns(&conn, "Ability").contains(&"position".to_string()));
+
+        // Nothing left to apply
+        assert!(migrate(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_migrate_legacy_database() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0].2).unwrap();
+        conn.execute_batch(
+            "INSERT INTO Game (phase) VALUES ('setup');
+             INSERT INTO Player (name, game_id) VALUES ('owner', 1);
+             INSERT INTO Ledger (game_id, player_id, command, payload) VALUES (1, 1, 'player_ready', '{}');"
+        ).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), 1);
+
+        assert_eq!(migrate(&conn).unwrap(), (2..=head()).collect::<Vec<_>>());
+        assert_eq!(schema_version(&conn).unwrap(), head());
+
+        // Existing rows survive, with the defaults of the new columns
+        let (phase, version): (String, i64) = conn
+            .query_row("SELECT phase, version FROM Game WHERE id = 1", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!((phase.as_str(), version), ("setup", 1));
+        let command: String = conn.query_row("SELECT command FROM Ledger WHERE id = 1", [], |row| row.get(0)).unwrap();
+        assert_eq!(command, "player_ready");
+        let token: String = conn.query_row("SELECT token FROM Player WHERE id = 1", [], |row| row.get(0)).unwrap();
+        assert_eq!(token.len(), 32);
+
+        // Ledger now accepts events without a player
+        conn.execute("INSERT INTO Ledger (game_id, command, payload) VALUES (1, 'game_over', '{}')", []).unwrap();
+    }
+
+    #[test]
+    fn test_failed_migration_is_rolled_back() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0].2).unwrap();
+        // Clashes with the table migration 2 creates after adding its columns
+        conn.execute("CREATE TABLE Ledger_new (id INTEGER)", []).unwrap();
+
+        assert!(migrate(&conn).is_err());
+        assert_eq!(schema_version(&conn).unwrap(), 1);
+        assert!(!columns(&conn, "Game").contains(&"battle".to_string()));
+    }
+}
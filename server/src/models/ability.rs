@@ -1,14 +1,15 @@
 use std::str::FromStr as _;
 
 use serde::{Deserialize, Serialize};
-use crate::embedding;
+use crate::{catalog::Catalog, embedding};
 use super::creature::{Attribute, Element};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct SmolAbility {
-    name: String,
-    base_value: u8,
-    modifier: f32
+    pub name: String,
+    pub base_value: u8,
+    pub modifier: f32,
+    pub category: AbilityCategory
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -18,16 +19,94 @@ pub struct Ability {
     pub base_damage: u16,
     pub available: (u8, u8), // (current, max)
     pub elements: Vec<Element>,
-    pub modifiers: Vec<(i8, Attribute)>
+    pub modifiers: Vec<(i8, Attribute)>,
+    #[serde(default)]
+    pub category: AbilityCategory,
+    #[serde(default)]
+    pub target: Target
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
 pub enum AbilityCategory {
+    #[default]
     Attack,
     Defense,
     Utility,
 }
 
+impl AbilityCategory {
+    /// Who an ability of this category is used on.
+    /// Attacks and utilities (e.g. Reduce) hit the enemy, defenses (e.g. Augment) the caster.
+    pub fn target(&self) -> Target {
+        match self {
+            AbilityCategory::Attack => Target::Enemy,
+            AbilityCategory::Defense => Target::Caster,
+            AbilityCategory::Utility => Target::Enemy,
+        }
+    }
+}
+
+/// The creature an ability is used on
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    #[default]
+    Enemy,
+    #[serde(rename = "self")]
+    Caster,
+}
+
+/// Minimum similarity for an element to count as mentioned by an ability.
+const ELEMENT_THRESHOLD: f32 = 0.25;
+/// Additional elements must score within this margin of the best match.
+const ELEMENT_MARGIN: f32 = 0.05;
+/// An ability carries at most as many elements as a creature may have.
+const MAX_ABILITY_ELEMENTS: usize = 2;
+
+/// Picks the elements of an ability from its element search results (best first).
+///
+/// Every match above the threshold and close enough to the best one is kept, so dual-element
+/// descriptions get both. Elements the creature doesn't have are dropped, except the neutral
+/// Physical and Mental which anyone can use. Falls back to Physical when nothing is left.
+pub fn resolve_elements(
+    matches: &[(String, f32)],
+    available: &[Element],
+    creature_elements: &[Element]
+) -> Vec<Element> {
+    let candidates: Vec<(Element, f32)> = matches
+        .iter()
+        .filter_map(|(name, score)| Element::from_str(name.trim()).ok().map(|element| (element, *score)))
+        .filter(|(element, _)| available.contains(element))
+        .collect();
+
+    let best = match candidates.first() {
+        Some((_, score)) => *score,
+        None => return vec![Element::Physical],
+    };
+
+    let mut elements: Vec<Element> = Vec::new();
+    for (element, score) in candidates {
+        if score < ELEMENT_THRESHOLD || score < best - ELEMENT_MARGIN {
+            break;
+        }
+        if !element.is_neutral() && !creature_elements.contains(&element) {
+            log::debug!("Dropping element {:?}: the creature is {:?}", element, creature_elements);
+            continue;
+        }
+        if !elements.contains(&element) {
+            elements.push(element);
+        }
+        if elements.len() == MAX_ABILITY_ELEMENTS {
+            break;
+        }
+    }
+
+    if elements.is_empty() {
+        elements.push(Element::Physical);
+    }
+    elements
+}
+
 /// Request to create an ability
 /// Entirely creative. This is the request to be matched with our static list
 #[derive(Debug, Deserialize, Clone)]
@@ -40,41 +119,131 @@ impl AbilityRequest {
     /// Matches the ability with the static list of abilities using embeddings
     pub async fn fill(
         &self,
-        abilities: &[SmolAbility],
-        elements: &[Element]
-    ) -> Ability {
+        catalog: &Catalog,
+        creature_elements: &[Element]
+    ) -> Result<Ability, String> {
         println!("Filling ability: {:?}", self);
         let query_embedding = embedding::embed(
             format!("{}: {}", self.name, self.description).as_str()
-        ).await.unwrap();
+        ).await.map_err(|e| format!("Failed to embed ability `{}`: {}", self.name, e))?;
         // Use a Vector Database
-        let ability_name = embedding::search(
+        let ability_name = match embedding::search(
             embedding::Query::Vector(&query_embedding),
-            embedding::Category::Ability,
+            embedding::Category::ABILITY,
             1
-        ).await.first().unwrap().0.clone();
+        ).await.map_err(|e| format!("Failed to search the ability templates: {}", e))?.first() {
+            Some((name, _)) => name.clone(),
+            None => return Err("No ability templates are embedded".to_string()),
+        };
 
-        let element_name = embedding::search(
+        let element_matches = embedding::search(
             embedding::Query::Vector(&query_embedding),
-            embedding::Category::Element,
-            1
-        ).await.first().unwrap().0.clone().trim().to_lowercase();
+            embedding::Category::ELEMENT,
+            catalog.elements.len()
+        ).await.map_err(|e| format!("Failed to search the elements: {}", e))?;
 
-        println!("Ability: {:?}. Element: {:?}", ability_name, element_name);
+        println!("Ability: {:?}. Elements: {:?}", ability_name, element_matches);
 
-        let ability = abilities.iter().find(|a| a.name == ability_name).unwrap();
+        let ability = catalog.abilities.iter().find(|a| a.name == ability_name)
+            .ok_or_else(|| format!("Matched ability template `{}` is not in the catalog", ability_name))?;
 
-        let element = Element::from_str(&element_name).unwrap();
+        let elements = resolve_elements(&element_matches, &catalog.elements, creature_elements);
 
-        println!("Found element: {:?}", element);
+        println!("Found elements: {:?}", elements);
 
-        Ability {
+        Ok(Ability {
             name: self.name.clone(),
             description: self.description.clone(),
             base_damage: ability.base_value as u16,
             available: (10, 10),
-            elements: vec![element],
-            modifiers: vec![]
-        }
+            elements,
+            modifiers: vec![],
+            category: ability.category,
+            target: ability.category.target()
+        })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(scores: &[(&str, f32)]) -> Vec<(String, f32)> {
+        scores.iter().map(|(name, score)| (name.to_string(), *score)).collect()
+    }
+
+    fn available() -> Vec<Element> {
+        vec![Element::Physical, Element::Mental, Element::Fire, Element::Water, Element::Earth, Element::Air]
+    }
+
+    #[test]
+    fn test_templates_have_categories() {
+        let available = std::fs::read_to_string(crate::catalog::CATALOG_PATH).unwrap();
+        let available: serde_json::Value = serde_json::from_str(&available).unwrap();
+        let templates: Vec<SmolAbility> = serde_json::from_value(available["Ability"].clone()).unwrap();
+
+        let category = |name: &str| templates.iter().find(|t| t.name == name).unwrap().category;
+        assert_eq!(category("Basic Attack"), AbilityCategory::Attack);
+        assert_eq!(category("Elemental Attack"), AbilityCategory::Attack);
+        assert_eq!(category("Augment"), AbilityCategory::Defense);
+        assert_eq!(category("Reduce"), AbilityCategory::Utility);
+    }
+
+    #[test]
+    fn test_category_target() {
+        assert_eq!(AbilityCategory::Attack.target(), Target::Enemy);
+        assert_eq!(AbilityCategory::Defense.target(), Target::Caster);
+        assert_eq!(AbilityCategory::Utility.target(), Target::Enemy);
+        assert_eq!(serde_json::to_string(&Target::Caster).unwrap(), "\"self\"");
+    }
+
+    #[test]
+    fn test_stored_ability_without_category() {
+        // Creatures stored before abilities had a category still load
+        let ability: Ability = serde_json::from_str(
+            r#"{"name":"Ember Strike","description":"","base_damage":8,"available":[10,10],"elements":["fire"],"modifiers":[]}"#
+        ).unwrap();
+        assert_eq!(ability.category, AbilityCategory::Attack);
+        assert_eq!(ability.target, Target::Enemy);
+    }
+
+    #[test]
+    fn test_resolve_single_element() {
+        let found = matches(&[("Fire", 0.45), ("Earth", 0.30), ("Air", 0.28)]);
+        assert_eq!(resolve_elements(&found, &available(), &[Element::Fire, Element::Earth]), vec![Element::Fire]);
+    }
+
+    #[test]
+    fn test_resolve_dual_element() {
+        let found = matches(&[("Fire", 0.42), ("Air", 0.40), ("Water", 0.39), ("Earth", 0.2)]);
+        let elements = resolve_elements(&found, &available(), &[Element::Fire, Element::Air, Element::Water]);
+        assert_eq!(elements, vec![Element::Fire, Element::Air]);
+    }
+
+    #[test]
+    fn test_resolve_drops_foreign_elements() {
+        // A water move on a fire creature keeps only what the creature can use
+        let found = matches(&[("Water", 0.45), ("Physical", 0.42), ("Fire", 0.2)]);
+        assert_eq!(resolve_elements(&found, &available(), &[Element::Fire]), vec![Element::Physical]);
+
+        let found = matches(&[("Water", 0.45)]);
+        assert_eq!(resolve_elements(&found, &available(), &[Element::Fire]), vec![Element::Physical]);
+    }
+
+    #[test]
+    fn test_resolve_neutral_elements() {
+        let found = matches(&[("mental", 0.38), ("Air", 0.2)]);
+        assert_eq!(resolve_elements(&found, &available(), &[Element::Air]), vec![Element::Mental]);
+
+        // Nothing clearly mentioned
+        let found = matches(&[("Fire", 0.1)]);
+        assert_eq!(resolve_elements(&found, &available(), &[Element::Fire]), vec![Element::Physical]);
+        assert_eq!(resolve_elements(&[], &available(), &[Element::Fire]), vec![Element::Physical]);
+    }
+
+    #[test]
+    fn test_resolve_ignores_unavailable_elements() {
+        let found = matches(&[("Lightning", 0.5), ("Earth", 0.40)]);
+        assert_eq!(resolve_elements(&found, &[Element::Earth], &[Element::Earth]), vec![Element::Earth]);
+    }
+}
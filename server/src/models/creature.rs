@@ -1,8 +1,9 @@
-use std::{collections::HashMap, fs, str::FromStr};
+use std::{collections::HashMap, str::FromStr};
 use serde::{Deserialize, Deserializer, Serialize};
-use super::ability::{Ability, AbilityRequest, SmolAbility};
+use crate::catalog::Catalog;
+use super::ability::{Ability, AbilityRequest};
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum Element {
     Physical,
@@ -13,6 +14,13 @@ pub enum Element {
     Air,
 }
 
+impl Element {
+    /// Physical and Mental aren't tied to an affinity, so any creature can use them.
+    pub fn is_neutral(&self) -> bool {
+        matches!(self, Element::Physical | Element::Mental)
+    }
+}
+
 impl FromStr for Element {
     type Err = String;
 
@@ -52,7 +60,9 @@ pub enum Attribute {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Creature {
-    //pub id: String,
+    /// Row id, `0` until the creature is stored
+    #[serde(default)]
+    pub id: i64,
     pub owner: i64,
     pub name: String,
     pub description: String,
@@ -73,7 +83,10 @@ pub struct State {
 /// Request to create a creature
 #[derive(Debug, Deserialize, Clone)]
 pub struct CreateRequest {
+    // Taken from the path and the token when sent to the server
+    #[serde(default)]
     pub game_id: i64,
+    #[serde(default)]
     pub user_id: i64,
     pub name: String,
     pub description: String,
@@ -111,27 +124,20 @@ impl CreateRequest {
         Ok(())
     }
 
-    pub async fn transform(&self) -> Creature {
-
-        // Reads the available static list of abilities and elements
-        let available = fs::read_to_string("database/available.json").expect("Failed to read abilities.json");
-        let available: serde_json::Value = serde_json::from_str(&available).unwrap();
-
-        let abilities = available["Ability"].as_array().unwrap().iter().map(|e| serde_json::from_value::<SmolAbility>(e.clone()).unwrap()).collect::<Vec<SmolAbility>>();
-
-        let elements = available["Element"].as_array().unwrap().iter().map(|e| serde_json::from_value::<Element>(e.clone()).unwrap()).collect::<Vec<Element>>();
-
+    /// Matches the requested abilities against the catalog's static list
+    pub async fn transform(&self, catalog: &Catalog) -> Result<Creature, String> {
         let mut filled_abilities = Vec::new();
         // TODO: Multi-threading this probably
         for ability in self.abilities.iter() {
             let result = ability.fill(
-                &abilities,
-                &elements
-            ).await;
+                catalog,
+                &self.elements
+            ).await?;
             filled_abilities.push(result);
         }
 
-        let creature = Creature {
+        Ok(Creature {
+            id: 0,
             owner: self.user_id,
             name: self.name.clone(),
             description: self.description.clone(),
@@ -140,24 +146,27 @@ impl CreateRequest {
             attributes: self.attributes.clone(),
             elements: self.elements.clone(),
             abilities: filled_abilities
-        };
-
-        creature
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
+    use crate::catalog::{SharedCatalog, CATALOG_PATH};
+    use crate::config::Config;
 
     #[tokio::test]
+    #[ignore = "needs the embedding API, set OPENAI_API_KEY"]
     async fn test_fill_abilities() {
+        let catalog = SharedCatalog::load(CATALOG_PATH, Config::default().storage_dir).expect("Failed to load catalog");
 
         let example = fs::read_to_string("examples/creature.json").expect("Failed to read creature.json");
         let example = serde_json::from_str::<CreateRequest>(&example).unwrap();
         example.validate().unwrap();
         println!("Validated");
-        let creature = example.transform().await;
+        let creature = example.transform(&catalog.current()).await.unwrap();
         println!("{:?}", creature);
 
     }
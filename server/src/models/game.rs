@@ -1,14 +1,30 @@
 use serde::{Deserialize, Serialize};
 
+use crate::battle::BattleState;
+use crate::bot::BotKind;
+use crate::ratings::RatingKind;
+use crate::rules::RuleSet;
+
 use super::creature::Creature;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GameState {
-    pub game_id: String,
+    pub game_id: i64,
+    pub version: i64,
     pub phase: GamePhase,
-    pub current_turn: Option<String>,
-    pub players: Vec<String>,
+    /// Player whose decision is awaited
+    pub current_turn: Option<i64>,
+    pub players: Vec<PlayerState>,
     pub entities: Vec<Creature>,
+    pub battle: Option<BattleState>,
+    pub rules: RuleSet,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlayerState {
+    pub id: i64,
+    pub name: String,
+    pub ready: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
@@ -21,10 +37,38 @@ pub enum GamePhase {
 
 #[derive(Deserialize)]
 pub struct PollRequest {
-    pub timestamp: i64,
+    /// Version of the state the client has, `0` for none
+    #[serde(default)]
+    pub version: i64,
+    /// Seconds to wait for a change
+    pub timeout: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct LeaderboardRequest {
+    /// `player`, `agent` or `creature`, players when omitted
+    pub kind: Option<RatingKind>,
+    /// Ratings to list
+    pub limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateGameRequest {
+    pub name: String,
+    #[serde(default)]
+    pub rules: RuleSet,
+    /// Plays against this bot, which joins right away with a team of its own
+    #[serde(default)]
+    pub bot: Option<BotKind>,
+    /// The agent playing for the owner, e.g. the model it runs, rated apart from the player
+    #[serde(default)]
+    pub agent: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct NameRequest {
     pub name: String,
+    /// The agent playing for the player, as for `CreateGameRequest`
+    #[serde(default)]
+    pub agent: Option<String>,
 }
\ No newline at end of file
@@ -2,4 +2,4 @@ pub mod room;
 pub mod game;
 pub mod creature;
 pub mod player;
-mod ability;
\ No newline at end of file
+pub mod ability;
\ No newline at end of file
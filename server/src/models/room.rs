@@ -3,10 +3,21 @@ use serde::Serialize;
 #[derive(Serialize)]
 pub struct Created {
     pub game_id: i64,
-    pub token: i64,
+    /// Public id of the owner, as found in states and events
+    pub player_id: i64,
+    /// Secret of the owner, sent under `Authorization`
+    pub token: String,
 }
 
 #[derive(Serialize)]
 pub struct Joined {
-    pub token: i64,
-}
\ No newline at end of file
+    /// Public id of the player, as found in states and events
+    pub player_id: i64,
+    /// Secret of the player, sent under `Authorization`
+    pub token: String,
+}
+
+#[derive(Serialize)]
+pub struct CreatureCreated {
+    pub id: i64,
+}
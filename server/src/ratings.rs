@@ -0,0 +1,344 @@
+// src/ratings.rs
+//! Glicko ratings of the players, the agents and the creature designs of finished games.
+//! Ratings are derived from the ledger, so they can always be recomputed from it, see `recompute`.
+use serde::{Deserialize, Serialize};
+use std::f64::consts::{LN_10, PI};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::db::DbError;
+use crate::events::GameEvent;
+use crate::models::creature::Creature;
+use crate::repository::GameRepository;
+
+/// Rating of a newcomer.
+pub const INITIAL_RATING: f64 = 1500.0;
+/// Deviation of a newcomer, how unsure its rating is.
+pub const INITIAL_DEVIATION: f64 = 350.0;
+/// Ratings never get surer than this, so they keep following changes in strength.
+const MIN_DEVIATION: f64 = 30.0;
+const Q: f64 = LN_10 / 400.0;
+
+/// What a rating is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RatingKind {
+    /// A player, by name. Names aren't authenticated, anyone may play under any of them
+    Player,
+    /// Who played for a player: a bot, or the agent the player declared
+    Agent,
+    /// A creature design, by name and fingerprint
+    Creature,
+}
+
+impl RatingKind {
+    pub const ALL: [RatingKind; 3] = [RatingKind::Player, RatingKind::Agent, RatingKind::Creature];
+
+    /// Name of the kind, as stored.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RatingKind::Player => "player",
+            RatingKind::Agent => "agent",
+            RatingKind::Creature => "creature",
+        }
+    }
+}
+
+impl fmt::Display for RatingKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for RatingKind {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        RatingKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == input)
+            .ok_or_else(|| format!("Unknown rating kind `{}`, expected player, agent or creature", input))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rating {
+    pub kind: RatingKind,
+    pub name: String,
+    pub rating: f64,
+    pub deviation: f64,
+    pub games: u32,
+    pub wins: u32,
+}
+
+impl Rating {
+    pub fn new(kind: RatingKind, name: &str) -> Self {
+        Rating { kind, name: name.to_string(), rating: INITIAL_RATING, deviation: INITIAL_DEVIATION, games: 0, wins: 0 }
+    }
+}
+
+/// Who played one side of a finished game.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Side {
+    pub player: String,
+    pub agent: Option<String>,
+    /// Designs of the creatures, see `design`
+    pub creatures: Vec<String>,
+}
+
+/// The outcome of a finished game, as rated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameResult {
+    pub game_id: i64,
+    /// The owner's side first
+    pub sides: [Side; 2],
+    /// Index of the winning side
+    pub winner: usize,
+}
+
+impl GameResult {
+    /// The result of the game from its ledger and creatures, `None` until it is over.
+    pub fn from_ledger(repo: &(impl GameRepository + ?Sized), game_id: i64) -> Result<Option<Self>, DbError> {
+        let events = repo.events_since(game_id, 0)?;
+        let winner_player_id = events.iter().find_map(|envelope| match envelope.event {
+            GameEvent::GameOver { winner_player_id } => Some(winner_player_id),
+            _ => None,
+        });
+        let Some(winner_player_id) = winner_player_id else { return Ok(None) };
+
+        let players = repo.get_players(game_id)?;
+        if players.len() != 2 {
+            return Err(DbError::InvalidGameState);
+        }
+        let agent_of = |player_id: i64| {
+            events.iter().rev().find_map(|envelope| match &envelope.event {
+                GameEvent::BotJoined { player_id: id, bot } if *id == player_id => Some(format!("bot:{}", bot)),
+                GameEvent::AgentJoined { player_id: id, agent } if *id == player_id => Some(agent.clone()),
+                _ => None,
+            })
+        };
+
+        let mut sides = Vec::new();
+        for (player_id, name) in &players {
+            sides.push(Side {
+                player: name.clone(),
+                agent: agent_of(*player_id),
+                creatures: repo.get_creatures(game_id, *player_id)?.iter().map(design).collect(),
+            });
+        }
+        let winner = players.iter().position(|(player_id, _)| *player_id == winner_player_id).ok_or(DbError::InvalidGameState)?;
+        Ok(Some(GameResult { game_id, sides: sides.try_into().expect("two players"), winner }))
+    }
+
+    /// Everything rated in the game.
+    pub fn subjects(&self) -> Vec<(RatingKind, String)> {
+        let mut subjects = Vec::new();
+        for side in &self.sides {
+            subjects.push((RatingKind::Player, side.player.clone()));
+            subjects.extend(side.agent.iter().map(|agent| (RatingKind::Agent, agent.clone())));
+            subjects.extend(side.creatures.iter().map(|creature| (RatingKind::Creature, creature.clone())));
+        }
+        subjects.sort_by(|a, b| (a.0.as_str(), &a.1).cmp(&(b.0.as_str(), &b.1)));
+        subjects.dedup();
+        subjects
+    }
+}
+
+/// Name of the creature's design: its name and a fingerprint of what it fights with,
+/// so creatures built alike in different games share a rating.
+pub fn design(creature: &Creature) -> String {
+    let mut attributes: Vec<(String, u8)> = creature.attributes.iter().map(|(attribute, value)| (format!("{:?}", attribute), *value)).collect();
+    attributes.sort();
+    let abilities: Vec<_> = creature.abilities
+        .iter()
+        .map(|ability| (&ability.name, ability.base_damage, ability.available.1, &ability.elements, &ability.modifiers, ability.category))
+        .collect();
+    let fingerprint = serde_json::to_string(&(creature.max_health, attributes, &creature.elements, abilities)).expect("creatures always serialize");
+
+    // FNV-1a, stable across builds unlike the std hasher
+    let hash = fingerprint.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3));
+    format!("{} #{:08x}", creature.name, hash >> 32)
+}
+
+fn g(deviation: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * Q * Q * deviation * deviation / (PI * PI)).sqrt()
+}
+
+/// Glicko update of `rating` after a game against `opponent`, a `(rating, deviation)`, scoring 1 for a win.
+fn update(rating: &Rating, opponent: (f64, f64), score: f64) -> Rating {
+    let g = g(opponent.1);
+    let expected = 1.0 / (1.0 + 10f64.powf(-g * (rating.rating - opponent.0) / 400.0));
+    let d_squared = 1.0 / (Q * Q * g * g * expected * (1.0 - expected));
+    let precision = 1.0 / (rating.deviation * rating.deviation) + 1.0 / d_squared;
+
+    Rating {
+        rating: rating.rating + Q / precision * g * (score - expected),
+        deviation: (1.0 / precision).sqrt().max(MIN_DEVIATION),
+        games: rating.games + 1,
+        wins: rating.wins + (score > 0.5) as u32,
+        ..rating.clone()
+    }
+}
+
+/// Rating and deviation of a team of creatures, as one opponent.
+fn combined(ratings: &[&Rating]) -> (f64, f64) {
+    let count = ratings.len().max(1) as f64;
+    let rating = ratings.iter().map(|rating| rating.rating).sum::<f64>() / count;
+    let variance = ratings.iter().map(|rating| rating.deviation * rating.deviation).sum::<f64>() / count;
+    (rating, variance.sqrt())
+}
+
+/// The new ratings of everything rated in the game. `current` holds the ratings before it, those
+/// missing start fresh. A player or agent only meets the one on the other side, or the other
+/// player when that side has no agent. Each creature meets the other team as a whole.
+/// Whatever played on both sides isn't rated.
+pub fn rate(result: &GameResult, current: &[Rating]) -> Vec<Rating> {
+    let find = |kind: RatingKind, name: &str| {
+        current.iter().find(|rating| rating.kind == kind && rating.name == name).cloned().unwrap_or_else(|| Rating::new(kind, name))
+    };
+    let players = result.sides.each_ref().map(|side| find(RatingKind::Player, &side.player));
+    let agents = result.sides.each_ref().map(|side| side.agent.as_ref().map(|agent| find(RatingKind::Agent, agent)));
+    let creatures = result.sides.each_ref().map(|side| {
+        side.creatures.iter().map(|creature| find(RatingKind::Creature, creature)).collect::<Vec<_>>()
+    });
+
+    let mut rated = Vec::new();
+    for side in 0..2 {
+        let other = 1 - side;
+        let score = if result.winner == side { 1.0 } else { 0.0 };
+        let opponent = |rating: &Rating| (rating.rating, rating.deviation);
+
+        if players[side].name != players[other].name {
+            rated.push(update(&players[side], opponent(&players[other]), score));
+        }
+        if let Some(agent) = &agents[side] {
+            let against = agents[other].as_ref().unwrap_or(&players[other]);
+            if against.kind != RatingKind::Agent || against.name != agent.name {
+                rated.push(update(agent, opponent(against), score));
+            }
+        }
+        let team = combined(&creatures[other].iter().collect::<Vec<_>>());
+        for (i, creature) in creatures[side].iter().enumerate() {
+            // The same design twice in a team is rated once
+            let repeated = creatures[side][..i].iter().any(|earlier| earlier.name == creature.name);
+            if !repeated && !creatures[other].iter().any(|mirror| mirror.name == creature.name) {
+                rated.push(update(creature, team, score));
+            }
+        }
+    }
+    rated
+}
+
+/// Rates the game if it is over and wasn't rated yet. Returns whether it was rated now.
+pub fn rate_finished(repo: &(impl GameRepository + ?Sized), game_id: i64) -> Result<bool, DbError> {
+    match GameResult::from_ledger(repo, game_id)? {
+        Some(result) => repo.rate_game(&result),
+        None => Ok(false),
+    }
+}
+
+/// Forgets every rating and rates every finished game again, in the order they finished.
+/// Returns how many games were rated.
+pub fn recompute(repo: &(impl GameRepository + ?Sized)) -> Result<usize, DbError> {
+    repo.clear_ratings()?;
+    let mut rated = 0;
+    for game_id in repo.finished_games()? {
+        if rate_finished(repo, game_id)? {
+            rated += 1;
+        }
+    }
+    Ok(rated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battle::tests::creature;
+    use crate::models::creature::Element;
+    use crate::repository::MemoryRepository;
+    use crate::rules::RuleSet;
+
+    fn side(player: &str, agent: Option<&str>, creatures: &[&str]) -> Side {
+        Side { player: player.to_string(), agent: agent.map(str::to_string), creatures: creatures.iter().map(|name| name.to_string()).collect() }
+    }
+
+    fn rating<'a>(ratings: &'a [Rating], kind: RatingKind, name: &str) -> Option<&'a Rating> {
+        ratings.iter().find(|rating| rating.kind == kind && rating.name == name)
+    }
+
+    #[test]
+    fn test_rate() {
+        let result = GameResult {
+            game_id: 1,
+            sides: [side("Ann", Some("gpt-4o"), &["Ember #1", "Pebble #2"]), side("Bob", None, &["Drop #3"])],
+            winner: 0,
+        };
+        let rated = rate(&result, &[]);
+        assert_eq!(rated.len(), 6);
+
+        let ann = rating(&rated, RatingKind::Player, "Ann").unwrap();
+        let bob = rating(&rated, RatingKind::Player, "Bob").unwrap();
+        assert!(ann.rating > INITIAL_RATING && bob.rating < INITIAL_RATING);
+        assert!((ann.rating - INITIAL_RATING - (INITIAL_RATING - bob.rating)).abs() < 1e-9);
+        assert!(ann.deviation < INITIAL_DEVIATION);
+        assert_eq!((ann.games, ann.wins, bob.games, bob.wins), (1, 1, 1, 0));
+        // The agent met the player on the other side
+        assert!(rating(&rated, RatingKind::Agent, "gpt-4o").unwrap().rating > INITIAL_RATING);
+        assert!(rating(&rated, RatingKind::Creature, "Drop #3").unwrap().rating < INITIAL_RATING);
+
+        // Beating a much stronger opponent is worth more
+        let strong = Rating { rating: 1900.0, deviation: 50.0, ..Rating::new(RatingKind::Player, "Bob") };
+        let upset = rate(&result, &[strong]);
+        assert!(rating(&upset, RatingKind::Player, "Ann").unwrap().rating > ann.rating);
+
+        // Mirror matches rate nothing of what is on both sides
+        let mirror = GameResult { sides: [side("Ann", Some("bot:greedy"), &["Ember #1"]), side("Ann", Some("bot:greedy"), &["Ember #1"])], ..result };
+        assert!(rate(&mirror, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_design() {
+        let ember = creature(1, "Ember", vec![Element::Fire], 6);
+        assert!(design(&ember).starts_with("Ember #"));
+        // Who owns it and where it is stored don't matter, what it fights with does
+        assert_eq!(design(&ember), design(&Creature { id: 7, owner: 2, description: "Another".to_string(), ..ember.clone() }));
+        assert_ne!(design(&ember), design(&creature(1, "Ember", vec![Element::Fire], 5)));
+    }
+
+    #[test]
+    fn test_recompute_from_ledger() {
+        let repo = MemoryRepository::new();
+        let play = |winner: usize| {
+            let (game_id, owner) = repo.create_game("Ann", &RuleSet::default(), "v1").unwrap();
+            let (owner, guest) = (owner.id, repo.join_game(game_id, "Bob").unwrap().id);
+            repo.append_ledger(game_id, Some(guest), &GameEvent::AgentJoined { player_id: guest, agent: "gpt-4o".to_string() }).unwrap();
+            repo.save_creature(game_id, owner, &creature(owner, "Ember", vec![Element::Fire], 6)).unwrap();
+            repo.save_creature(game_id, guest, &creature(guest, "Drop", vec![Element::Water], 4)).unwrap();
+            assert!(!rate_finished(&repo, game_id).unwrap());
+
+            let winner_player_id = [owner, guest][winner];
+            repo.append_ledger(game_id, None, &GameEvent::GameOver { winner_player_id }).unwrap();
+            assert!(rate_finished(&repo, game_id).unwrap());
+            // Counted once
+            assert!(!rate_finished(&repo, game_id).unwrap());
+        };
+        play(0);
+        play(1);
+        play(0);
+
+        let players = repo.leaderboard(RatingKind::Player, 10).unwrap();
+        assert_eq!(players.iter().map(|rating| rating.name.as_str()).collect::<Vec<_>>(), vec!["Ann", "Bob"]);
+        assert_eq!((players[0].games, players[0].wins), (3, 2));
+        assert_eq!(repo.leaderboard(RatingKind::Agent, 10).unwrap()[0].name, "gpt-4o");
+        assert_eq!(repo.leaderboard(RatingKind::Creature, 1).unwrap().len(), 1);
+
+        assert_eq!(recompute(&repo).unwrap(), 3);
+        assert_eq!(repo.leaderboard(RatingKind::Player, 10).unwrap(), players);
+    }
+
+    #[test]
+    fn test_parse_kind() {
+        assert_eq!("creature".parse(), Ok(RatingKind::Creature));
+        assert!("team".parse::<RatingKind>().is_err());
+    }
+}
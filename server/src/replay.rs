@@ -0,0 +1,249 @@
+// src/replay.rs
+use serde::{Deserialize, Serialize};
+use std::{fs, hash::Hasher, io};
+use thiserror::Error;
+
+use crate::battle::{BattleError, BattleState, Decision};
+use crate::models::{creature::Creature, game::GameState};
+use crate::rules::RuleSet;
+
+/// Bumped whenever the layout of replay files changes.
+pub const REPLAY_FORMAT: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum ReplayError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Unsupported replay format {0}, expected {REPLAY_FORMAT}")]
+    Format(u32),
+    #[error("The battle has not started")]
+    NotStarted,
+    #[error("Turn {turn}: {error}")]
+    Battle { turn: usize, error: BattleError },
+    #[error("Desync at turn {turn}: expected state {expected}, got {actual}")]
+    Desync { turn: usize, expected: String, actual: String },
+}
+
+/// Everything needed to play a battle again: the teams as they entered it and every decision.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub format: u32,
+    pub game_id: i64,
+    pub rules: RuleSet,
+    pub seed: u64,
+    /// Version of the catalog the creatures were created with
+    pub catalog_version: String,
+    pub teams: Vec<ReplayTeam>,
+    /// Hash of the battle before the first decision
+    pub initial_hash: String,
+    pub decisions: Vec<ReplayDecision>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayTeam {
+    pub player_id: i64,
+    pub name: String,
+    pub creatures: Vec<Creature>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayDecision {
+    pub player_id: i64,
+    pub decision: Decision,
+    /// Hash of the battle once the decision is resolved
+    pub state_hash: String,
+}
+
+/// A short hash of `bytes`, stable across platforms and releases.
+pub fn fingerprint(bytes: &[u8]) -> String {
+    let mut hasher = fnv::FnvHasher::default();
+    hasher.write(bytes);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Hash of a battle. Maps are serialized with sorted keys, so equal states always hash the same.
+pub fn state_hash(battle: &BattleState) -> String {
+    let canonical = serde_json::to_value(battle).expect("battles always serialize");
+    fingerprint(canonical.to_string().as_bytes())
+}
+
+impl Replay {
+    /// Records the battle of a game along with the `(player_id, decision)` made in it.
+    /// Fails with `Desync` when the decisions don't lead to the stored battle, e.g. one is missing.
+    pub fn record(state: &GameState, catalog_version: &str, decisions: &[(i64, Decision)]) -> Result<Self, ReplayError> {
+        let battle = state.battle.as_ref().ok_or(ReplayError::NotStarted)?;
+        let start = battle.restart().map_err(|error| ReplayError::Battle { turn: 0, error })?;
+        let states = start.replay(decisions).map_err(|error| ReplayError::Battle { turn: 0, error })?;
+        let (expected, actual) = (state_hash(battle), state_hash(states.last().unwrap_or(&start)));
+        if expected != actual {
+            return Err(ReplayError::Desync { turn: decisions.len(), expected, actual });
+        }
+
+        let teams = start.teams
+            .iter()
+            .map(|team| ReplayTeam {
+                player_id: team.player_id,
+                name: state.players
+                    .iter()
+                    .find(|player| player.id == team.player_id)
+                    .map(|player| player.name.clone())
+                    .unwrap_or_default(),
+                creatures: team.fighters.iter().map(|fighter| fighter.creature.clone()).collect(),
+            })
+            .collect();
+        let decisions = decisions
+            .iter()
+            .zip(&states[1..])
+            .map(|((player_id, decision), after)| ReplayDecision {
+                player_id: *player_id,
+                decision: decision.clone(),
+                state_hash: state_hash(after),
+            })
+            .collect();
+
+        Ok(Replay {
+            format: REPLAY_FORMAT,
+            game_id: state.game_id,
+            rules: state.rules.clone(),
+            seed: start.seed,
+            catalog_version: catalog_version.to_string(),
+            teams,
+            initial_hash: state_hash(&start),
+            decisions,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Self, ReplayError> {
+        let replay: Replay = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if replay.format != REPLAY_FORMAT {
+            return Err(ReplayError::Format(replay.format));
+        }
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), ReplayError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Plays the battle again, checking every state against the recorded hashes.
+    /// Returns the final state.
+    pub fn verify(&self) -> Result<BattleState, ReplayError> {
+        let teams = self.teams.iter().map(|team| (team.player_id, team.creatures.clone())).collect();
+        let mut battle = BattleState::new(self.seed, teams).map_err(|error| ReplayError::Battle { turn: 0, error })?;
+        check(0, &self.initial_hash, &battle)?;
+
+        for (turn, recorded) in self.decisions.iter().enumerate() {
+            let turn = turn + 1;
+            let team = battle.team_of(recorded.player_id).ok_or(ReplayError::Battle { turn, error: BattleError::NotYourTurn })?;
+            battle.apply(team, recorded.decision.clone()).map_err(|error| ReplayError::Battle { turn, error })?;
+            check(turn, &recorded.state_hash, &battle)?;
+        }
+        Ok(battle)
+    }
+}
+
+fn check(turn: usize, expected: &str, battle: &BattleState) -> Result<(), ReplayError> {
+    let actual = state_hash(battle);
+    if actual != expected {
+        return Err(ReplayError::Desync { turn, expected: expected.to_string(), actual });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battle::tests::battle;
+    use crate::models::game::{GamePhase, PlayerState};
+
+    /// A finished game and the decisions made in it.
+    fn finished() -> (GameState, Vec<(i64, Decision)>) {
+        let mut battle = battle(9);
+        let decisions = vec![
+            (1, Decision::Action { ability: 1 }),
+            (2, Decision::Action { ability: 0 }),
+            (1, Decision::Swap { creature: 1 }),
+            (2, Decision::Concede),
+        ];
+        for (player_id, decision) in &decisions {
+            battle.apply(battle.team_of(*player_id).unwrap(), decision.clone()).unwrap();
+        }
+        let state = GameState {
+            game_id: 3,
+            version: 1,
+            phase: GamePhase::Finish,
+            current_turn: None,
+            players: vec![
+                PlayerState { id: 1, name: "first".to_string(), ready: true },
+                PlayerState { id: 2, name: "second".to_string(), ready: true },
+            ],
+            entities: vec![],
+            battle: Some(battle),
+            rules: RuleSet::default(),
+        };
+        (state, decisions)
+    }
+
+    fn replay() -> Replay {
+        let (state, decisions) = finished();
+        Replay::record(&state, "catalog", &decisions).unwrap()
+    }
+
+    #[test]
+    fn test_state_hash_is_stable() {
+        assert_eq!(state_hash(&battle(1)), state_hash(&battle(1)));
+        assert_ne!(state_hash(&battle(1)), state_hash(&battle(2)));
+        assert_eq!(fingerprint(b""), "cbf29ce484222325");
+    }
+
+    #[test]
+    fn test_verify_reproduces_the_battle() {
+        let replay = replay();
+        assert_eq!(replay.teams[1].name, "second");
+        assert_eq!(replay.decisions.len(), 4);
+
+        let battle = replay.verify().unwrap();
+        assert_eq!(battle.winner, Some(0));
+    }
+
+    #[test]
+    fn test_verify_detects_desync() {
+        let mut replay = replay();
+        replay.decisions[1].decision = Decision::Wait;
+        match replay.verify() {
+            Err(ReplayError::Desync { turn, .. }) => assert_eq!(turn, 2),
+            other => panic!("Expected a desync, got {:?}", other),
+        }
+
+        let mut replay = self::replay();
+        replay.seed += 1;
+        assert!(matches!(replay.verify(), Err(ReplayError::Desync { turn: 0, .. })));
+    }
+
+    #[test]
+    fn test_record_detects_desync() {
+        // A decision missing from the ledger leads to another battle than the stored one
+        let (state, mut decisions) = finished();
+        decisions.pop();
+        assert!(matches!(Replay::record(&state, "catalog", &decisions), Err(ReplayError::Desync { turn: 3, .. })));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("battllm_replay_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let replay = replay();
+        replay.save(path).unwrap();
+        let loaded = Replay::load(path).unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&replay).unwrap());
+
+        let mut future = replay.clone();
+        future.format = REPLAY_FORMAT + 1;
+        future.save(path).unwrap();
+        assert!(matches!(Replay::load(path), Err(ReplayError::Format(_))));
+        fs::remove_file(path).unwrap();
+    }
+}
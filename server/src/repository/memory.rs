@@ -0,0 +1,267 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+
+use super::{new_token, BattleUpdate, GameRepository, Seat};
+use crate::battle::BattleState;
+use crate::db::{self, DbError};
+use crate::events::{EventEnvelope, GameEvent};
+use crate::models::creature::Creature;
+use crate::models::game::{GameState, PlayerState};
+use crate::ratings::{self, GameResult, Rating, RatingKind};
+use crate::rules::RuleSet;
+
+/// Games kept in memory, lost with the process. For tests and local experiments.
+#[derive(Default)]
+pub struct MemoryRepository {
+    store: Mutex<Store>,
+}
+
+/// Ids are shared by every game, like the rowids of the SQLite tables.
+#[derive(Default)]
+struct Store {
+    games: HashMap<i64, StoredGame>,
+    last_game_id: i64,
+    last_player_id: i64,
+    last_creature_id: i64,
+    last_event_id: i64,
+    ratings: HashMap<(RatingKind, String), Rating>,
+    rated_games: HashSet<i64>,
+}
+
+struct StoredGame {
+    phase: String,
+    version: i64,
+    seed: u64,
+    rules: RuleSet,
+    catalog_version: String,
+    battle: Option<BattleState>,
+    players: Vec<(i64, String)>,
+    /// Player id of each token
+    tokens: HashMap<String, i64>,
+    creatures: Vec<Creature>,
+    ledger: Vec<EventEnvelope>,
+}
+
+impl StoredGame {
+    /// Marks the game as changed.
+    fn touch(&mut self) {
+        self.version += 1;
+    }
+}
+
+impl Store {
+    /// Appends an event to the game's ledger, numbered after every event of every game.
+    fn append(&mut self, game_id: i64, event: &GameEvent) -> Result<EventEnvelope, DbError> {
+        let id = self.last_event_id + 1;
+        let game = self.games.get_mut(&game_id).ok_or(DbError::NotFound)?;
+        let envelope = EventEnvelope {
+            id,
+            game_id,
+            timestamp: chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            event: event.clone(),
+        };
+        game.ledger.push(envelope.clone());
+        game.touch();
+        self.last_event_id = id;
+        Ok(envelope)
+    }
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn store(&self) -> MutexGuard<'_, Store> {
+        // Calls never leave a game half-changed, so a panic elsewhere doesn't spoil the data
+        self.store.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Runs `f` on the game.
+    fn with_game<T>(&self, game_id: i64, f: impl FnOnce(&mut StoredGame) -> Result<T, DbError>) -> Result<T, DbError> {
+        f(self.store().games.get_mut(&game_id).ok_or(DbError::NotFound)?)
+    }
+}
+
+impl GameRepository for MemoryRepository {
+    fn create_game(&self, name: &str, rules: &RuleSet, catalog_version: &str) -> Result<(i64, Seat), DbError> {
+        let mut store = self.store();
+        store.last_game_id += 1;
+        store.last_player_id += 1;
+        let game_id = store.last_game_id;
+        let owner = Seat { id: store.last_player_id, token: new_token() };
+        store.games.insert(game_id, StoredGame {
+            phase: "setup".to_string(),
+            version: 1,
+            seed: rand::random(),
+            rules: rules.clone(),
+            catalog_version: catalog_version.to_string(),
+            battle: None,
+            players: vec![(owner.id, name.to_string())],
+            tokens: HashMap::from([(owner.token.clone(), owner.id)]),
+            creatures: Vec::new(),
+            ledger: Vec::new(),
+        });
+        Ok((game_id, owner))
+    }
+
+    fn join_game(&self, game_id: i64, name: &str) -> Result<Seat, DbError> {
+        let mut store = self.store();
+        let seat = Seat { id: store.last_player_id + 1, token: new_token() };
+        let game = store.games.get_mut(&game_id).ok_or(DbError::NotFound)?;
+        if game.players.len() != 1 {
+            return Err(DbError::GameFull);
+        }
+        game.players.push((seat.id, name.to_string()));
+        game.tokens.insert(seat.token.clone(), seat.id);
+        game.touch();
+        store.last_player_id = seat.id;
+        Ok(seat)
+    }
+
+    fn poll_game_state(&self, game_id: i64, version: i64) -> Result<bool, DbError> {
+        self.with_game(game_id, |game| Ok(game.version > version))
+    }
+
+    fn load_state(&self, game_id: i64) -> Result<GameState, DbError> {
+        let ready = self.ready_players(game_id)?;
+        self.with_game(game_id, |game| {
+            let players = game.players
+                .iter()
+                .map(|(id, name)| PlayerState { id: *id, name: name.clone(), ready: ready.contains(id) })
+                .collect::<Vec<_>>();
+            // Grouped by player like the SQLite state
+            let mut entities = Vec::new();
+            for player in &players {
+                entities.extend(game.creatures.iter().filter(|creature| creature.owner == player.id).cloned());
+            }
+
+            Ok(GameState {
+                game_id,
+                version: game.version,
+                phase: db::game_phase(&game.phase, players.len()),
+                current_turn: db::current_turn(game.battle.as_ref()),
+                players,
+                entities,
+                battle: game.battle.clone(),
+                rules: game.rules.clone(),
+            })
+        })
+    }
+
+    fn get_players(&self, game_id: i64) -> Result<Vec<(i64, String)>, DbError> {
+        // Like a query, an unknown game simply has no players
+        Ok(self.store().games.get(&game_id).map(|game| game.players.clone()).unwrap_or_default())
+    }
+
+    fn authenticate(&self, game_id: i64, token: &str) -> Result<Option<i64>, DbError> {
+        Ok(self.store().games.get(&game_id).and_then(|game| game.tokens.get(token).copied()))
+    }
+
+    fn save_creature(&self, game_id: i64, player_id: i64, creature: &Creature) -> Result<i64, DbError> {
+        let mut store = self.store();
+        let creature_id = store.last_creature_id + 1;
+        let game = store.games.get_mut(&game_id).ok_or(DbError::NotFound)?;
+        if !game.players.iter().any(|(id, _)| *id == player_id) {
+            return Err(DbError::NotFound);
+        }
+        game.creatures.push(Creature { id: creature_id, owner: player_id, ..creature.clone() });
+        game.touch();
+        store.last_creature_id = creature_id;
+        Ok(creature_id)
+    }
+
+    fn get_creatures(&self, game_id: i64, player_id: i64) -> Result<Vec<Creature>, DbError> {
+        Ok(self.store().games.get(&game_id)
+            .map(|game| game.creatures.iter().filter(|creature| creature.owner == player_id).cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn get_seed(&self, game_id: i64) -> Result<u64, DbError> {
+        self.with_game(game_id, |game| Ok(game.seed))
+    }
+
+    fn get_catalog_version(&self, game_id: i64) -> Result<String, DbError> {
+        self.with_game(game_id, |game| Ok(game.catalog_version.clone()))
+    }
+
+    fn save_battle(&self, game_id: i64, phase: &str, battle: &BattleState) -> Result<(), DbError> {
+        self.with_game(game_id, |game| {
+            game.phase = phase.to_string();
+            game.battle = Some(battle.clone());
+            game.touch();
+            Ok(())
+        })
+    }
+
+    fn load_battle(&self, game_id: i64) -> Result<Option<BattleState>, DbError> {
+        self.with_game(game_id, |game| Ok(game.battle.clone()))
+    }
+
+    fn update_battle(
+        &self,
+        game_id: i64,
+        update: &mut dyn FnMut(Option<BattleState>) -> Option<BattleUpdate>,
+    ) -> Result<Vec<EventEnvelope>, DbError> {
+        // The store stays locked throughout, so updates take turns
+        let mut store = self.store();
+        let game = store.games.get_mut(&game_id).ok_or(DbError::NotFound)?;
+        let BattleUpdate { phase, battle, events } = match update(game.battle.clone()) {
+            Some(update) => update,
+            None => return Ok(Vec::new()),
+        };
+        game.phase = phase.to_string();
+        game.battle = Some(battle);
+        game.touch();
+        events.iter().map(|(_, event)| store.append(game_id, event)).collect()
+    }
+
+    fn append_ledger(&self, game_id: i64, _player_id: Option<i64>, event: &GameEvent) -> Result<EventEnvelope, DbError> {
+        self.store().append(game_id, event)
+    }
+
+    fn events_since(&self, game_id: i64, after: i64) -> Result<Vec<EventEnvelope>, DbError> {
+        Ok(self.store().games.get(&game_id)
+            .map(|game| game.ledger.iter().filter(|envelope| envelope.id > after).cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn finished_games(&self) -> Result<Vec<i64>, DbError> {
+        let store = self.store();
+        let mut finished: Vec<(i64, i64)> = store.games
+            .iter()
+            .flat_map(|(game_id, game)| game.ledger.iter().filter(|envelope| matches!(envelope.event, GameEvent::GameOver { .. })).map(|envelope| (envelope.id, *game_id)))
+            .collect();
+        finished.sort_unstable();
+        Ok(finished.into_iter().map(|(_, game_id)| game_id).collect())
+    }
+
+    fn rate_game(&self, result: &GameResult) -> Result<bool, DbError> {
+        let mut store = self.store();
+        if !store.rated_games.insert(result.game_id) {
+            return Ok(false);
+        }
+        let current: Vec<Rating> = result.subjects()
+            .into_iter()
+            .filter_map(|key| store.ratings.get(&key).cloned())
+            .collect();
+        for rating in ratings::rate(result, &current) {
+            store.ratings.insert((rating.kind, rating.name.clone()), rating);
+        }
+        Ok(true)
+    }
+
+    fn leaderboard(&self, kind: RatingKind, limit: usize) -> Result<Vec<Rating>, DbError> {
+        let mut leaderboard: Vec<Rating> = self.store().ratings.values().filter(|rating| rating.kind == kind).cloned().collect();
+        leaderboard.sort_by(|a, b| b.rating.total_cmp(&a.rating).then_with(|| a.name.cmp(&b.name)));
+        leaderboard.truncate(limit);
+        Ok(leaderboard)
+    }
+
+    fn clear_ratings(&self) -> Result<(), DbError> {
+        let mut store = self.store();
+        store.ratings.clear();
+        store.rated_games.clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,365 @@
+//! Storage of games behind one trait, so the handlers and the battle logic don't depend on SQLite.
+pub mod memory;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod sqlite;
+
+pub use memory::MemoryRepository;
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresRepository;
+pub use sqlite::SqliteRepository;
+
+use crate::battle::{BattleState, Decision};
+use crate::bot::BotKind;
+use crate::db::DbError;
+use crate::events::{EventEnvelope, GameEvent};
+use crate::models::creature::Creature;
+use crate::models::game::GameState;
+use crate::ratings::{GameResult, Rating, RatingKind};
+use crate::rules::RuleSet;
+
+/// A player's place in a game. `id` is public, it names the player in states and events.
+/// `token` is the player's secret, sent under `Authorization` and only ever returned to them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Seat {
+    pub id: i64,
+    pub token: String,
+}
+
+/// A new random player token, too long to guess.
+pub fn new_token() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+/// A change of the battle, stored along with its events by `GameRepository::update_battle`.
+pub struct BattleUpdate {
+    /// Phase of the game once the battle is stored
+    pub phase: &'static str,
+    pub battle: BattleState,
+    /// `(player_id, event)` to append to the ledger, in order
+    pub events: Vec<(Option<i64>, GameEvent)>,
+}
+
+/// Everything the server stores about games. Calls block, handlers run them on blocking threads.
+/// Each call is atomic on its own. A game or player that doesn't exist is `DbError::NotFound`.
+pub trait GameRepository: Send + Sync + 'static {
+    /// Creates a game played with `rules` and the catalog of `catalog_version`.
+    /// Returns its id and the owner's seat.
+    fn create_game(&self, name: &str, rules: &RuleSet, catalog_version: &str) -> Result<(i64, Seat), DbError>;
+
+    /// Adds the second player to the game and returns their seat.
+    fn join_game(&self, game_id: i64, name: &str) -> Result<Seat, DbError>;
+
+    /// Whether the game changed past `version`.
+    fn poll_game_state(&self, game_id: i64, version: i64) -> Result<bool, DbError>;
+
+    /// Returns the whole state of the game.
+    fn load_state(&self, game_id: i64) -> Result<GameState, DbError>;
+
+    /// Returns the `(id, name)` of every player in the game, owner first.
+    fn get_players(&self, game_id: i64) -> Result<Vec<(i64, String)>, DbError>;
+
+    /// Returns the id of the player of `token` in the game, `None` if no player of the game has it.
+    fn authenticate(&self, game_id: i64, token: &str) -> Result<Option<i64>, DbError>;
+
+    /// Stores a creature of `player_id` and returns its id.
+    fn save_creature(&self, game_id: i64, player_id: i64, creature: &Creature) -> Result<i64, DbError>;
+
+    /// Returns the creatures of `player_id`, in the order they were created.
+    fn get_creatures(&self, game_id: i64, player_id: i64) -> Result<Vec<Creature>, DbError>;
+
+    /// Returns the seed the game's battle is played with.
+    fn get_seed(&self, game_id: i64) -> Result<u64, DbError>;
+
+    /// Returns the version of the catalog the game is played with.
+    fn get_catalog_version(&self, game_id: i64) -> Result<String, DbError>;
+
+    /// Stores the battle along with the phase of the game.
+    fn save_battle(&self, game_id: i64, phase: &str, battle: &BattleState) -> Result<(), DbError>;
+
+    /// Returns the battle of the game, if it has started.
+    fn load_battle(&self, game_id: i64) -> Result<Option<BattleState>, DbError>;
+
+    /// Hands the battle, if it has started, to `update` and stores the battle and events it returns,
+    /// all at once. Updates of the same game take turns, each one sees the battle the previous one stored.
+    /// Nothing changes when `update` returns `None`. Returns the events appended.
+    fn update_battle(
+        &self,
+        game_id: i64,
+        update: &mut dyn FnMut(Option<BattleState>) -> Option<BattleUpdate>,
+    ) -> Result<Vec<EventEnvelope>, DbError>;
+
+    /// Appends an event to the game's ledger and returns it with its sequence number.
+    fn append_ledger(&self, game_id: i64, player_id: Option<i64>, event: &GameEvent) -> Result<EventEnvelope, DbError>;
+
+    /// Returns every event of the game recorded after the event `after`, oldest first.
+    fn events_since(&self, game_id: i64, after: i64) -> Result<Vec<EventEnvelope>, DbError>;
+
+    /// Returns the ids of the finished games, in the order they finished.
+    fn finished_games(&self) -> Result<Vec<i64>, DbError>;
+
+    /// Counts the game in the ratings of everything rated in it, see `ratings::rate`, all at once.
+    /// Returns `false` without changing anything if the game was already counted.
+    fn rate_game(&self, result: &GameResult) -> Result<bool, DbError>;
+
+    /// Returns the `limit` best ratings of `kind`, best first.
+    fn leaderboard(&self, kind: RatingKind, limit: usize) -> Result<Vec<Rating>, DbError>;
+
+    /// Forgets every rating and which games were counted, before recomputing them.
+    fn clear_ratings(&self) -> Result<(), DbError>;
+
+    /// Returns the last `limit` events of the game, oldest first.
+    fn recent_events(&self, game_id: i64, limit: usize) -> Result<Vec<EventEnvelope>, DbError> {
+        let mut events = self.events_since(game_id, 0)?;
+        let skipped = events.len().saturating_sub(limit);
+        Ok(events.split_off(skipped))
+    }
+
+    /// Returns every `(player_id, decision)` made in the game's battle, in order.
+    fn get_decisions(&self, game_id: i64) -> Result<Vec<(i64, Decision)>, DbError> {
+        Ok(self.events_since(game_id, 0)?
+            .into_iter()
+            .filter_map(|envelope| match envelope.event {
+                GameEvent::DecisionMade { player_id, decision } => Some((player_id, decision)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Returns the players played by a bot, and which one.
+    fn bots(&self, game_id: i64) -> Result<Vec<(i64, BotKind)>, DbError> {
+        Ok(self.events_since(game_id, 0)?
+            .into_iter()
+            .filter_map(|envelope| match envelope.event {
+                GameEvent::BotJoined { player_id, bot } => Some((player_id, bot)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Returns the players who declared themselves ready to battle.
+    fn ready_players(&self, game_id: i64) -> Result<Vec<i64>, DbError> {
+        let mut ready: Vec<i64> = self.events_since(game_id, 0)?
+            .into_iter()
+            .filter_map(|envelope| match envelope.event {
+                GameEvent::PlayerReady { player_id } => Some(player_id),
+                _ => None,
+            })
+            .collect();
+        ready.sort_unstable();
+        ready.dedup();
+        Ok(ready)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::battle::tests::creature;
+    use crate::models::creature::Element;
+    use crate::db::tests::TempDatabase;
+    use crate::models::game::GamePhase;
+    use crate::ratings::Side;
+    use std::sync::Barrier;
+
+    /// Runs the same checks against any implementation, so they all behave alike.
+    pub(crate) fn check_repository(repo: &impl GameRepository) {
+        let (game_id, owner_seat) = repo.create_game("Owner", &RuleSet::default(), "v1").unwrap();
+        let owner = owner_seat.id;
+        assert_eq!(repo.get_catalog_version(game_id).unwrap(), "v1");
+        assert_eq!(repo.authenticate(game_id, &owner_seat.token).unwrap(), Some(owner));
+        // The public id is not a token
+        assert_eq!(repo.authenticate(game_id, &owner.to_string()).unwrap(), None);
+        assert_eq!(repo.authenticate(game_id, "").unwrap(), None);
+        assert_eq!(repo.load_state(game_id).unwrap().phase, GamePhase::Waiting);
+
+        // The version moves on every change
+        let version = repo.load_state(game_id).unwrap().version;
+        assert!(!repo.poll_game_state(game_id, version).unwrap());
+        let guest_seat = repo.join_game(game_id, "Guest").unwrap();
+        let guest = guest_seat.id;
+        assert_ne!(guest_seat.token, owner_seat.token);
+        assert_eq!(repo.authenticate(game_id, &guest_seat.token).unwrap(), Some(guest));
+        assert!(repo.poll_game_state(game_id, version).unwrap());
+        assert_eq!(repo.get_players(game_id).unwrap(), vec![(owner, "Owner".to_string()), (guest, "Guest".to_string())]);
+        assert!(matches!(repo.join_game(game_id, "Third"), Err(DbError::GameFull)));
+        assert!(matches!(repo.join_game(game_id + 1000, "Nobody"), Err(DbError::NotFound)));
+        assert!(matches!(repo.poll_game_state(game_id + 1000, 0), Err(DbError::NotFound)));
+
+        // Creatures keep their abilities in order and get an id
+        let first = repo.save_creature(game_id, owner, &creature(owner, "Ember", vec![Element::Fire], 6)).unwrap();
+        let second = repo.save_creature(game_id, owner, &creature(owner, "Pebble", vec![Element::Earth], 4)).unwrap();
+        assert!(matches!(repo.save_creature(game_id, guest + 1000, &creature(guest, "Drop", vec![Element::Water], 4)), Err(DbError::NotFound)));
+        let creatures = repo.get_creatures(game_id, owner).unwrap();
+        assert_eq!(creatures.iter().map(|creature| creature.id).collect::<Vec<_>>(), vec![first, second]);
+        assert_eq!(
+            serde_json::to_value(&creatures[0].abilities).unwrap(),
+            serde_json::to_value(creature(owner, "Ember", vec![Element::Fire], 6).abilities).unwrap(),
+        );
+        assert!(repo.get_creatures(game_id, guest).unwrap().is_empty());
+        assert_eq!(repo.load_state(game_id).unwrap().phase, GamePhase::Creation);
+
+        // The ledger numbers events in order
+        let ready = repo.append_ledger(game_id, Some(guest), &GameEvent::PlayerReady { player_id: guest }).unwrap();
+        let again = repo.append_ledger(game_id, Some(guest), &GameEvent::PlayerReady { player_id: guest }).unwrap();
+        assert!(again.id > ready.id);
+        assert_eq!(repo.ready_players(game_id).unwrap(), vec![guest]);
+        assert_eq!(repo.events_since(game_id, ready.id).unwrap(), vec![again.clone()]);
+        assert_eq!(repo.recent_events(game_id, 1).unwrap(), vec![again]);
+        assert_eq!(repo.recent_events(game_id, 10).unwrap().len(), 2);
+        assert!(repo.bots(game_id).unwrap().is_empty());
+        repo.append_ledger(game_id, Some(guest), &GameEvent::BotJoined { player_id: guest, bot: BotKind::Greedy }).unwrap();
+        assert_eq!(repo.bots(game_id).unwrap(), vec![(guest, BotKind::Greedy)]);
+
+        // Battles round-trip along with the phase
+        assert!(repo.load_battle(game_id).unwrap().is_none());
+        let battle = BattleState::new(repo.get_seed(game_id).unwrap(), vec![
+            (owner, repo.get_creatures(game_id, owner).unwrap()),
+            (guest, vec![creature(guest, "Drop", vec![Element::Water], 4)]),
+        ]).unwrap();
+        repo.save_battle(game_id, "battle", &battle).unwrap();
+        let loaded = repo.load_battle(game_id).unwrap().unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&battle).unwrap());
+        assert_eq!(repo.load_state(game_id).unwrap().phase, GamePhase::Battle);
+
+        repo.append_ledger(game_id, Some(owner), &GameEvent::DecisionMade { player_id: owner, decision: Decision::Wait }).unwrap();
+        assert_eq!(repo.get_decisions(game_id).unwrap(), vec![(owner, Decision::Wait)]);
+
+        // Games don't see each other's data
+        let (other_game, _) = repo.create_game("Other", &RuleSet::default(), "v1").unwrap();
+        assert!(repo.events_since(other_game, 0).unwrap().is_empty());
+        assert_eq!(repo.authenticate(other_game, &owner_seat.token).unwrap(), None);
+
+        // Ratings count each finished game once
+        assert!(repo.finished_games().unwrap().is_empty());
+        repo.append_ledger(game_id, None, &GameEvent::GameOver { winner_player_id: owner }).unwrap();
+        assert_eq!(repo.finished_games().unwrap(), vec![game_id]);
+        let result = GameResult::from_ledger(repo, game_id).unwrap().unwrap();
+        assert_eq!(result.sides[1].agent.as_deref(), Some("bot:greedy"));
+        assert!(repo.rate_game(&result).unwrap());
+        assert!(!repo.rate_game(&result).unwrap());
+        let players = repo.leaderboard(RatingKind::Player, 10).unwrap();
+        assert_eq!(players.iter().map(|rating| rating.name.as_str()).collect::<Vec<_>>(), vec!["Owner", "Guest"]);
+        assert_eq!(players, crate::ratings::rate(&result, &[]).into_iter().filter(|rating| rating.kind == RatingKind::Player).collect::<Vec<_>>());
+        assert_eq!(repo.leaderboard(RatingKind::Creature, 1).unwrap().len(), 1);
+        assert_eq!(repo.leaderboard(RatingKind::Agent, 10).unwrap()[0].name, "bot:greedy");
+
+        repo.clear_ratings().unwrap();
+        assert!(repo.leaderboard(RatingKind::Player, 10).unwrap().is_empty());
+        assert!(repo.rate_game(&result).unwrap());
+    }
+
+    /// Many players join the same game at once, exactly one of them gets the seat.
+    pub(crate) fn check_concurrent_joins(repo: &impl GameRepository) {
+        const JOINERS: usize = 8;
+        let (game_id, _) = repo.create_game("Owner", &RuleSet::default(), "v1").unwrap();
+        let barrier = Barrier::new(JOINERS);
+
+        let results: Vec<Result<Seat, DbError>> = std::thread::scope(|scope| {
+            let joiners: Vec<_> = (0..JOINERS)
+                .map(|i| {
+                    let barrier = &barrier;
+                    scope.spawn(move || {
+                        barrier.wait();
+                        repo.join_game(game_id, &format!("Joiner {}", i))
+                    })
+                })
+                .collect();
+            joiners.into_iter().map(|joiner| joiner.join().unwrap()).collect()
+        });
+
+        assert!(results.iter().all(|result| matches!(result, Ok(_) | Err(DbError::GameFull))));
+        let winners: Vec<i64> = results.into_iter().filter_map(Result::ok).map(|seat| seat.id).collect();
+        assert_eq!(winners.len(), 1);
+        let players = repo.get_players(game_id).unwrap();
+        assert_eq!(players.len(), 2);
+        assert_eq!(players[1].0, winners[0]);
+    }
+
+    /// The same decision sent many times at once, e.g. a retry, is only resolved once.
+    pub(crate) fn check_concurrent_decisions(repo: &impl GameRepository) {
+        const DECIDERS: usize = 8;
+        let (game_id, Seat { id: owner, .. }) = repo.create_game("Owner", &RuleSet::default(), "v1").unwrap();
+        let guest = repo.join_game(game_id, "Guest").unwrap().id;
+        let battle = BattleState::new(repo.get_seed(game_id).unwrap(), vec![
+            (owner, vec![creature(owner, "Ember", vec![Element::Fire], 6)]),
+            (guest, vec![creature(guest, "Drop", vec![Element::Water], 4)]),
+        ]).unwrap();
+        let player_id = battle.teams[battle.current].player_id;
+        repo.save_battle(game_id, "battle", &battle).unwrap();
+        let barrier = Barrier::new(DECIDERS);
+
+        let results: Vec<Vec<EventEnvelope>> = std::thread::scope(|scope| {
+            let deciders: Vec<_> = (0..DECIDERS)
+                .map(|_| {
+                    let barrier = &barrier;
+                    scope.spawn(move || {
+                        barrier.wait();
+                        repo.update_battle(game_id, &mut |battle| {
+                            let mut battle = battle?;
+                            let team = battle.team_of(player_id)?;
+                            battle.apply(team, Decision::Wait).ok()?;
+                            let event = GameEvent::DecisionMade { player_id, decision: Decision::Wait };
+                            Some(BattleUpdate { phase: "battle", battle, events: vec![(Some(player_id), event)] })
+                        }).unwrap()
+                    })
+                })
+                .collect();
+            deciders.into_iter().map(|decider| decider.join().unwrap()).collect()
+        });
+
+        assert_eq!(results.iter().filter(|events| !events.is_empty()).count(), 1);
+        assert_eq!(repo.get_decisions(game_id).unwrap(), vec![(player_id, Decision::Wait)]);
+        assert_eq!(repo.load_battle(game_id).unwrap().unwrap().turn, battle.turn + 1);
+        assert!(matches!(repo.update_battle(game_id + 1000, &mut |_| None), Err(DbError::NotFound)));
+    }
+
+    /// Games finishing at once with the same new player all count, none overwrites another.
+    pub(crate) fn check_concurrent_ratings(repo: &impl GameRepository) {
+        const GAMES: usize = 8;
+        let results: Vec<GameResult> = (0..GAMES)
+            .map(|i| {
+                let (game_id, _) = repo.create_game("Ann", &RuleSet::default(), "v1").unwrap();
+                let side = |player: String| Side { player, agent: None, creatures: vec!["Ember #1a2b3c4d".to_string()] };
+                GameResult { game_id, sides: [side("Ann".to_string()), side(format!("Bob {}", i))], winner: 0 }
+            })
+            .collect();
+        let barrier = Barrier::new(GAMES);
+
+        std::thread::scope(|scope| {
+            for result in &results {
+                let barrier = &barrier;
+                scope.spawn(move || {
+                    barrier.wait();
+                    assert!(repo.rate_game(result).unwrap());
+                });
+            }
+        });
+
+        let players = repo.leaderboard(RatingKind::Player, 100).unwrap();
+        let ann = players.iter().find(|rating| rating.name == "Ann").unwrap();
+        assert_eq!((ann.games, ann.wins), (GAMES as u32, GAMES as u32));
+        // The creature fought a mirror every time, it was never rated
+        let creatures = repo.leaderboard(RatingKind::Creature, 100).unwrap();
+        assert!(creatures.iter().all(|rating| rating.name != "Ember #1a2b3c4d"));
+    }
+
+    #[test]
+    fn test_memory_repository() {
+        check_repository(&MemoryRepository::new());
+        check_concurrent_joins(&MemoryRepository::new());
+        check_concurrent_decisions(&MemoryRepository::new());
+        check_concurrent_ratings(&MemoryRepository::new());
+    }
+
+    #[test]
+    fn test_sqlite_repository() {
+        check_repository(&SqliteRepository::open_in_memory());
+        let database = TempDatabase::new("repository_joins");
+        check_concurrent_joins(&SqliteRepository::new(database.pool.clone()));
+        let database = TempDatabase::new("repository_decisions");
+        check_concurrent_decisions(&SqliteRepository::new(database.pool.clone()));
+        let database = TempDatabase::new("repository_ratings");
+        check_concurrent_ratings(&SqliteRepository::new(database.pool.clone()));
+    }
+}
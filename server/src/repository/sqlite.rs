@@ -0,0 +1,144 @@
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+
+use super::{BattleUpdate, GameRepository, Seat};
+use crate::battle::{BattleState, Decision};
+use crate::db::{self, DbError, Pool};
+use crate::events::{EventEnvelope, GameEvent};
+use crate::models::creature::Creature;
+use crate::models::game::GameState;
+use crate::ratings::{GameResult, Rating, RatingKind};
+use crate::rules::RuleSet;
+
+/// Games stored in SQLite, see `db`.
+#[derive(Clone)]
+pub struct SqliteRepository {
+    pool: Pool,
+}
+
+impl SqliteRepository {
+    /// Uses a pool opened with `db::open_pool`, whose schema is up to date.
+    pub fn new(pool: Pool) -> Self {
+        SqliteRepository { pool }
+    }
+
+    /// A fresh database that lives as long as the repository. It has a single connection,
+    /// since every in-memory connection is a database of its own.
+    pub fn open_in_memory() -> Self {
+        let pool = r2d2::Pool::builder()
+            .max_size(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .build(SqliteConnectionManager::memory())
+            .expect("an in-memory database always opens");
+        db::initialize_database(&pool.get().expect("the connection was just opened"));
+        SqliteRepository { pool }
+    }
+
+    pub fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    /// Runs `f` with a connection of the pool.
+    fn with<T>(&self, f: impl FnOnce(&Connection) -> Result<T, DbError>) -> Result<T, DbError> {
+        let conn = self.pool.get()?;
+        f(&conn).map_err(|e| match e {
+            DbError::DatabaseError(rusqlite::Error::QueryReturnedNoRows) => DbError::NotFound,
+            e => e,
+        })
+    }
+}
+
+impl GameRepository for SqliteRepository {
+    fn create_game(&self, name: &str, rules: &RuleSet, catalog_version: &str) -> Result<(i64, Seat), DbError> {
+        self.with(|conn| Ok(db::create_game_with_rules(conn, name, rules, catalog_version)?))
+    }
+
+    fn join_game(&self, game_id: i64, name: &str) -> Result<Seat, DbError> {
+        self.with(|conn| db::join_game(conn, game_id, name))
+    }
+
+    fn poll_game_state(&self, game_id: i64, version: i64) -> Result<bool, DbError> {
+        self.with(|conn| Ok(db::poll_game_state(conn, game_id, version)?))
+    }
+
+    fn load_state(&self, game_id: i64) -> Result<GameState, DbError> {
+        self.with(|conn| db::get_game_state(conn, game_id))
+    }
+
+    fn get_players(&self, game_id: i64) -> Result<Vec<(i64, String)>, DbError> {
+        self.with(|conn| Ok(db::get_players(conn, game_id)?))
+    }
+
+    fn authenticate(&self, game_id: i64, token: &str) -> Result<Option<i64>, DbError> {
+        self.with(|conn| Ok(db::authenticate(conn, game_id, token)?))
+    }
+
+    fn save_creature(&self, game_id: i64, player_id: i64, creature: &Creature) -> Result<i64, DbError> {
+        self.with(|conn| Ok(db::create_creature(conn, game_id, player_id, creature)?))
+    }
+
+    fn get_creatures(&self, game_id: i64, player_id: i64) -> Result<Vec<Creature>, DbError> {
+        self.with(|conn| Ok(db::get_creatures(conn, game_id, player_id)?))
+    }
+
+    fn get_seed(&self, game_id: i64) -> Result<u64, DbError> {
+        self.with(|conn| Ok(db::get_seed(conn, game_id)?))
+    }
+
+    fn get_catalog_version(&self, game_id: i64) -> Result<String, DbError> {
+        self.with(|conn| Ok(db::get_catalog_version(conn, game_id)?))
+    }
+
+    fn save_battle(&self, game_id: i64, phase: &str, battle: &BattleState) -> Result<(), DbError> {
+        self.with(|conn| db::save_battle(conn, game_id, phase, battle))
+    }
+
+    fn load_battle(&self, game_id: i64) -> Result<Option<BattleState>, DbError> {
+        self.with(|conn| db::load_battle(conn, game_id))
+    }
+
+    fn update_battle(
+        &self,
+        game_id: i64,
+        update: &mut dyn FnMut(Option<BattleState>) -> Option<BattleUpdate>,
+    ) -> Result<Vec<EventEnvelope>, DbError> {
+        self.with(|conn| db::update_battle(conn, game_id, update))
+    }
+
+    fn append_ledger(&self, game_id: i64, player_id: Option<i64>, event: &GameEvent) -> Result<EventEnvelope, DbError> {
+        self.with(|conn| db::append_event(conn, game_id, player_id, event))
+    }
+
+    fn events_since(&self, game_id: i64, after: i64) -> Result<Vec<EventEnvelope>, DbError> {
+        self.with(|conn| db::events_since(conn, game_id, after))
+    }
+
+    fn recent_events(&self, game_id: i64, limit: usize) -> Result<Vec<EventEnvelope>, DbError> {
+        self.with(|conn| db::recent_events(conn, game_id, limit as i64))
+    }
+
+    fn get_decisions(&self, game_id: i64) -> Result<Vec<(i64, Decision)>, DbError> {
+        self.with(|conn| db::get_decisions(conn, game_id))
+    }
+
+    fn ready_players(&self, game_id: i64) -> Result<Vec<i64>, DbError> {
+        self.with(|conn| Ok(db::ready_players(conn, game_id)?))
+    }
+
+    fn finished_games(&self) -> Result<Vec<i64>, DbError> {
+        self.with(|conn| Ok(db::finished_games(conn)?))
+    }
+
+    fn rate_game(&self, result: &GameResult) -> Result<bool, DbError> {
+        self.with(|conn| db::rate_game(conn, result))
+    }
+
+    fn leaderboard(&self, kind: RatingKind, limit: usize) -> Result<Vec<Rating>, DbError> {
+        self.with(|conn| Ok(db::leaderboard(conn, kind, limit as i64)?))
+    }
+
+    fn clear_ratings(&self) -> Result<(), DbError> {
+        self.with(|conn| Ok(db::clear_ratings(conn)?))
+    }
+}
@@ -0,0 +1,49 @@
+// src/rules.rs
+use serde::{Deserialize, Serialize};
+
+/// Settings a game is played with, chosen when it is created.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuleSet {
+    pub fog_of_war: FogOfWar,
+}
+
+/// What a player can't see of the opponent's team until the battle is over.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FogOfWar {
+    /// Abilities the opponent has not used yet
+    pub abilities: bool,
+    /// Creatures that have not been in the field yet
+    pub bench: bool,
+    /// Exact attribute values
+    pub attributes: bool,
+}
+
+impl Default for FogOfWar {
+    fn default() -> Self {
+        FogOfWar { abilities: true, bench: true, attributes: true }
+    }
+}
+
+impl FogOfWar {
+    /// Everything is visible.
+    pub fn none() -> Self {
+        FogOfWar { abilities: false, bench: false, attributes: false }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_settings_use_defaults() {
+        let rules: RuleSet = serde_json::from_str("{}").unwrap();
+        assert_eq!(rules, RuleSet::default());
+
+        let rules: RuleSet = serde_json::from_str(r#"{ "fog_of_war": { "bench": false } }"#).unwrap();
+        assert!(rules.fog_of_war.abilities);
+        assert!(!rules.fog_of_war.bench);
+    }
+}
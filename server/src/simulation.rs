@@ -0,0 +1,410 @@
+// src/simulation.rs
+//! Monte Carlo balance analysis: many seeded battles between two teams played by bots, summed up as
+//! win rates, battle lengths, damage distributions and what each ability and template achieves.
+//! See README "# Balance".
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::Write;
+
+use crate::battle::{BattleState, Decision, Effect};
+use crate::bot::{self, BotKind};
+use crate::catalog::Catalog;
+use crate::models::ability::{AbilityCategory, SmolAbility};
+use crate::models::creature::Creature;
+
+/// How the battles are played.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setup {
+    /// Bot of each team
+    pub bots: [BotKind; 2],
+    pub games: u32,
+    /// Seed of the first battle, the next ones count up from it
+    pub seed: u64,
+    /// Battles still going after this many turns are a draw
+    pub max_turns: u32,
+    /// Write every turn as a line of JSON
+    pub verbose: bool,
+}
+
+/// Summary of a sample.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Distribution {
+    pub mean: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub median: f64,
+    /// 90% of the sample is at most this
+    pub p90: f64,
+    pub max: f64,
+}
+
+impl Distribution {
+    pub fn of(values: &[f64]) -> Self {
+        if values.is_empty() {
+            return Distribution::default();
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let count = sorted.len() as f64;
+        let mean = sorted.iter().sum::<f64>() / count;
+        let variance = sorted.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / count;
+        // Nearest rank
+        let percentile = |p: f64| sorted[((p * count).ceil() as usize).clamp(1, sorted.len()) - 1];
+
+        Distribution {
+            mean,
+            std_dev: variance.sqrt(),
+            min: sorted[0],
+            median: percentile(0.5),
+            p90: percentile(0.9),
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+/// What one ability of one creature did over every battle.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AbilityStats {
+    /// Team of the creature, `0` for the first
+    pub team: usize,
+    pub creature: String,
+    pub ability: String,
+    /// Catalog template the ability was filled from, when one matches
+    pub template: Option<String>,
+    pub category: AbilityCategory,
+    pub uses: u32,
+    /// Uses that dealt damage
+    pub hits: u32,
+    pub misses: u32,
+    pub damage: u64,
+    pub damage_per_use: f64,
+    /// Share of the team's decisions
+    pub use_share: f64,
+    /// Share of the damage the team dealt
+    pub damage_share: f64,
+    /// Battles the ability was used in, and how many of those the team won
+    pub battles_used: u32,
+    pub win_rate_when_used: f64,
+}
+
+/// What every ability filled from a catalog template did, on both teams.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TemplateStats {
+    pub template: String,
+    pub base_value: u8,
+    pub uses: u32,
+    pub hits: u32,
+    pub misses: u32,
+    pub damage: u64,
+    pub damage_per_use: f64,
+    pub damage_per_hit: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Simulation {
+    pub games: u32,
+    pub bots: [BotKind; 2],
+    /// Battles won by each team
+    pub wins: [u32; 2],
+    pub draws: u32,
+    pub win_rates: [f64; 2],
+    /// Half the width of the 95% confidence interval of the win rates
+    pub margin: f64,
+    /// Turns of each battle
+    pub turns: Distribution,
+    /// Damage each team dealt per battle
+    pub damage: [Distribution; 2],
+    pub abilities: Vec<AbilityStats>,
+    pub templates: Vec<TemplateStats>,
+}
+
+/// The catalog template an ability was filled from: the one with its category and base value.
+fn template_of(catalog: &Catalog, category: AbilityCategory, base_damage: u16) -> Option<&SmolAbility> {
+    catalog.abilities.iter().find(|template| template.category == category && template.base_value as u16 == base_damage)
+}
+
+fn ratio(part: f64, whole: f64) -> f64 {
+    if whole > 0.0 { part / whole } else { 0.0 }
+}
+
+/// Plays `setup.games` battles between the teams, each played by its bot. Every turn is written
+/// to `out` when `setup.verbose`. `catalog` tells which template each ability was filled from.
+pub fn simulate(teams: [Vec<Creature>; 2], setup: &Setup, catalog: &Catalog, out: &mut dyn Write) -> Result<Simulation, Box<dyn Error>> {
+    // Player ids only tell the teams apart
+    let teams: Vec<(i64, Vec<Creature>)> = teams
+        .into_iter()
+        .zip([1, 2])
+        .map(|(creatures, player_id)| (player_id, creatures.into_iter().map(|creature| Creature { owner: player_id, ..creature }).collect()))
+        .collect();
+    let bots = setup.bots.map(BotKind::bot);
+
+    // Every ability, found by (team, creature, ability) as decisions name them
+    let mut abilities = Vec::new();
+    let mut index = HashMap::new();
+    for (team, (_, creatures)) in teams.iter().enumerate() {
+        for (creature_index, creature) in creatures.iter().enumerate() {
+            for (ability_index, ability) in creature.abilities.iter().enumerate() {
+                index.insert((team, creature_index, ability_index), abilities.len());
+                abilities.push(AbilityStats {
+                    team,
+                    creature: creature.name.clone(),
+                    ability: ability.name.clone(),
+                    template: template_of(catalog, ability.category, ability.base_damage).map(|template| template.name.clone()),
+                    category: ability.category,
+                    uses: 0,
+                    hits: 0,
+                    misses: 0,
+                    damage: 0,
+                    damage_per_use: 0.0,
+                    use_share: 0.0,
+                    damage_share: 0.0,
+                    battles_used: 0,
+                    win_rate_when_used: 0.0,
+                });
+            }
+        }
+    }
+
+    let mut wins = [0; 2];
+    let mut draws = 0;
+    let mut turns = Vec::new();
+    let mut damage = [Vec::new(), Vec::new()];
+    let mut decisions = [0u32; 2];
+    let mut wins_when_used = vec![0u32; abilities.len()];
+
+    for game in 0..setup.games {
+        let seed = setup.seed.wrapping_add(game as u64);
+        let mut battle = BattleState::new(seed, teams.clone())?;
+        let mut dealt = [0u64; 2];
+        let mut used = HashSet::new();
+
+        while !battle.is_finished() && battle.turn < setup.max_turns {
+            let team = battle.current;
+            let decision = bots[team].decide(&battle, team, &mut bot::turn_rng(&battle));
+            let ability = match decision {
+                Decision::Action { ability } => index.get(&(team, battle.teams[team].active, ability)).copied(),
+                _ => None,
+            };
+            let report = battle.apply(team, decision)?;
+            if setup.verbose {
+                writeln!(out, "{}", serde_json::to_string(&serde_json::json!({ "game": game, "seed": seed, "report": report }))?)?;
+            }
+
+            decisions[team] += 1;
+            let mut hit = false;
+            for effect in &report.effects {
+                match effect {
+                    Effect::Damage { team: target, amount, .. } if *target != team => {
+                        dealt[team] += *amount as u64;
+                        if let Some(ability) = ability {
+                            abilities[ability].damage += *amount as u64;
+                            hit = true;
+                        }
+                    }
+                    Effect::Missed { .. } => {
+                        if let Some(ability) = ability {
+                            abilities[ability].misses += 1;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(ability) = ability {
+                abilities[ability].uses += 1;
+                abilities[ability].hits += hit as u32;
+                used.insert(ability);
+            }
+        }
+
+        turns.push(battle.turn as f64);
+        for team in 0..2 {
+            damage[team].push(dealt[team] as f64);
+        }
+        match battle.winner {
+            Some(team) => wins[team] += 1,
+            None => draws += 1,
+        }
+        for ability in used {
+            abilities[ability].battles_used += 1;
+            if battle.winner == Some(abilities[ability].team) {
+                wins_when_used[ability] += 1;
+            }
+        }
+    }
+
+    let team_damage = [0, 1].map(|team| abilities.iter().filter(|stats| stats.team == team).map(|stats| stats.damage).sum::<u64>());
+    for (stats, wins) in abilities.iter_mut().zip(&wins_when_used) {
+        stats.damage_per_use = ratio(stats.damage as f64, stats.uses as f64);
+        stats.use_share = ratio(stats.uses as f64, decisions[stats.team] as f64);
+        stats.damage_share = ratio(stats.damage as f64, team_damage[stats.team] as f64);
+        stats.win_rate_when_used = ratio(*wins as f64, stats.battles_used as f64);
+    }
+
+    let templates = catalog.abilities
+        .iter()
+        .map(|template| {
+            let filled: Vec<&AbilityStats> = abilities.iter().filter(|stats| stats.template.as_ref() == Some(&template.name)).collect();
+            let uses = filled.iter().map(|stats| stats.uses).sum::<u32>();
+            let hits = filled.iter().map(|stats| stats.hits).sum::<u32>();
+            let damage = filled.iter().map(|stats| stats.damage).sum::<u64>();
+            TemplateStats {
+                template: template.name.clone(),
+                base_value: template.base_value,
+                uses,
+                hits,
+                misses: filled.iter().map(|stats| stats.misses).sum(),
+                damage,
+                damage_per_use: ratio(damage as f64, uses as f64),
+                damage_per_hit: ratio(damage as f64, hits as f64),
+            }
+        })
+        .collect();
+
+    let games = setup.games;
+    let win_rates = wins.map(|wins| ratio(wins as f64, games as f64));
+    // The wider of the two intervals
+    let variance = win_rates.map(|rate| rate * (1.0 - rate)).into_iter().fold(0.0, f64::max);
+    Ok(Simulation {
+        games,
+        bots: setup.bots,
+        wins,
+        draws,
+        win_rates,
+        margin: 1.96 * (variance / games.max(1) as f64).sqrt(),
+        turns: Distribution::of(&turns),
+        damage: [Distribution::of(&damage[0]), Distribution::of(&damage[1])],
+        abilities,
+        templates,
+    })
+}
+
+impl Simulation {
+    /// Writes the simulation for people, abilities by the share of damage they deal.
+    pub fn write_summary(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        let percent = |rate: f64| rate * 100.0;
+        writeln!(out, "{} battles, {} against {}", self.games, self.bots[0], self.bots[1])?;
+        writeln!(
+            out,
+            "First team won {} ({:.1}%), second team won {} ({:.1}%), {} draws. Win rates are within ±{:.1}%",
+            self.wins[0], percent(self.win_rates[0]), self.wins[1], percent(self.win_rates[1]), self.draws, percent(self.margin),
+        )?;
+        writeln!(
+            out,
+            "Turns: {:.1} on average, median {}, 90% within {}, from {} to {}",
+            self.turns.mean, self.turns.median, self.turns.p90, self.turns.min, self.turns.max,
+        )?;
+        for (team, damage) in ["First", "Second"].iter().zip(&self.damage) {
+            writeln!(
+                out,
+                "{} team damage per battle: {:.1} ± {:.1}, median {}, from {} to {}",
+                team, damage.mean, damage.std_dev, damage.median, damage.min, damage.max,
+            )?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "{:<4} {:<36} {:<18} {:>6} {:>7} {:>7} {:>9} {:>8}", "Team", "Ability", "Template", "Uses", "Used", "Damage", "Dmg/use", "Win rate")?;
+        let mut abilities: Vec<&AbilityStats> = self.abilities.iter().collect();
+        abilities.sort_by(|a, b| a.team.cmp(&b.team).then(b.damage_share.total_cmp(&a.damage_share)).then(b.use_share.total_cmp(&a.use_share)));
+        for stats in abilities {
+            writeln!(
+                out,
+                "{:<4} {:<36} {:<18} {:>6} {:>6.1}% {:>6.1}% {:>9.1} {:>7.1}%",
+                stats.team + 1,
+                format!("{}: {}", stats.creature, stats.ability),
+                stats.template.as_deref().unwrap_or("-"),
+                stats.uses,
+                percent(stats.use_share),
+                percent(stats.damage_share),
+                stats.damage_per_use,
+                percent(stats.win_rate_when_used),
+            )?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "{:<18} {:>5} {:>7} {:>7} {:>7} {:>9} {:>9}", "Template", "Base", "Uses", "Hits", "Misses", "Dmg/use", "Dmg/hit")?;
+        for stats in &self.templates {
+            writeln!(
+                out,
+                "{:<18} {:>5} {:>7} {:>7} {:>7} {:>9.1} {:>9.1}",
+                stats.template, stats.base_value, stats.uses, stats.hits, stats.misses, stats.damage_per_use, stats.damage_per_hit,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battle::tests::creature;
+    use crate::catalog::CATALOG_PATH;
+    use crate::models::creature::Element;
+    use std::io;
+
+    fn teams() -> [Vec<Creature>; 2] {
+        [
+            vec![creature(0, "Ember", vec![Element::Fire], 6), creature(0, "Pebble", vec![Element::Earth], 4)],
+            vec![creature(0, "Drop", vec![Element::Water], 4)],
+        ]
+    }
+
+    fn setup(bots: [BotKind; 2], games: u32) -> Setup {
+        Setup { bots, games, seed: 3, max_turns: 500, verbose: false }
+    }
+
+    #[test]
+    fn test_distribution() {
+        let distribution = Distribution::of(&[4.0, 1.0, 3.0, 2.0, 10.0]);
+        assert_eq!((distribution.min, distribution.median, distribution.p90, distribution.max), (1.0, 3.0, 10.0, 10.0));
+        assert_eq!(distribution.mean, 4.0);
+        assert_eq!(Distribution::of(&[]), Distribution::default());
+    }
+
+    #[test]
+    fn test_simulate() {
+        let catalog = Catalog::load(CATALOG_PATH).unwrap();
+        let simulation = simulate(teams(), &setup([BotKind::Greedy, BotKind::Random], 40), &catalog, &mut io::sink()).unwrap();
+        assert_eq!(simulation.wins[0] + simulation.wins[1] + simulation.draws, 40);
+        assert!((simulation.win_rates[0] - simulation.wins[0] as f64 / 40.0).abs() < 1e-9);
+        assert!(simulation.margin < 0.2);
+        assert!(simulation.turns.min > 0.0);
+        assert!(simulation.damage[0].mean > 0.0);
+
+        // Shares add up per team, and damage comes from the abilities that dealt it
+        for team in 0..2 {
+            let abilities: Vec<&AbilityStats> = simulation.abilities.iter().filter(|stats| stats.team == team).collect();
+            let damage: u64 = abilities.iter().map(|stats| stats.damage).sum();
+            assert!((simulation.damage[team].mean * 40.0 - damage as f64).abs() < 1e-6);
+            assert!((abilities.iter().map(|stats| stats.damage_share).sum::<f64>() - 1.0).abs() < 1e-9);
+            assert!(abilities.iter().map(|stats| stats.use_share).sum::<f64>() <= 1.0 + 1e-9);
+        }
+        let strike = simulation.abilities.iter().find(|stats| stats.team == 1 && stats.ability == "Strike").unwrap();
+        assert!(strike.hits + strike.misses <= strike.uses);
+        assert!(strike.battles_used > 0);
+
+        // Templates gather the abilities filled from them
+        let filled: u32 = simulation.abilities.iter().filter(|stats| stats.template.is_some()).map(|stats| stats.uses).sum();
+        assert_eq!(simulation.templates.iter().map(|stats| stats.uses).sum::<u32>(), filled);
+
+        // The seed decides everything
+        assert_eq!(simulate(teams(), &setup([BotKind::Greedy, BotKind::Random], 40), &catalog, &mut io::sink()).unwrap(), simulation);
+
+        let mut lines = Vec::new();
+        let verbose = Setup { verbose: true, ..setup([BotKind::Random, BotKind::Random], 1) };
+        let simulation = simulate(teams(), &verbose, &catalog, &mut lines).unwrap();
+        assert_eq!(String::from_utf8(lines).unwrap().lines().count() as f64, simulation.turns.max);
+    }
+
+    #[test]
+    fn test_summary() {
+        let catalog = Catalog::load(CATALOG_PATH).unwrap();
+        let simulation = simulate(teams(), &setup([BotKind::Greedy, BotKind::Greedy], 5), &catalog, &mut io::sink()).unwrap();
+        let mut out = Vec::new();
+        simulation.write_summary(&mut out).unwrap();
+        let summary = String::from_utf8(out).unwrap();
+        assert!(summary.starts_with("5 battles, greedy against greedy"));
+        assert!(summary.contains("Drop: Strike"));
+        assert!(summary.contains("Basic Attack"));
+    }
+}
@@ -0,0 +1,490 @@
+// src/tournament.rs
+//! Tournaments between teams played by bots and LLM agents, entirely in-process against the
+//! battle engine. Everything but the agents' replies comes from the seed, see README "# Tournaments".
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::agent::{self, Agent, ChatClient};
+use crate::battle::{BattleState, Decision, Effect, TurnReport};
+use crate::bot::{self, Bot, BotKind};
+use crate::config::Config;
+use crate::events::{EventEnvelope, GameEvent};
+use crate::models::creature::Creature;
+use crate::models::game::{GamePhase, GameState, PlayerState};
+use crate::rules::RuleSet;
+use crate::visibility;
+
+/// Who makes the decisions of an entrant.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Player {
+    Bot(BotKind),
+    /// An LLM agent, with the configured model unless another is given
+    Agent { model: Option<String> },
+}
+
+impl fmt::Display for Player {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Player::Bot(kind) => write!(f, "{}", kind),
+            Player::Agent { model: None } => f.write_str("agent"),
+            Player::Agent { model: Some(model) } => write!(f, "agent:{}", model),
+        }
+    }
+}
+
+impl FromStr for Player {
+    type Err = String;
+
+    /// `random`, `greedy`, `minimax`, `agent` or `agent:<model>`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.split_once(':') {
+            Some(("agent", model)) if !model.is_empty() => Ok(Player::Agent { model: Some(model.to_string()) }),
+            None if input == "agent" => Ok(Player::Agent { model: None }),
+            _ => input.parse().map(Player::Bot).map_err(|_| {
+                format!("Unknown player `{}`, expected random, greedy, minimax, agent or agent:<model>", input)
+            }),
+        }
+    }
+}
+
+/// A team and who plays it.
+#[derive(Debug, Clone)]
+pub struct Entrant {
+    pub name: String,
+    pub team: Vec<Creature>,
+    pub player: Player,
+}
+
+/// How entrants are paired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Pairing {
+    /// Every entrant meets every other once
+    RoundRobin,
+    /// Entrants meet others with the same score, never twice
+    Swiss,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub pairing: Pairing,
+    /// Rounds of a Swiss tournament, enough to single out a winner when `None`
+    pub rounds: Option<u32>,
+    /// Battles of each match, the entrants swap sides after each
+    pub games: u32,
+    pub seed: u64,
+    /// Battles still going after this many turns are a draw
+    pub max_turns: u32,
+}
+
+/// One match between two entrants, from the point of view of `first`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Matchup {
+    pub round: u32,
+    pub first: String,
+    pub second: String,
+    pub games: u32,
+    pub wins: [u32; 2],
+    pub draws: u32,
+    pub average_turns: f64,
+    /// Damage dealt by each entrant over every battle
+    pub damage: [u64; 2],
+    /// Decisions where an agent never gave a legal move
+    pub fallbacks: [u32; 2],
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Standing {
+    pub rank: usize,
+    pub name: String,
+    pub player: String,
+    pub played: u32,
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+    /// A point per battle won, half for a draw. Sitting out a Swiss round is worth a match won
+    pub points: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Tournament {
+    pub pairing: Pairing,
+    pub seed: u64,
+    pub entrants: Vec<String>,
+    pub standings: Vec<Standing>,
+    /// Battles the entrant of the row won against the entrant of the column
+    pub win_matrix: Vec<Vec<u32>>,
+    pub matchups: Vec<Matchup>,
+}
+
+/// Makes the decisions of an entrant.
+enum Controller {
+    Bot(Box<dyn Bot>),
+    Agent(Agent),
+}
+
+impl Controller {
+    fn new(player: &Player, config: &Config) -> Self {
+        match player {
+            Player::Bot(kind) => Controller::Bot(kind.bot()),
+            Player::Agent { model } => {
+                let model = model.as_deref().unwrap_or(&config.agent_model);
+                let client = ChatClient::new(&config.agent_base_url, model, std::env::var("OPENAI_API_KEY").ok());
+                Controller::Agent(Agent::new(client, config.agent_retries))
+            }
+        }
+    }
+
+    /// The decision of `team` and whether an agent fell back to it.
+    async fn decide(&self, battle: &BattleState, team: usize, history: &[TurnReport]) -> (Decision, bool) {
+        match self {
+            Controller::Bot(bot) => (bot.decide(battle, team, &mut bot::turn_rng(battle)), false),
+            Controller::Agent(agent) => {
+                let player_id = battle.teams[team].player_id;
+                let view = visibility::view(&game_state(battle), Some(player_id), events(history));
+                match agent.decide(&view, player_id).await {
+                    Ok(choice) => (choice.decision, choice.fallback),
+                    Err(e) => {
+                        log::warn!("Agent of player {} could not decide: {}", player_id, e);
+                        (agent::fallback(&agent::legal_decisions(&view, player_id)), true)
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The battle as a game, so agents see it as they do when playing on the server.
+fn game_state(battle: &BattleState) -> GameState {
+    GameState {
+        game_id: 0,
+        version: battle.turn as i64,
+        phase: GamePhase::Battle,
+        current_turn: Some(battle.teams[battle.current].player_id),
+        players: battle.teams
+            .iter()
+            .map(|team| PlayerState { id: team.player_id, name: format!("Player {}", team.player_id), ready: true })
+            .collect(),
+        entities: Vec::new(),
+        battle: Some(battle.clone()),
+        rules: RuleSet::default(),
+    }
+}
+
+/// The turns played so far, as the ledger would hold them.
+fn events(history: &[TurnReport]) -> Vec<EventEnvelope> {
+    history
+        .iter()
+        .map(|report| EventEnvelope {
+            id: report.turn as i64,
+            game_id: 0,
+            timestamp: String::new(),
+            event: GameEvent::TurnResolved { report: report.clone() },
+        })
+        .collect()
+}
+
+/// Plays one battle between two teams, the first as player 1. Adds the outcome to `matchup`,
+/// where team `i` is on side `sides[i]`.
+async fn battle(
+    teams: [&Entrant; 2],
+    controllers: [&Controller; 2],
+    sides: [usize; 2],
+    seed: u64,
+    max_turns: u32,
+    matchup: &mut Matchup,
+) -> Result<(), Box<dyn Error>> {
+    let teams = teams
+        .iter()
+        .zip([1, 2])
+        .map(|(entrant, player_id)| (player_id, entrant.team.iter().cloned().map(|creature| Creature { owner: player_id, ..creature }).collect()))
+        .collect();
+    let mut battle = BattleState::new(seed, teams)?;
+    let mut history = Vec::new();
+
+    while !battle.is_finished() && battle.turn < max_turns {
+        let team = battle.current;
+        let (decision, fallback) = controllers[team].decide(&battle, team, &history).await;
+        if fallback {
+            matchup.fallbacks[sides[team]] += 1;
+        }
+        let report = battle.apply(team, decision)?;
+        for effect in &report.effects {
+            if let Effect::Damage { team, amount, .. } = effect {
+                matchup.damage[sides[1 - team]] += *amount as u64;
+            }
+        }
+        history.push(report);
+    }
+
+    matchup.average_turns += battle.turn as f64;
+    matchup.games += 1;
+    match battle.winner {
+        Some(team) => matchup.wins[sides[team]] += 1,
+        None => matchup.draws += 1,
+    }
+    Ok(())
+}
+
+/// Pairs entrants of the same score who haven't met, the best first. With an odd count, the lowest
+/// entrant that hasn't sat out yet does. Returns the pairs and who sits out.
+fn swiss_pairs(standings: &[Standing], met: &[Vec<bool>], sat_out: &[bool]) -> (Vec<(usize, usize)>, Option<usize>) {
+    let mut order: Vec<usize> = (0..standings.len()).collect();
+    order.sort_by(|a, b| standings[*b].points.total_cmp(&standings[*a].points).then(a.cmp(b)));
+
+    let bye = (order.len() % 2 == 1).then(|| {
+        let position = order.iter().rposition(|entrant| !sat_out[*entrant]).unwrap_or(order.len() - 1);
+        order.remove(position)
+    });
+
+    let mut pairs = Vec::new();
+    while !order.is_empty() {
+        let first = order.remove(0);
+        // A rematch only when everyone left was met already
+        let position = order.iter().position(|other| !met[first][*other]).unwrap_or(0);
+        pairs.push((first, order.remove(position)));
+    }
+    (pairs, bye)
+}
+
+/// Plays the tournament. Agents are built from `config`.
+pub async fn run(entrants: &[Entrant], schedule: &Schedule, config: &Config) -> Result<Tournament, Box<dyn Error>> {
+    if entrants.len() < 2 {
+        return Err("a tournament needs at least two entrants".into());
+    }
+    let controllers: Vec<Controller> = entrants.iter().map(|entrant| Controller::new(&entrant.player, config)).collect();
+    let mut rng = ChaCha8Rng::seed_from_u64(schedule.seed);
+    let mut standings: Vec<Standing> = entrants
+        .iter()
+        .map(|entrant| Standing { name: entrant.name.clone(), player: entrant.player.to_string(), ..Standing::default() })
+        .collect();
+    let mut met = vec![vec![false; entrants.len()]; entrants.len()];
+    let mut win_matrix = vec![vec![0; entrants.len()]; entrants.len()];
+    let mut matchups = Vec::new();
+
+    let mut sat_out = vec![false; entrants.len()];
+    let rounds = match schedule.pairing {
+        Pairing::RoundRobin => 1,
+        Pairing::Swiss => schedule.rounds.unwrap_or_else(|| entrants.len().next_power_of_two().trailing_zeros()).max(1),
+    };
+
+    for round in 1..=rounds {
+        let pairs: Vec<(usize, usize)> = match schedule.pairing {
+            Pairing::RoundRobin => (0..entrants.len())
+                .flat_map(|a| (a + 1..entrants.len()).map(move |b| (a, b)))
+                .collect(),
+            // Swiss rounds are paired as the standings change
+            Pairing::Swiss => {
+                let (pairs, bye) = swiss_pairs(&standings, &met, &sat_out);
+                if let Some(bye) = bye {
+                    sat_out[bye] = true;
+                    standings[bye].points += schedule.games as f64;
+                }
+                pairs
+            }
+        };
+
+        for (a, b) in pairs {
+            let mut matchup = Matchup {
+                round,
+                first: entrants[a].name.clone(),
+                second: entrants[b].name.clone(),
+                ..Matchup::default()
+            };
+            for game in 0..schedule.games {
+                let seed = rng.gen();
+                let seats = if game % 2 == 0 { [a, b] } else { [b, a] };
+                let sides = if game % 2 == 0 { [0, 1] } else { [1, 0] };
+                battle(
+                    [&entrants[seats[0]], &entrants[seats[1]]],
+                    [&controllers[seats[0]], &controllers[seats[1]]],
+                    sides,
+                    seed,
+                    schedule.max_turns,
+                    &mut matchup,
+                ).await?;
+            }
+            matchup.average_turns /= matchup.games.max(1) as f64;
+
+            met[a][b] = true;
+            met[b][a] = true;
+            win_matrix[a][b] += matchup.wins[0];
+            win_matrix[b][a] += matchup.wins[1];
+            for (entrant, side) in [(a, 0), (b, 1)] {
+                let standing = &mut standings[entrant];
+                standing.played += matchup.games;
+                standing.wins += matchup.wins[side];
+                standing.losses += matchup.wins[1 - side];
+                standing.draws += matchup.draws;
+                standing.points += matchup.wins[side] as f64 + matchup.draws as f64 / 2.0;
+            }
+            matchups.push(matchup);
+        }
+    }
+
+    standings.sort_by(|a, b| b.points.total_cmp(&a.points).then(b.wins.cmp(&a.wins)));
+    for (rank, standing) in standings.iter_mut().enumerate() {
+        standing.rank = rank + 1;
+    }
+    Ok(Tournament {
+        pairing: schedule.pairing,
+        seed: schedule.seed,
+        entrants: entrants.iter().map(|entrant| entrant.name.clone()).collect(),
+        standings,
+        win_matrix,
+        matchups,
+    })
+}
+
+/// Quotes a CSV field when it needs to.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writes the standings, the win matrix and the matchups as three CSV tables, a blank line apart.
+pub fn write_csv(tournament: &Tournament, out: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(out, "rank,name,player,played,wins,losses,draws,points")?;
+    for standing in &tournament.standings {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{}",
+            standing.rank,
+            csv_field(&standing.name),
+            csv_field(&standing.player),
+            standing.played,
+            standing.wins,
+            standing.losses,
+            standing.draws,
+            standing.points,
+        )?;
+    }
+
+    writeln!(out)?;
+    let names: Vec<String> = tournament.entrants.iter().map(|name| csv_field(name)).collect();
+    writeln!(out, "wins,{}", names.join(","))?;
+    for (name, row) in names.iter().zip(&tournament.win_matrix) {
+        let row: Vec<String> = row.iter().map(u32::to_string).collect();
+        writeln!(out, "{},{}", name, row.join(","))?;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "round,first,second,games,first_wins,second_wins,draws,average_turns,first_damage,second_damage,first_fallbacks,second_fallbacks")?;
+    for matchup in &tournament.matchups {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{:.1},{},{},{},{}",
+            matchup.round,
+            csv_field(&matchup.first),
+            csv_field(&matchup.second),
+            matchup.games,
+            matchup.wins[0],
+            matchup.wins[1],
+            matchup.draws,
+            matchup.average_turns,
+            matchup.damage[0],
+            matchup.damage[1],
+            matchup.fallbacks[0],
+            matchup.fallbacks[1],
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battle::tests::creature;
+    use crate::models::creature::Element;
+
+    fn entrants() -> Vec<Entrant> {
+        let team = |name: &str, element: Element, strength: u8| vec![creature(0, name, vec![element], strength)];
+        vec![
+            Entrant { name: "ember (greedy)".to_string(), team: team("Ember", Element::Fire, 6), player: Player::Bot(BotKind::Greedy) },
+            Entrant { name: "ember (random)".to_string(), team: team("Ember", Element::Fire, 6), player: Player::Bot(BotKind::Random) },
+            Entrant { name: "drop (greedy)".to_string(), team: team("Drop", Element::Water, 4), player: Player::Bot(BotKind::Greedy) },
+        ]
+    }
+
+    fn schedule(pairing: Pairing) -> Schedule {
+        Schedule { pairing, rounds: None, games: 4, seed: 9, max_turns: 200 }
+    }
+
+    #[actix_web::test]
+    async fn test_round_robin() {
+        let tournament = run(&entrants(), &schedule(Pairing::RoundRobin), &Config::default()).await.unwrap();
+        assert_eq!(tournament.matchups.len(), 3);
+        for standing in &tournament.standings {
+            assert_eq!(standing.played, 8);
+            assert_eq!(standing.wins + standing.losses + standing.draws, 8);
+        }
+        assert!(tournament.standings.windows(2).all(|pair| pair[0].points >= pair[1].points));
+        for matchup in &tournament.matchups {
+            let (a, b) = (tournament.entrants.iter().position(|name| *name == matchup.first).unwrap(), tournament.entrants.iter().position(|name| *name == matchup.second).unwrap());
+            assert_eq!(tournament.win_matrix[a][b], matchup.wins[0]);
+            assert_eq!(tournament.win_matrix[b][a], matchup.wins[1]);
+            assert!(matchup.damage[0] + matchup.damage[1] > 0);
+        }
+
+        // The seed decides everything
+        assert_eq!(run(&entrants(), &schedule(Pairing::RoundRobin), &Config::default()).await.unwrap(), tournament);
+    }
+
+    #[actix_web::test]
+    async fn test_swiss() {
+        let mut entrants = entrants();
+        entrants.push(Entrant { name: "drop (minimax)".to_string(), player: Player::Bot(BotKind::Minimax), ..entrants[2].clone() });
+        entrants.push(Entrant { name: "drop (random)".to_string(), player: Player::Bot(BotKind::Random), ..entrants[2].clone() });
+
+        let tournament = run(&entrants, &schedule(Pairing::Swiss), &Config::default()).await.unwrap();
+        // 3 rounds of 2 matches, a different entrant sitting out each round
+        assert_eq!(tournament.matchups.len(), 6);
+        let mut sat_out = Vec::new();
+        for round in 1..=3 {
+            let mut playing: Vec<&String> = tournament.matchups
+                .iter()
+                .filter(|matchup| matchup.round == round)
+                .flat_map(|matchup| [&matchup.first, &matchup.second])
+                .collect();
+            playing.sort();
+            playing.dedup();
+            assert_eq!(playing.len(), 4);
+            sat_out.push(tournament.entrants.iter().find(|name| !playing.contains(name)).unwrap().clone());
+        }
+        sat_out.dedup();
+        assert_eq!(sat_out.len(), 3);
+        assert!(tournament.standings.iter().all(|standing| standing.played == 8 || standing.played == 12));
+    }
+
+    #[actix_web::test]
+    async fn test_csv() {
+        let tournament = run(&entrants()[..2], &schedule(Pairing::RoundRobin), &Config::default()).await.unwrap();
+        let mut out = Vec::new();
+        write_csv(&tournament, &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let tables: Vec<&str> = csv.split("\n\n").collect();
+        assert_eq!(tables.len(), 3);
+        assert_eq!(tables[0].lines().count(), 3);
+        assert_eq!(tables[1].lines().next(), Some("wins,ember (greedy),ember (random)"));
+        assert_eq!(tables[2].lines().count(), 2);
+        assert_eq!(csv_field("a, \"b\""), "\"a, \"\"b\"\"\"");
+    }
+
+    #[test]
+    fn test_parse_player() {
+        assert_eq!("minimax".parse(), Ok(Player::Bot(BotKind::Minimax)));
+        assert_eq!("agent".parse(), Ok(Player::Agent { model: None }));
+        assert_eq!("agent:gpt-4o".parse(), Ok(Player::Agent { model: Some("gpt-4o".to_string()) }));
+        assert_eq!("agent:gpt-4o".parse::<Player>().unwrap().to_string(), "agent:gpt-4o");
+        assert!("agent:".parse::<Player>().is_err());
+        assert!("human".parse::<Player>().is_err());
+    }
+}
@@ -0,0 +1,355 @@
+// src/visibility.rs
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::battle::{Decision, Effect, Fighter, Team, TurnReport};
+use crate::events::{EventEnvelope, GameEvent};
+use crate::models::{
+    ability::Ability,
+    creature::{Attribute, Element},
+    game::{GamePhase, GameState, PlayerState},
+};
+use crate::rules::FogOfWar;
+
+/// What one player (or a spectator) is allowed to see of a game.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameView {
+    pub game_id: i64,
+    pub version: i64,
+    /// Id of the player the view is for, `null` for spectators
+    pub viewer: Option<i64>,
+    pub phase: GamePhase,
+    pub current_turn: Option<i64>,
+    pub winner: Option<i64>,
+    pub players: Vec<PlayerState>,
+    pub teams: Vec<TeamView>,
+    pub events: Vec<EventEnvelope>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TeamView {
+    pub player_id: i64,
+    /// Index of the creature in the field, once the battle has started
+    pub active: Option<usize>,
+    /// Hidden creatures are `null`, so indices match the ones used in decisions and events
+    pub fighters: Vec<Option<FighterView>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FighterView {
+    pub name: String,
+    pub description: String,
+    pub image: Option<String>,
+    pub elements: Vec<Element>,
+    pub health: u32,
+    pub max_health: u32,
+    /// `null` when the exact values are hidden
+    pub attributes: Option<HashMap<Attribute, u8>>,
+    pub modifiers: Vec<(i8, Attribute)>,
+    /// Hidden abilities are `null`
+    pub abilities: Vec<Option<Ability>>,
+}
+
+/// Whether the fighter shows through `fog`, or stays on the hidden bench.
+fn sees_fighter(fighter: &Fighter, fog: &FogOfWar) -> bool {
+    !fog.bench || fighter.revealed
+}
+
+/// Whether the ability at `index` of the fighter shows through `fog`.
+fn sees_ability(fighter: &Fighter, index: usize, fog: &FogOfWar) -> bool {
+    !fog.abilities || fighter.revealed_abilities.contains(&index)
+}
+
+impl FighterView {
+    fn new(fighter: &Fighter, fog: &FogOfWar) -> Self {
+        let abilities = fighter.state.abilities
+            .iter()
+            .enumerate()
+            .map(|(i, ability)| sees_ability(fighter, i, fog).then(|| ability.clone()))
+            .collect();
+
+        FighterView {
+            name: fighter.creature.name.clone(),
+            description: fighter.creature.description.clone(),
+            image: fighter.creature.image.clone(),
+            elements: fighter.creature.elements.clone(),
+            health: fighter.state.health,
+            max_health: fighter.creature.max_health,
+            attributes: (!fog.attributes).then(|| fighter.creature.attributes.clone()),
+            modifiers: fighter.state.modifiers.clone(),
+            abilities,
+        }
+    }
+}
+
+impl TeamView {
+    fn new(team: &Team, active: Option<usize>, fog: &FogOfWar) -> Self {
+        let fighters = team.fighters
+            .iter()
+            .map(|fighter| sees_fighter(fighter, fog).then(|| FighterView::new(fighter, fog)))
+            .collect();
+        TeamView { player_id: team.player_id, active, fighters }
+    }
+}
+
+/// Redacts events for `viewer`, or for a spectator when `None`, as `view` redacts the state.
+/// Every event sent to a client goes through one, whether with the state or on a stream.
+/// A filter sees what the state it was built from shows, streams build a new one when the battle moves on.
+#[derive(Debug, Clone)]
+pub struct EventFilter {
+    viewer: Option<i64>,
+    /// Fog of war of the opponents, none once the battle is over
+    fog: FogOfWar,
+    /// What the viewer sees of each team of the battle, none before it starts
+    teams: Vec<TeamSight>,
+}
+
+/// The creatures of a team and the abilities the viewer sees, by index, as `TeamView` shows them.
+#[derive(Debug, Clone)]
+struct TeamSight {
+    player_id: i64,
+    creatures: Vec<bool>,
+    /// Abilities seen on any of the creatures: a report doesn't say which creature used one
+    abilities: Vec<bool>,
+}
+
+impl EventFilter {
+    pub fn new(state: &GameState, viewer: Option<i64>) -> Self {
+        let fog = if state.phase == GamePhase::Finish {
+            FogOfWar::none()
+        } else {
+            state.rules.fog_of_war.clone()
+        };
+        let mut filter = EventFilter { viewer, fog, teams: Vec::new() };
+        if let Some(battle) = &state.battle {
+            filter.teams = battle.teams
+                .iter()
+                .map(|team| {
+                    let fog = filter.fog_for(team.player_id);
+                    let most = team.fighters.iter().map(|fighter| fighter.state.abilities.len()).max().unwrap_or(0);
+                    TeamSight {
+                        player_id: team.player_id,
+                        creatures: team.fighters.iter().map(|fighter| sees_fighter(fighter, &fog)).collect(),
+                        abilities: (0..most)
+                            .map(|index| team.fighters.iter().any(|fighter| index < fighter.state.abilities.len() && sees_ability(fighter, index, &fog)))
+                            .collect(),
+                    }
+                })
+                .collect();
+        }
+        filter
+    }
+
+    pub fn viewer(&self) -> Option<i64> {
+        self.viewer
+    }
+
+    /// Whether events of this kind change what the state shows, so the filter must be built again.
+    pub fn is_outdated_by(event: &GameEvent) -> bool {
+        matches!(event, GameEvent::BattleStarted { .. } | GameEvent::DecisionMade { .. } | GameEvent::TurnResolved { .. } | GameEvent::GameOver { .. })
+    }
+
+    fn sees_creature(&self, team: usize, creature: usize) -> bool {
+        self.teams.get(team).is_some_and(|sight| sight.creatures.get(creature).copied().unwrap_or(false))
+    }
+
+    /// Whether every creature and ability `decision` of `team` names is seen.
+    fn sees_decision(&self, team: usize, decision: &Decision) -> bool {
+        match decision {
+            Decision::Action { ability } => self.teams.get(team).is_some_and(|sight| sight.abilities.get(*ability).copied().unwrap_or(false)),
+            Decision::Swap { creature } => self.sees_creature(team, *creature),
+            Decision::Wait | Decision::Concede => true,
+        }
+    }
+
+    fn sees_report(&self, report: &TurnReport) -> bool {
+        self.sees_decision(report.team, &report.decision) && report.effects.iter().all(|effect| match effect {
+            Effect::Damage { team, creature, .. }
+            | Effect::Missed { team, creature }
+            | Effect::Modifier { team, creature, .. }
+            | Effect::Fainted { team, creature } => self.sees_creature(*team, *creature),
+            Effect::Swapped { team, from, to } => self.sees_creature(*team, *from) && self.sees_creature(*team, *to),
+            Effect::Waited { .. } | Effect::Conceded { .. } => true,
+        })
+    }
+
+    fn fog_for(&self, player_id: i64) -> FogOfWar {
+        if self.viewer == Some(player_id) {
+            FogOfWar::none()
+        } else {
+            self.fog.clone()
+        }
+    }
+
+    /// Names of creatures the opponent created are as hidden as the bench. Decisions and turns that
+    /// name a creature or ability of the opponent the state doesn't show are withheld, `None`.
+    pub fn apply(&self, mut envelope: EventEnvelope) -> Option<EventEnvelope> {
+        match &mut envelope.event {
+            GameEvent::CreatureCreated { player_id, name } if self.fog_for(*player_id).bench => name.clear(),
+            GameEvent::DecisionMade { player_id, decision } => {
+                let team = self.teams.iter().position(|sight| sight.player_id == *player_id)?;
+                if !self.sees_decision(team, decision) {
+                    return None;
+                }
+            }
+            GameEvent::TurnResolved { report } if !self.sees_report(report) => return None,
+            _ => {}
+        }
+        Some(envelope)
+    }
+}
+
+/// Projects the state for `viewer`, or for a spectator when `None`.
+/// The opponent's team is redacted according to the game's fog of war, until the battle is over.
+pub fn view(state: &GameState, viewer: Option<i64>, events: Vec<EventEnvelope>) -> GameView {
+    let filter = EventFilter::new(state, viewer);
+    let fog_for = |player_id: i64| filter.fog_for(player_id);
+
+    let teams = match &state.battle {
+        Some(battle) => battle.teams
+            .iter()
+            .map(|team| TeamView::new(team, Some(team.active), &fog_for(team.player_id)))
+            .collect(),
+        // Before the battle, every creature is still on the bench
+        None => state.players
+            .iter()
+            .map(|player| {
+                let team = Team {
+                    player_id: player.id,
+                    fighters: state.entities
+                        .iter()
+                        .filter(|creature| creature.owner == player.id)
+                        .cloned()
+                        .map(Fighter::new)
+                        .collect(),
+                    active: 0,
+                };
+                TeamView::new(&team, None, &fog_for(player.id))
+            })
+            .collect(),
+    };
+
+    let events = events.into_iter().filter_map(|envelope| filter.apply(envelope)).collect();
+
+    let winner = state.battle
+        .as_ref()
+        .and_then(|battle| battle.winner.map(|team| battle.teams[team].player_id));
+
+    GameView {
+        game_id: state.game_id,
+        version: state.version,
+        viewer,
+        phase: state.phase.clone(),
+        current_turn: state.current_turn,
+        winner,
+        players: state.players.clone(),
+        teams,
+        events,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battle::{tests::battle, Decision};
+    use crate::rules::RuleSet;
+
+    fn state(battle: crate::battle::BattleState, phase: GamePhase) -> GameState {
+        GameState {
+            game_id: 1,
+            version: 1,
+            phase,
+            current_turn: None,
+            players: vec![
+                PlayerState { id: 1, name: "first".to_string(), ready: true },
+                PlayerState { id: 2, name: "second".to_string(), ready: true },
+            ],
+            entities: battle.teams.iter().flat_map(|team| team.fighters.iter().map(|fighter| fighter.creature.clone())).collect(),
+            battle: Some(battle),
+            rules: RuleSet::default(),
+        }
+    }
+
+    #[test]
+    fn test_own_team_is_fully_visible() {
+        let view = view(&state(battle(4), GamePhase::Battle), Some(1), vec![]);
+        let own = &view.teams[0];
+        assert!(own.fighters.iter().all(Option::is_some));
+        let fighter = own.fighters[0].as_ref().unwrap();
+        assert!(fighter.attributes.is_some());
+        assert!(fighter.abilities.iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn test_opponent_is_redacted() {
+        let mut battle = battle(4);
+        battle.apply(0, Decision::Wait).unwrap();
+        battle.apply(1, Decision::Action { ability: 2 }).unwrap();
+
+        // Player 2 sees the active creature of player 1, but not the bench
+        let view = view(&state(battle.clone(), GamePhase::Battle), Some(2), vec![]);
+        assert!(view.teams[0].fighters[0].is_some());
+        assert!(view.teams[0].fighters[1].is_none());
+
+        // Player 1 only sees the ability player 2 used, and no exact attributes
+        let view = super::view(&state(battle, GamePhase::Battle), Some(1), vec![]);
+        let opponent = view.teams[1].fighters[0].as_ref().unwrap();
+        assert!(opponent.attributes.is_none());
+        let visible: Vec<usize> = opponent.abilities.iter().enumerate().filter(|(_, a)| a.is_some()).map(|(i, _)| i).collect();
+        assert_eq!(visible, vec![2]);
+    }
+
+    #[test]
+    fn test_rules_and_game_over_lift_the_fog() {
+        let mut open = state(battle(4), GamePhase::Battle);
+        open.rules.fog_of_war = FogOfWar::none();
+        let spectator = view(&open, None, vec![]);
+        assert!(spectator.teams.iter().flat_map(|team| &team.fighters).all(Option::is_some));
+
+        let finished = view(&state(battle(4), GamePhase::Finish), None, vec![]);
+        assert!(finished.teams[1].fighters[0].as_ref().unwrap().attributes.is_some());
+    }
+
+    #[test]
+    fn test_hides_names_of_opponent_creatures_in_events() {
+        let mut created = state(battle(4), GamePhase::Creation);
+        created.battle = None;
+        let events = vec![EventEnvelope {
+            id: 1,
+            game_id: 1,
+            timestamp: String::new(),
+            event: GameEvent::CreatureCreated { player_id: 2, name: "Drop".to_string() },
+        }];
+
+        let view = view(&created, Some(1), events.clone());
+        assert_eq!(view.events[0].event, GameEvent::CreatureCreated { player_id: 2, name: String::new() });
+        assert!(view.teams[1].fighters.iter().all(Option::is_none));
+        assert_eq!(super::view(&created, Some(2), events).events[0].event, GameEvent::CreatureCreated { player_id: 2, name: "Drop".to_string() });
+    }
+
+    fn envelope(event: GameEvent) -> EventEnvelope {
+        EventEnvelope { id: 1, game_id: 1, timestamp: String::new(), event }
+    }
+
+    #[test]
+    fn test_withholds_turns_naming_what_the_state_hides() {
+        let before = battle(4);
+        let mut after = before.clone();
+        let report = after.apply(0, Decision::Swap { creature: 1 }).unwrap();
+        let swap = envelope(GameEvent::TurnResolved { report });
+        let unused = envelope(GameEvent::DecisionMade { player_id: 1, decision: Decision::Action { ability: 1 } });
+
+        // Player 2 can't see the bench of player 1 nor the abilities it hasn't used
+        let hidden = EventFilter::new(&state(before.clone(), GamePhase::Battle), Some(2));
+        assert_eq!(hidden.apply(swap.clone()), None);
+        assert_eq!(hidden.apply(unused.clone()), None);
+        assert!(hidden.apply(envelope(GameEvent::DecisionMade { player_id: 1, decision: Decision::Wait })).is_some());
+
+        // Once the swap is in the state, its report shows
+        assert_eq!(EventFilter::new(&state(after, GamePhase::Battle), Some(2)).apply(swap.clone()), Some(swap.clone()));
+        let own = EventFilter::new(&state(before.clone(), GamePhase::Battle), Some(1));
+        assert!(own.apply(swap.clone()).is_some() && own.apply(unused.clone()).is_some());
+        let over = EventFilter::new(&state(before, GamePhase::Finish), None);
+        assert!(over.apply(swap).is_some() && over.apply(unused).is_some());
+    }
+}
@@ -0,0 +1,183 @@
+// src/visualization.rs
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::Path};
+
+use crate::battle::{BattleState, Fighter};
+use crate::models::creature::Attribute;
+
+/// Attributes in the order their status effects are listed.
+const ATTRIBUTES: [Attribute; 5] = [
+    Attribute::Strength,
+    Attribute::Defense,
+    Attribute::Perception,
+    Attribute::Intelligence,
+    Attribute::Wisdom,
+];
+
+/// Where the teams stand on screen, see `llmbattle/Visualization/Data`.
+const PLAYER_COLUMN: i32 = 100;
+const ENEMY_COLUMN: i32 = 300;
+/// How far the creature in the field steps towards the other team.
+const ACTIVE_STEP: i32 = 50;
+const FIRST_ROW: i32 = 200;
+const ROW_SPACING: i32 = 200;
+
+/// One `game_state_N.json` file read by the Godot visualization.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    pub turn: u32,
+    pub creatures: Vec<FrameCreature>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrameCreature {
+    pub id: u32,
+    pub name: String,
+    pub health: u32,
+    pub max_health: u32,
+    pub attack: i32,
+    pub defense: i32,
+    pub position: Position,
+    pub status_effects: Vec<String>,
+    /// "player" for the team the battle is seen from, "enemy" otherwise
+    pub team: String,
+    /// Whether anything differs from the previous frame
+    pub changed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Status effects of a fighter: fainted, then every attribute its modifiers raise or lower.
+fn status_effects(fighter: &Fighter) -> Vec<String> {
+    let mut effects = Vec::new();
+    if fighter.is_fainted() {
+        effects.push("fainted".to_string());
+    }
+    for attribute in &ATTRIBUTES {
+        let net: i32 = fighter.state.modifiers
+            .iter()
+            .filter(|(_, modified)| modified == attribute)
+            .map(|(amount, _)| *amount as i32)
+            .sum();
+        let name = format!("{:?}", attribute).to_lowercase();
+        match net.signum() {
+            1 => effects.push(format!("{}_up", name)),
+            -1 => effects.push(format!("{}_down", name)),
+            _ => {}
+        }
+    }
+    effects
+}
+
+/// Projects a battle as seen from team `perspective`. Every creature is marked as changed.
+/// Creatures get ids from 1 in team then roster order, which never changes during a battle.
+pub fn frame(battle: &BattleState, perspective: usize) -> Frame {
+    let mut creatures = Vec::new();
+    let mut id = 0;
+    for (index, team) in battle.teams.iter().enumerate() {
+        let own = index == perspective;
+        let (column, step) = if own { (PLAYER_COLUMN, ACTIVE_STEP) } else { (ENEMY_COLUMN, -ACTIVE_STEP) };
+
+        for (row, fighter) in team.fighters.iter().enumerate() {
+            id += 1;
+            let x = if row == team.active { column + step } else { column };
+            creatures.push(FrameCreature {
+                id,
+                name: fighter.creature.name.clone(),
+                health: fighter.state.health,
+                max_health: fighter.creature.max_health,
+                attack: fighter.attribute(&Attribute::Strength).max(fighter.attribute(&Attribute::Intelligence)),
+                defense: fighter.attribute(&Attribute::Defense),
+                position: Position { x, y: FIRST_ROW + ROW_SPACING * row as i32 },
+                status_effects: status_effects(fighter),
+                team: if own { "player" } else { "enemy" }.to_string(),
+                changed: true,
+            });
+        }
+    }
+
+    Frame { turn: battle.turn, creatures }
+}
+
+/// Projects every state of a battle, flagging the creatures that changed since the previous one.
+pub fn frames(states: &[BattleState], perspective: usize) -> Vec<Frame> {
+    let mut frames: Vec<Frame> = Vec::with_capacity(states.len());
+    for state in states {
+        let mut frame = frame(state, perspective);
+        if let Some(previous) = frames.last() {
+            for (creature, before) in frame.creatures.iter_mut().zip(&previous.creatures) {
+                creature.changed = FrameCreature { changed: true, ..before.clone() } != *creature;
+            }
+        }
+        frames.push(frame);
+    }
+    frames
+}
+
+/// Writes every frame as `game_state_{turn}.json` in `directory`, as the visualization expects.
+pub fn write_frames(directory: &Path, frames: &[Frame]) -> io::Result<()> {
+    fs::create_dir_all(directory)?;
+    for frame in frames {
+        let json = serde_json::to_string_pretty(frame).map_err(io::Error::other)?;
+        fs::write(directory.join(format!("game_state_{}.json", frame.turn)), json)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battle::{tests::battle, Decision};
+
+    #[test]
+    fn test_frame_matches_visualization_format() {
+        let frame = frame(&battle(4), 0);
+        let json = serde_json::to_value(&frame).unwrap();
+
+        assert_eq!(json["turn"], 0);
+        let first = &json["creatures"][0];
+        for key in ["id", "name", "health", "max_health", "attack", "defense", "position", "status_effects", "team", "changed"] {
+            assert!(first.get(key).is_some(), "missing {}", key);
+        }
+        assert_eq!(first["position"]["x"], PLAYER_COLUMN + ACTIVE_STEP);
+
+        let ids: Vec<u32> = frame.creatures.iter().map(|creature| creature.id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        let teams: Vec<&str> = frame.creatures.iter().map(|creature| creature.team.as_str()).collect();
+        assert_eq!(teams, vec!["player", "player", "enemy"]);
+    }
+
+    #[test]
+    fn test_changed_flag() {
+        let start = battle(4);
+        let decisions = vec![(1, Decision::Action { ability: 2 }), (2, Decision::Wait)];
+        let frames = frames(&start.replay(&decisions).unwrap(), 1);
+
+        assert_eq!(frames.len(), 3);
+        assert!(frames[0].creatures.iter().all(|creature| creature.changed));
+
+        // Only the creature that hardened itself changed
+        let changed: Vec<u32> = frames[1].creatures.iter().filter(|creature| creature.changed).map(|creature| creature.id).collect();
+        assert_eq!(changed, vec![1]);
+        assert!(frames[1].creatures[0].status_effects.contains(&"defense_up".to_string()));
+        assert_eq!(frames[1].creatures[0].team, "enemy");
+
+        // Waiting changes nothing
+        assert!(frames[2].creatures.iter().all(|creature| !creature.changed));
+    }
+
+    #[test]
+    fn test_write_frames() {
+        let directory = std::env::temp_dir().join(format!("battllm_frames_{}", std::process::id()));
+        let frames = frames(&battle(4).replay(&[(1, Decision::Wait)]).unwrap(), 0);
+        write_frames(&directory, &frames).unwrap();
+
+        let written: Frame = serde_json::from_str(&fs::read_to_string(directory.join("game_state_1.json")).unwrap()).unwrap();
+        assert_eq!(written, frames[1]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+}